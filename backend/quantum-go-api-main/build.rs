@@ -1,6 +1,12 @@
 use std::path::Path;
 
 fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    // The Rust port replaces the C++ estimator entirely
+    if std::env::var_os("CARGO_FEATURE_RUST_ESTIMATOR").is_some() {
+        return;
+    }
+
     let score_estimator_dir = "katago/score-estimator-master";
 
     if !Path::new(score_estimator_dir).exists() {
@@ -16,6 +22,8 @@ fn main() {
         .define("NOMINMAX", None)
         .define("WIN32_LEAN_AND_MEAN", None)
         .define("DEBUG", Some("1"))
+        // Thread-local grid defaults and a per-board RNG: estimators run concurrently on the blocking pool
+        .define("USE_THREADS", Some("1"))
         .file(format!("{}/Goban.cc", score_estimator_dir))
         .file(format!("{}/simple_estimator.cpp", score_estimator_dir))
         .include(score_estimator_dir)
@@ -25,6 +33,7 @@ fn main() {
     println!("cargo:rustc-link-lib=m");
 
     println!("cargo:rerun-if-changed={}/Goban.cc", score_estimator_dir);
+    println!("cargo:rerun-if-changed={}/simple_estimator.cpp", score_estimator_dir);
     println!("cargo:rerun-if-changed={}/Goban.h", score_estimator_dir);
     println!("cargo:rerun-if-changed={}/Color.h", score_estimator_dir);
     println!("cargo:rerun-if-changed={}/Point.h", score_estimator_dir);
@@ -115,4 +115,39 @@ extern "C" {
             dead_stones[i * 2 + 1] = dead[i].y;
         }
     }
+
+    /* One rollout pass per board: ownership, territory and the dead stones of
+     * that pass. dead_stones holds max_count (x, y) pairs. */
+    void estimate_board(
+        void* estimator,
+        int player_to_move,
+        int trials,
+        float tolerance,
+        float* ownership,
+        float* territory,
+        int* dead_stones,
+        int* count,
+        int max_count
+    ) {
+        auto* g = static_cast<Goban*>(estimator);
+        Color player = to_color(player_to_move);
+
+        Grid owned = g->estimate(player, trials, tolerance, false);
+        Grid area = g->computeTerritory();
+        for (int y = 0; y < g->height; ++y) {
+            for (int x = 0; x < g->width; ++x) {
+                Point p(x, y);
+                ownership[y * g->width + x] = owned[p];
+                territory[y * g->width + x] = area[p];
+            }
+        }
+
+        const Vec& dead = g->getLastDead();
+        int limit = std::min(dead.size, max_count);
+        *count = limit;
+        for (int i = 0; i < limit; ++i) {
+            dead_stones[i * 2] = dead[i].x;
+            dead_stones[i * 2 + 1] = dead[i].y;
+        }
+    }
 }
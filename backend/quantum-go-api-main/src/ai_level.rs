@@ -0,0 +1,231 @@
+// AI 难度档位
+//
+// 每个档位对应一组 KataGo 参数：访问数上限、选点温度，以及（配置了 human SL 模型时）
+// 模仿某个段位人类棋手的 humanSLProfile。没有 human 模型时只靠低访问数 + 高温度降低棋力。
+// 引擎进程启动参数不同，所以引擎池按（棋盘尺寸, 档位）区分。
+use serde::Serialize;
+use std::io;
+use std::path::Path;
+
+const HUMAN_5K_CONFIG: &str = "gtp_human5k_example.cfg";
+const HUMAN_9D_CONFIG: &str = "gtp_human9d_search_example.cfg";
+
+/// 未指定档位时使用全力（即 KATAGO_GTP_CONFIG 原样配置）
+pub const DEFAULT_LEVEL: &str = "max";
+
+#[derive(Debug)]
+pub struct AiLevel {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub description: &'static str,
+    pub approx_rank: &'static str,
+    /// AI 房间里该档位机器人账号的固定评分（Glicko 尺度）
+    pub rating: f64,
+    pub max_visits: Option<u32>,
+    pub temperature_early: Option<f32>,
+    pub temperature: Option<f32>,
+    pub human_profile: Option<&'static str>,
+    // Human SL example config shipped next to the GTP config
+    human_config: Option<&'static str>,
+}
+
+pub static LEVELS: [AiLevel; 6] = [
+    AiLevel {
+        id: "beginner",
+        name: "Beginner",
+        description: "Plays like a new player; makes frequent tactical mistakes",
+        approx_rank: "15k",
+        rating: 800.0,
+        max_visits: Some(1),
+        temperature_early: Some(1.0),
+        temperature: Some(0.9),
+        human_profile: Some("preaz_15k"),
+        human_config: Some(HUMAN_5K_CONFIG),
+    },
+    AiLevel {
+        id: "novice",
+        name: "Novice",
+        description: "Knows the basics but misreads fights",
+        approx_rank: "10k",
+        rating: 1100.0,
+        max_visits: Some(4),
+        temperature_early: Some(0.9),
+        temperature: Some(0.8),
+        human_profile: Some("preaz_10k"),
+        human_config: Some(HUMAN_5K_CONFIG),
+    },
+    AiLevel {
+        id: "intermediate",
+        name: "Intermediate",
+        description: "A solid club player",
+        approx_rank: "5k",
+        rating: 1400.0,
+        max_visits: Some(40),
+        temperature_early: Some(0.85),
+        temperature: Some(0.7),
+        human_profile: Some("preaz_5k"),
+        human_config: Some(HUMAN_5K_CONFIG),
+    },
+    AiLevel {
+        id: "advanced",
+        name: "Advanced",
+        description: "A strong amateur with a human style",
+        approx_rank: "1d",
+        rating: 1900.0,
+        max_visits: Some(100),
+        temperature_early: Some(0.7),
+        temperature: Some(0.35),
+        human_profile: Some("preaz_1d"),
+        human_config: Some(HUMAN_9D_CONFIG),
+    },
+    AiLevel {
+        id: "expert",
+        name: "Expert",
+        description: "Top amateur strength with a human style",
+        approx_rank: "9d",
+        rating: 2400.0,
+        max_visits: Some(400),
+        temperature_early: Some(0.7),
+        temperature: Some(0.25),
+        human_profile: Some("preaz_9d"),
+        human_config: Some(HUMAN_9D_CONFIG),
+    },
+    AiLevel {
+        id: "max",
+        name: "Maximum",
+        description: "Full engine strength as configured on the server",
+        approx_rank: "superhuman",
+        rating: 2900.0,
+        max_visits: None,
+        temperature_early: None,
+        temperature: None,
+        human_profile: None,
+        human_config: None,
+    },
+];
+
+/// 按 id 查找档位；None 表示默认档位
+pub fn resolve(level: Option<&str>) -> io::Result<&'static AiLevel> {
+    let id = level.map(str::trim).filter(|s| !s.is_empty()).unwrap_or(DEFAULT_LEVEL);
+    LEVELS
+        .iter()
+        .find(|l| l.id.eq_ignore_ascii_case(id))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("unknown AI level: {}", id)))
+}
+
+/// KATAGO_HUMAN_MODEL：human SL 模型路径（b18c384nbt-humanv0.bin.gz），未设置时不启用拟人下法
+pub fn human_model() -> Option<String> {
+    std::env::var("KATAGO_HUMAN_MODEL").ok().filter(|s| !s.trim().is_empty())
+}
+
+impl AiLevel {
+    /// 该档位机器人账号的用户名
+    pub fn bot_username(&self) -> String {
+        format!("KataGo {}", self.name)
+    }
+
+    /// 是否以 human SL 模型模仿人类下法
+    pub fn human_like(&self) -> bool {
+        self.human_profile.is_some() && human_model().is_some()
+    }
+
+    /// human 档位使用与 GTP 配置同目录下的示例配置
+    pub fn config_path(&self, gtp_config: &str) -> String {
+        match self.human_config {
+            Some(file) if self.human_like() => Path::new(gtp_config).with_file_name(file).to_string_lossy().into_owned(),
+            _ => gtp_config.to_string(),
+        }
+    }
+
+    /// 覆盖到 KataGo 配置上的参数
+    pub fn overrides(&self, human_like: bool) -> Vec<(&'static str, String)> {
+        let mut out = Vec::new();
+        if let Some(v) = self.max_visits {
+            out.push(("maxVisits", v.to_string()));
+        }
+        if let Some(t) = self.temperature_early {
+            out.push(("chosenMoveTemperatureEarly", t.to_string()));
+        }
+        if let Some(t) = self.temperature {
+            out.push(("chosenMoveTemperature", t.to_string()));
+        }
+        if let (Some(profile), true) = (self.human_profile, human_like) {
+            out.push(("humanSLProfile", profile.to_string()));
+        }
+        out
+    }
+}
+
+/// 合并 `-override-config` 字符串；同名键以 extra 为准（KataGo 不接受重复键）
+pub fn merge_overrides(base: &str, extra: &[(&str, String)]) -> String {
+    let mut merged: Vec<(String, String)> = base
+        .split(',')
+        .filter_map(|kv| kv.split_once('='))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .filter(|(k, _)| !extra.iter().any(|(e, _)| e == k))
+        .collect();
+    merged.extend(extra.iter().map(|(k, v)| (k.to_string(), v.clone())));
+    merged.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join(",")
+}
+
+#[derive(Debug, Serialize)]
+pub struct LevelInfo {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub description: &'static str,
+    pub approx_rank: &'static str,
+    pub rating: f64,
+    pub max_visits: Option<u32>,
+    pub temperature: Option<f32>,
+    pub human_profile: Option<&'static str>,
+    pub human_like: bool,
+    pub default: bool,
+}
+
+pub fn describe_levels() -> Vec<LevelInfo> {
+    LEVELS
+        .iter()
+        .map(|l| LevelInfo {
+            id: l.id,
+            name: l.name,
+            description: l.description,
+            approx_rank: l.approx_rank,
+            rating: l.rating,
+            max_visits: l.max_visits,
+            temperature: l.temperature,
+            human_profile: l.human_profile,
+            human_like: l.human_like(),
+            default: l.id == DEFAULT_LEVEL,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_levels() {
+        assert_eq!(resolve(None).unwrap().id, DEFAULT_LEVEL);
+        assert_eq!(resolve(Some(" Novice ")).unwrap().id, "novice");
+        assert_eq!(resolve(Some("godlike")).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_merge_overrides_replaces_duplicate_keys() {
+        let merged = merge_overrides(
+            "ponderingEnabled=false,maxTime=3.0,maxVisits=1000",
+            &[("maxVisits", "40".to_string()), ("humanSLProfile", "preaz_5k".to_string())],
+        );
+        assert_eq!(merged, "ponderingEnabled=false,maxTime=3.0,maxVisits=40,humanSLProfile=preaz_5k");
+        assert_eq!(merge_overrides("ponderingEnabled=false", &[]), "ponderingEnabled=false");
+    }
+
+    #[test]
+    fn test_overrides_only_set_profile_with_human_model() {
+        let level = resolve(Some("intermediate")).unwrap();
+        assert!(level.overrides(false).iter().all(|(k, _)| *k != "humanSLProfile"));
+        assert!(level.overrides(true).contains(&("humanSLProfile", "preaz_5k".to_string())));
+        assert!(resolve(Some("max")).unwrap().overrides(true).is_empty());
+    }
+}
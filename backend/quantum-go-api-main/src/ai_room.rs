@@ -0,0 +1,314 @@
+// AI 房间：机器人坐在访客席位，轮到它时由服务端调用 KataGo 落子
+//
+// 房主执黑、机器人执白（让子局白先）。玩家的落子仍由客户端计算并上报；服务端按 chessman_records
+// 重放量子对局，生成机器人的一手，按前端格式更新棋盘与记录并写库，再以 updateChess 推送给玩家。
+// 玩家虚手后机器人也虚手，或玩家在机器人虚手后进入数子阶段时，由服务端按房间规则数子终局。
+// 对局与 PvP 一样写入 room_infos；计分房间只更新玩家评分，机器人评分固定为档位评分。
+use axum::extract::ws::Message;
+use futures::sink::SinkExt;
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::ai_level::{self, AiLevel};
+use crate::entity::{Chessman, RoomInfo};
+use crate::katago::{genmove_dual_with_katago, gtp_to_xy, AiDualGenmoveRequest, MoveItem};
+use crate::quantum::{Board, QuantumGame, Stone};
+use crate::quantum_search::game_from_records;
+use crate::outcome::GameOutcome;
+use crate::rules::Ruleset;
+use crate::ws::{record_pass, update_game_state, update_winner, AppState, Data, SetWinner, UpdataChess};
+
+/// 机器人执白
+pub const BOT_COLOR: Stone = Stone::White;
+
+// Rooms whose bot is currently thinking; guards against answering the same move twice.
+// A trigger arriving meanwhile is kept (Some(human_passed)) and re-checked after the current turn.
+static THINKING: Lazy<std::sync::Mutex<HashMap<Uuid, Option<bool>>>> = Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+pub(crate) enum BotMove {
+    Play(usize),
+    Pass,
+    Resign,
+}
+
+pub(crate) fn color_name(stone: Stone) -> &'static str {
+    match stone {
+        Stone::Black => "black",
+        Stone::White => "white",
+    }
+}
+
+fn chessman_at(game: &QuantumGame, board: &Board, idx: usize, color: Stone) -> Chessman {
+    Chessman {
+        position: game.position_of(idx),
+        color: color_name(color).to_string(),
+        brother: game.position_of(board.brother(idx).unwrap_or(idx)),
+    }
+}
+
+/// 棋盘序列化为前端 `[...board]` 的格式：[[pos, chessman], ...]
+pub fn board_entries(game: &QuantumGame, board: usize) -> Value {
+    let b = &game.boards[board];
+    Value::Array(
+        b.stones()
+            .map(|(idx, color)| {
+                let chessman = chessman_at(game, b, idx, color);
+                json!([chessman.position.clone(), chessman])
+            })
+            .collect(),
+    )
+}
+
+// Current stones of one board as a setup sequence for the GTP replay (colours need not alternate).
+// Quantum captures cannot be replayed as moves, so the engine sees no ko / superko history;
+// choose_move keeps its suggestion legal through the forbidden list computed from the full game.
+fn setup_moves(game: &QuantumGame, board: usize) -> Vec<MoveItem> {
+    game.boards[board]
+        .stones()
+        .map(|(idx, color)| MoveItem { color: color_name(color).to_string(), position: game.position_of(idx) })
+        .collect()
+}
+
+/// 为当前行棋方选点；禁着点（两盘任一不合法）通过 forbidden 排除
+pub(crate) async fn choose_move(game: &QuantumGame, komi: f64, rules: Ruleset, level: &AiLevel) -> io::Result<BotMove> {
+    let legal = game.legal_moves();
+    if legal.is_empty() {
+        return Ok(BotMove::Pass);
+    }
+    let forbidden = (0..game.size * game.size)
+        .filter(|i| !legal.contains(i))
+        .map(|i| game.position_of(i))
+        .collect();
+    let req = AiDualGenmoveRequest {
+        board_size: game.size as u8,
+        next_to_move: color_name(game.to_move).to_string(),
+        board_a_moves: setup_moves(game, 0),
+        board_b_moves: setup_moves(game, 1),
+        komi: Some(komi as f32),
+        rules: Some(rules.id().to_string()),
+        forbidden: Some(forbidden),
+        k: None,
+        metric: None,
+        level: Some(level.id.to_string()),
+    };
+    let resp = genmove_dual_with_katago(req).await?;
+    let xy = gtp_to_xy(&resp.move_coord, game.size as u8)?;
+    Ok(match xy.as_str() {
+        "PASS" => BotMove::Pass,
+        "RESIGN" => BotMove::Resign,
+        _ => match game.index_of(&xy).filter(|idx| legal.contains(idx)) {
+            Some(idx) => BotMove::Play(idx),
+            // The engine ignored the forbidden list; passing is safer than an arbitrary point
+            None => {
+                info!("AI suggested an illegal move {}; passing instead", xy);
+                BotMove::Pass
+            }
+        },
+    })
+}
+
+/// 与前端一致的对局状态：量子局面、chessman_records 与 A 盘提子数
+pub(crate) struct LiveGame {
+    pub game: QuantumGame,
+    pub records: Vec<Value>,
+    pub black_lost: i32,
+    pub white_lost: i32,
+}
+
+impl LiveGame {
+    pub fn from_room(room: &RoomInfo) -> io::Result<Self> {
+        Ok(Self {
+            game: game_from_records(room.model, room.handicap, &room.chessman_records)?,
+            records: room.chessman_records.as_array().cloned().unwrap_or_default(),
+            black_lost: room.black_lost,
+            white_lost: room.white_lost,
+        })
+    }
+
+    /// 最后一手的颜色（记录中不含 pass）
+    pub fn last_color(&self) -> Option<Stone> {
+        self.records
+            .last()
+            .and_then(|r| r.pointer("/add/0/type"))
+            .and_then(Value::as_str)
+            .and_then(Stone::parse)
+    }
+
+    /// 当前行棋方在 idx（A 盘点击位置）落子，追加记录并返回 putChess
+    pub fn play(&mut self, idx: usize) -> io::Result<Chessman> {
+        let before = self.game.clone();
+        let color = self.game.to_move;
+        let outcome = self
+            .game
+            .play(idx)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+        // Removed stones as they were before the move, tagged with their board (1 = A, 2 = B)
+        let mut reduce = Vec::new();
+        for (b, captured) in outcome.captured.iter().enumerate() {
+            for &p in captured {
+                let Some(stone) = before.boards[b].get(p) else { continue };
+                let mut entry = serde_json::to_value(chessman_at(&before, &before.boards[b], p, stone)).map_err(io::Error::other)?;
+                entry["board"] = json!(b + 1);
+                reduce.push(entry);
+                if b == 0 {
+                    match stone {
+                        Stone::Black => self.black_lost += 1,
+                        Stone::White => self.white_lost += 1,
+                    }
+                }
+            }
+        }
+        let put_chess = chessman_at(&self.game, &self.game.boards[0], outcome.positions[0], color);
+        self.records.push(json!({ "add": [put_chess.clone()], "reduce": reduce }));
+        Ok(put_chess)
+    }
+
+    /// 客户端上报落子时发送的 updateChess 数据
+    pub fn update(&self, put_chess: Chessman) -> UpdataChess {
+        UpdataChess {
+            put_chess,
+            board: board_entries(&self.game, 0),
+            black_lost: self.black_lost,
+            white_lost: self.white_lost,
+            chessman_records: Value::Array(self.records.clone()),
+        }
+    }
+}
+
+pub(crate) fn pass_chessman(color: Stone) -> Chessman {
+    Chessman { position: "0,0".to_string(), color: color_name(color).to_string(), brother: "0,0".to_string() }
+}
+
+async fn send_to_owner(state: &AppState, room_id: Uuid, message: &Value) {
+    // Do not hold the rooms lock while writing to the socket
+    let user1 = state.rooms.lock().await.get(&room_id).and_then(|room| room.user1.clone());
+    if let Some(user1) = user1 {
+        let _ = user1.lock().await.send(Message::Text(message.to_string().into())).await;
+    }
+}
+
+async fn finish(state: &AppState, room: &RoomInfo, result: GameOutcome) -> io::Result<()> {
+    update_winner(state, room, &result).await.map_err(io::Error::other)?;
+    let msg = Data { mode: "setWinner".to_string(), data: SetWinner::from(&result) };
+    send_to_owner(state, room.room_id, &serde_json::to_value(&msg).map_err(io::Error::other)?).await;
+    Ok(())
+}
+
+async fn score_and_finish(state: &AppState, room: &RoomInfo, game: &QuantumGame) -> io::Result<()> {
+    let (black, white) = game.score(room.komi, Ruleset::from_id(&room.rules));
+    finish(state, room, GameOutcome::score(room, black, white)).await
+}
+
+async fn load_room(state: &AppState, room_id: Uuid) -> io::Result<Option<(RoomInfo, &'static AiLevel)>> {
+    let room = state
+        .db
+        .get_room_by_room_id(room_id)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::NotFound, "room not found"))?;
+    if room.status != "playing" {
+        return Ok(None);
+    }
+    let Some(level) = room.ai_level.as_deref() else { return Ok(None) };
+    let level = ai_level::resolve(Some(level))?;
+    Ok(Some((room, level)))
+}
+
+async fn bot_turn(state: &AppState, room_id: Uuid, human_passed: bool) -> io::Result<()> {
+    let Some((room, level)) = load_room(state, room_id).await? else { return Ok(()) };
+    let mut live = LiveGame::from_room(&room)?;
+
+    // Records omit passes: it is the bot's turn after the player's stone, after a pass,
+    // or at the start of a handicap game where White moves first
+    let last = live.last_color();
+    let bot_to_move = human_passed || last == Some(BOT_COLOR.opposite()) || (last.is_none() && room.handicap >= 2);
+    if !bot_to_move {
+        return Ok(());
+    }
+    live.game.to_move = BOT_COLOR;
+
+    match choose_move(&live.game, room.komi, Ruleset::from_id(&room.rules), level).await? {
+        BotMove::Resign => finish(state, &room, GameOutcome::resign(&room, BOT_COLOR)).await,
+        BotMove::Pass if human_passed => score_and_finish(state, &room, &live.game).await,
+        BotMove::Pass => {
+            record_pass(state, &room).await.map_err(io::Error::other)?;
+            send_to_owner(state, room_id, &json!({ "type": "updateChess", "data": { "putChess": pass_chessman(BOT_COLOR) } })).await;
+            Ok(())
+        }
+        BotMove::Play(idx) => {
+            let put_chess = live.play(idx)?;
+            let update = live.update(put_chess);
+            update_game_state(state, &room, &update).await.map_err(io::Error::other)?;
+            let msg = json!({
+                "type": "updateChess",
+                "data": { "putChess": update.put_chess, "board2": board_entries(&live.game, 1) },
+            });
+            send_to_owner(state, room_id, &msg).await;
+            Ok(())
+        }
+    }
+}
+
+/// 轮到机器人时落子；同一房间同时只有一个思考任务，思考期间到达的触发在本轮结束后重新检查
+pub async fn play_bot_turn(state: AppState, room_id: Uuid, human_passed: bool) {
+    {
+        let mut thinking = THINKING.lock().unwrap();
+        if let Some(rerun) = thinking.get_mut(&room_id) {
+            // The running turn may have read the room before this trigger's move was stored
+            *rerun = Some(rerun.unwrap_or(false) || human_passed);
+            return;
+        }
+        thinking.insert(room_id, None);
+    }
+    let mut human_passed = human_passed;
+    loop {
+        if let Err(err) = bot_turn(&state, room_id, human_passed).await {
+            info!("AI move failed in room {}: {}", room_id, err);
+        }
+        let mut thinking = THINKING.lock().unwrap();
+        match thinking.get_mut(&room_id).and_then(Option::take) {
+            Some(passed) => human_passed = passed,
+            None => {
+                thinking.remove(&room_id);
+                return;
+            }
+        }
+    }
+}
+
+/// 玩家在机器人虚手后也虚手（客户端直接进入数子阶段）：服务端数子终局
+pub async fn finish_by_score(state: AppState, room_id: Uuid) {
+    let result = async {
+        let Some((room, _)) = load_room(&state, room_id).await? else { return Ok(()) };
+        let live = LiveGame::from_room(&room)?;
+        score_and_finish(&state, &room, &live.game).await
+    }
+    .await;
+    if let Err(err) = result {
+        info!("Failed to score AI room {}: {}", room_id, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_board_entries_match_frontend_format() {
+        let mut game = QuantumGame::new(9);
+        let (p, q) = (game.index_of("3,3").unwrap(), game.index_of("7,7").unwrap());
+        game.play(p).unwrap();
+        game.play(q).unwrap();
+        let a = board_entries(&game, 0);
+        let entries = a.as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        // Quantum pair: Black's stone on A is linked to Black's stone on B
+        assert_eq!(entries[0], json!(["3,3", {"position": "3,3", "type": "black", "brother": "7,7"}]));
+        let b = board_entries(&game, 1);
+        assert_eq!(b[0][1]["type"], "white");
+    }
+}
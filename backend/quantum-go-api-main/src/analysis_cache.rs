@@ -0,0 +1,300 @@
+// KataGo 分析结果缓存
+//
+// GTP 后端的候选着分析（A 盘）与应手评估（B 盘）都按局面缓存：键为
+// （棋盘尺寸, 贴目, 规则, 局面哈希, 行棋方, 超级劫禁着点, 难度档位）。局面哈希由着法重放（含提子）后的盘面算出，
+// 所以悔棋后重新请求、或着法顺序不同但局面相同（转置）时都能直接命中；禁着点随历史不同，
+// 打劫后的局面与无劫历史的同形局面不会共用结果。
+//
+// - 内存层为 LRU，容量由 ANALYSIS_CACHE_SIZE 设置（默认 4096，0 关闭缓存）
+// - ANALYSIS_CACHE_PERSIST=1 时再加一层 Postgres（analysis_cache 表），重启后仍可命中；
+//   超过 ANALYSIS_CACHE_PERSIST_TTL_HOURS（默认 168）的条目不再命中，每小时清理一次，
+//   并只保留最新的 ANALYSIS_CACHE_PERSIST_MAX_ROWS（默认 100000）行
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::db::Database;
+use crate::katago::{color_to_gtp, InfoEntry, MoveItem};
+use crate::quantum::{Board, Stone};
+use crate::rules::Ruleset;
+
+const DEFAULT_CAPACITY: usize = 4096;
+const DEFAULT_PERSIST_TTL_HOURS: i64 = 168;
+const DEFAULT_PERSIST_MAX_ROWS: i64 = 100_000;
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+type Entries = Arc<Vec<InfoEntry>>;
+
+/// 与局面无关的分析条件
+pub struct AnalysisScope<'a> {
+    pub size: u8,
+    pub komi: f32,
+    pub rules: Ruleset,
+    pub level: &'a str,
+    pub cached: bool,
+}
+
+/// 容量固定的 LRU：tick 越小越久未使用
+struct Lru<V> {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<String, (V, u64)>,
+    order: BTreeMap<u64, String>,
+}
+
+impl<V: Clone> Lru<V> {
+    fn new(capacity: usize) -> Self {
+        Self { capacity, tick: 0, entries: HashMap::new(), order: BTreeMap::new() }
+    }
+
+    fn touch(&mut self, key: &str) -> u64 {
+        self.tick += 1;
+        if let Some((_, used)) = self.entries.get(key) {
+            self.order.remove(used);
+        }
+        self.order.insert(self.tick, key.to_string());
+        self.tick
+    }
+
+    fn get(&mut self, key: &str) -> Option<V> {
+        if !self.entries.contains_key(key) {
+            return None;
+        }
+        let tick = self.touch(key);
+        let entry = self.entries.get_mut(key)?;
+        entry.1 = tick;
+        Some(entry.0.clone())
+    }
+
+    fn put(&mut self, key: String, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        let tick = self.touch(&key);
+        self.entries.insert(key, (value, tick));
+        while self.entries.len() > self.capacity {
+            let Some((_, oldest)) = self.order.pop_first() else { break };
+            self.entries.remove(&oldest);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+static MEMORY: Lazy<std::sync::Mutex<Lru<Entries>>> = Lazy::new(|| {
+    let capacity = std::env::var("ANALYSIS_CACHE_SIZE")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(DEFAULT_CAPACITY);
+    std::sync::Mutex::new(Lru::new(capacity))
+});
+
+static PERSISTENT: OnceCell<Arc<Database>> = OnceCell::new();
+
+static HITS: AtomicU64 = AtomicU64::new(0);
+static DB_HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Serialize)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub capacity: usize,
+    pub persistent: bool,
+    pub hits: u64,
+    pub db_hits: u64,
+    pub misses: u64,
+}
+
+fn env_i64(name: &str, default: i64) -> i64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|&v: &i64| v > 0)
+        .unwrap_or(default)
+}
+
+/// Postgres 层条目的有效期（秒）
+fn persist_ttl_secs() -> i64 {
+    env_i64("ANALYSIS_CACHE_PERSIST_TTL_HOURS", DEFAULT_PERSIST_TTL_HOURS) * 3600
+}
+
+/// 按 ANALYSIS_CACHE_PERSIST 决定是否启用 Postgres 层，启用时在后台定期清理过期与超量的行
+pub fn init(db: Arc<Database>) {
+    let enabled = std::env::var("ANALYSIS_CACHE_PERSIST")
+        .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"))
+        .unwrap_or(false);
+    if !enabled || PERSISTENT.set(db.clone()).is_err() {
+        return;
+    }
+    let max_rows = env_i64("ANALYSIS_CACHE_PERSIST_MAX_ROWS", DEFAULT_PERSIST_MAX_ROWS);
+    tracing::info!(
+        "analysis cache: postgres tier enabled (ttl {}s, max {} rows)",
+        persist_ttl_secs(),
+        max_rows
+    );
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+        loop {
+            interval.tick().await;
+            match db.prune_analysis_cache(persist_ttl_secs(), max_rows).await {
+                Ok(0) => {}
+                Ok(removed) => tracing::info!("analysis cache: pruned {} rows", removed),
+                Err(err) => tracing::warn!("analysis cache prune failed: {}", err),
+            }
+        }
+    });
+}
+
+fn parse_point(pos: &str, size: usize) -> Option<usize> {
+    let (x, y) = pos.split_once(',')?;
+    let (x, y) = (x.trim().parse::<usize>().ok()?, y.trim().parse::<usize>().ok()?);
+    if x == 0 || y == 0 || x > size || y > size {
+        return None;
+    }
+    Some((x - 1) * size + (y - 1))
+}
+
+/// 着法重放后的局面键；不使用缓存、或着法无法解析/不合法时返回 None
+pub fn key(scope: &AnalysisScope<'_>, moves: &[MoveItem], to_move: &str) -> Option<String> {
+    if !scope.cached {
+        return None;
+    }
+    let size = scope.size as usize;
+    let mut board = Board::new(size);
+    // Same situational-superko history as QuantumGame: (position, side that just moved)
+    let mut history = HashSet::from([board.hash_with_turn(Stone::Black)]);
+    for m in moves {
+        let color = Stone::parse(&m.color)?;
+        board = board.after_move(parse_point(&m.position, size)?, color)?;
+        history.insert(board.hash_with_turn(color));
+    }
+    let to_move = color_to_gtp(to_move);
+    let stone = if to_move == "B" { Stone::Black } else { Stone::White };
+    // Points the history forbids: the same stones with a different ko state need a different answer
+    let forbidden: Vec<String> = (0..size * size)
+        .filter(|&p| {
+            board
+                .after_move(p, stone)
+                .is_some_and(|next| history.contains(&next.hash_with_turn(stone)))
+        })
+        .map(|p| p.to_string())
+        .collect();
+    let forbidden = if forbidden.is_empty() { "-".to_string() } else { forbidden.join(",") };
+    Some(format!(
+        "{}:{}:{}:{:016x}:{}:{}:{}",
+        scope.size,
+        scope.komi,
+        scope.rules.id(),
+        board.hash_with_turn(stone),
+        to_move,
+        forbidden,
+        scope.level
+    ))
+}
+
+pub async fn get(key: &str) -> Option<Entries> {
+    if let Some(hit) = MEMORY.lock().unwrap().get(key) {
+        HITS.fetch_add(1, Ordering::Relaxed);
+        return Some(hit);
+    }
+    if let Some(db) = PERSISTENT.get() {
+        match db.get_analysis_cache(key, persist_ttl_secs()).await {
+            Ok(Some(value)) => {
+                if let Ok(entries) = serde_json::from_value::<Vec<InfoEntry>>(value) {
+                    let entries = Arc::new(entries);
+                    MEMORY.lock().unwrap().put(key.to_string(), entries.clone());
+                    DB_HITS.fetch_add(1, Ordering::Relaxed);
+                    return Some(entries);
+                }
+            }
+            Ok(None) => {}
+            Err(err) => tracing::warn!("analysis cache lookup failed: {}", err),
+        }
+    }
+    MISSES.fetch_add(1, Ordering::Relaxed);
+    None
+}
+
+pub fn put(key: String, entries: Entries) {
+    if let Some(db) = PERSISTENT.get() {
+        let (db, key, entries) = (db.clone(), key.clone(), entries.clone());
+        // Written in the background so the request does not wait on the database
+        tokio::spawn(async move {
+            let Ok(value) = serde_json::to_value(&*entries) else { return };
+            if let Err(err) = db.put_analysis_cache(&key, &value).await {
+                tracing::warn!("analysis cache write failed: {}", err);
+            }
+        });
+    }
+    MEMORY.lock().unwrap().put(key, entries);
+}
+
+pub fn stats() -> CacheStats {
+    let memory = MEMORY.lock().unwrap();
+    CacheStats {
+        entries: memory.len(),
+        capacity: memory.capacity,
+        persistent: PERSISTENT.get().is_some(),
+        hits: HITS.load(Ordering::Relaxed),
+        db_hits: DB_HITS.load(Ordering::Relaxed),
+        misses: MISSES.load(Ordering::Relaxed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mv(color: &str, position: &str) -> MoveItem {
+        MoveItem { color: color.to_string(), position: position.to_string() }
+    }
+
+    #[test]
+    fn test_key_is_shared_by_transpositions_only() {
+        let scope = AnalysisScope { size: 9, komi: 7.5, rules: Ruleset::Chinese, level: "intermediate", cached: true };
+        let a = key(&scope, &[mv("black", "3,3"), mv("white", "5,5"), mv("black", "7,7")], "white");
+        let b = key(&scope, &[mv("black", "7,7"), mv("white", "5,5"), mv("black", "3,3")], "white");
+        assert!(a.is_some());
+        assert_eq!(a, b);
+        assert_ne!(a, key(&scope, &[mv("black", "7,7"), mv("white", "5,5"), mv("black", "3,3")], "black"));
+        let other_level = AnalysisScope { level: "expert", ..scope };
+        assert_ne!(a, key(&other_level, &[mv("black", "3,3"), mv("white", "5,5"), mv("black", "7,7")], "white"));
+        // Occupied point: not a real position, never cached
+        assert!(key(&other_level, &[mv("black", "3,3"), mv("white", "3,3")], "black").is_none());
+    }
+
+    #[test]
+    fn test_key_carries_ko_history() {
+        let scope = AnalysisScope { size: 9, komi: 7.5, rules: Ruleset::Chinese, level: "intermediate", cached: true };
+        let frame = [mv("black", "1,2"), mv("white", "1,3"), mv("black", "2,1"), mv("white", "3,3"), mv("black", "3,2"), mv("white", "2,4"), mv("black", "9,9")];
+        // Black takes the ko at 2,2: white may not retake immediately
+        let mut fought = frame.to_vec();
+        fought.extend([mv("white", "2,2"), mv("black", "2,3")]);
+        // Same stones, but 2,2 was never occupied
+        let mut quiet = frame.to_vec();
+        quiet.push(mv("black", "2,3"));
+        let fought = key(&scope, &fought, "white").unwrap();
+        let quiet = key(&scope, &quiet, "white").unwrap();
+        assert!(fought.contains(":10:"), "{}", fought);
+        assert!(quiet.contains(":-:"), "{}", quiet);
+        assert_ne!(fought, quiet);
+    }
+
+    #[test]
+    fn test_lru_evicts_least_recently_used() {
+        let mut lru = Lru::new(2);
+        lru.put("a".to_string(), 1);
+        lru.put("b".to_string(), 2);
+        assert_eq!(lru.get("a"), Some(1));
+        lru.put("c".to_string(), 3);
+        assert_eq!(lru.get("b"), None);
+        assert_eq!(lru.get("a"), Some(1));
+        assert_eq!(lru.get("c"), Some(3));
+        assert_eq!(lru.len(), 2);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::entity::{RoomInfo, RoomSummary, User, LeaderboardEntry};
+use crate::entity::{RoomInfo, RoomSummary, User, LeaderboardEntry, OverallRating, SizeRatingEntry};
 use axum::{Json, extract::State, http::StatusCode};
 use serde::Deserialize;
 use uuid::Uuid;
@@ -14,6 +14,16 @@ use crate::katago::{
     estimate_with_score_estimator,
 };
 use crate::jwt::verify_jwt_token;
+use crate::analysis_cache;
+use crate::katago_pool;
+use crate::ai_level;
+use crate::katago_analysis::{self, AnalyzeRequest, AnalyzeResponse};
+use crate::quantum_search::{self, QuantumSearchRequest, QuantumSearchResponse};
+use crate::handicap::{initial_board, max_handicap, HANDICAP_KOMI};
+use crate::review::{self, ReviewStatus};
+use crate::matchmaking::{self, MatchStatus, QueueRequest};
+use crate::rules::Ruleset;
+use crate::seating::{ColorChoice, Players};
 
 type ApiResult<T> = Result<(StatusCode, Json<T>), (StatusCode, Json<serde_json::Value>)>;
 
@@ -46,8 +56,19 @@ pub struct CreateRoomRequest {
     user_id: Uuid,
     model: i32,
     countdown: i32,
+    // "pvp"（默认）或 "ai"：AI 房间由服务端机器人坐访客席位
     game_mode: Option<String>,
+    // AI 房间的难度档位（见 /ai/levels），缺省为默认档位
+    ai_level: Option<String>,
+    // 是否计入评分，默认计入
+    rated: Option<bool>,
+    // 房主执子颜色：black（默认）/ white / random / nigiri；AI 房间中玩家固定执黑
+    color: Option<String>,
     komi: Option<f64>,
+    // 规则集（chinese / japanese / tromp-taylor / aga / quantum），缺省为本站量子规则
+    rules: Option<String>,
+    // Handicap stones: 0/None = even, 1 = no stones with reduced komi, 2+ = fixed placement
+    handicap: Option<i32>,
     time_control: Option<serde_json::Value>,
     // Phase 1 lobby options (optional)
     is_public: Option<bool>,
@@ -55,11 +76,30 @@ pub struct CreateRoomRequest {
     allow_spectate: Option<bool>,
 }
 
+#[derive(Deserialize)]
+pub struct MatchQueueRequest {
+    user_id: Uuid,
+    model: i32,
+    time_control: Option<serde_json::Value>,
+    // 是否计入评分，默认计入
+    rated: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct MatchCancelRequest {
+    user_id: Uuid,
+}
+
 #[derive(Deserialize)]
 pub struct GetGameInfo {
     room_id: Uuid,
 }
 
+#[derive(Deserialize)]
+pub struct GameReviewRequest {
+    room_id: Uuid,
+}
+
 #[derive(Deserialize)]
 pub struct ListRoomsRequest {
     model: Option<i32>,
@@ -70,7 +110,7 @@ pub struct ListRoomsRequest {
 #[derive(Deserialize)]
 pub struct RecentRoomsRequest {
     user_id: Uuid,
-    status: Option<String>, // optional: 'waiting' | 'playing' | 'finished'
+    status: Option<String>, // optional: 'waiting' | 'playing' | 'scoring' | 'finished' | 'aborted'
     page: Option<i32>,
     size: Option<i32>,
 }
@@ -97,6 +137,10 @@ pub struct UserProfileResponse {
     pub wins: i32,
     pub losses: i32,
     pub draws: i32,
+    // 跨尺寸综合评分；尚未下过任何对局时为 null
+    pub overall: Option<OverallRating>,
+    // 各尺寸评分
+    pub rankings: Vec<SizeRatingEntry>,
 }
 
 #[axum::debug_handler]
@@ -137,19 +181,74 @@ pub async fn create_room(
     Json(req): Json<CreateRoomRequest>,
 ) -> ApiResult<serde_json::Value> {
     let room_id = Uuid::new_v4();
-    
- 
-    let (status, phase) = ("waiting".to_string(), None);
-    
+
+    let handicap = req.handicap.unwrap_or(0);
+    if handicap < 0 || handicap > max_handicap(req.model) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": format!("Invalid handicap {} for board size {}", handicap, req.model)
+            })),
+        ));
+    }
+    let rules = Ruleset::resolve(req.rules.as_deref(), Ruleset::Quantum).map_err(|err| {
+        (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": err.to_string() })))
+    })?;
+    let default_komi = if handicap > 0 { HANDICAP_KOMI } else { 7.5 };
+    // 摆了让子后由白方先行
+    let round = if handicap >= 2 { "white" } else { "black" };
+
+    // AI 房间：机器人账号直接占据访客席位，对局立即开始，不出现在大厅
+    let bot = match req.game_mode.as_deref().unwrap_or("pvp") {
+        "pvp" => None,
+        "ai" => {
+            let level = ai_level::resolve(req.ai_level.as_deref()).map_err(|err| {
+                (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": err.to_string() })))
+            })?;
+            let user = state.db.ensure_bot_user(&level.bot_username(), level.rating).await.map_err(|err| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(serde_json::json!({ "error": format!("Failed to prepare AI player: {}", err) })),
+                )
+            })?;
+            Some((level, user.user_id))
+        }
+        other => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({ "error": format!("Invalid game mode: {}", other) })),
+            ))
+        }
+    };
+
+    let color = ColorChoice::resolve(req.color.as_deref()).map_err(|err| {
+        (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": err.to_string() })))
+    })?;
+    if bot.is_some() && color != ColorChoice::Black {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": "In AI rooms the player takes Black" })),
+        ));
+    }
+    let visitor_id = bot.map(|(_, id)| id);
+    let players = Players::on_create(color, req.user_id, visitor_id, rand::random());
+
+    let (status, phase) = match bot {
+        Some(_) => ("playing".to_string(), None),
+        None => ("waiting".to_string(), None),
+    };
+
     let room_info = RoomInfo {
         id: 0,
         room_id,
         owner_id: req.user_id,
-        visitor_id: None,
+        visitor_id,
+        black_id: players.black_id,
+        white_id: players.white_id,
         status,
-        round: "black".to_string(),
+        round: round.to_string(),
         winner: None,
-        board: serde_json::Value::Object(serde_json::Map::new()),
+        board: initial_board(req.model, handicap),
         moves: 0,
         white_lost: 0,
         black_lost: 0,
@@ -157,11 +256,17 @@ pub async fn create_room(
         model: req.model,
         chessman_records: serde_json::Value::Array(vec![]),
         phase,
-        komi: req.komi.unwrap_or(7.5),
+        komi: req.komi.unwrap_or(default_komi),
+        handicap,
         time_control: req.time_control,
-        is_public: req.is_public.unwrap_or(true),
-        is_listed: req.is_listed.unwrap_or(true),
+        is_public: req.is_public.unwrap_or(true) && bot.is_none(),
+        is_listed: req.is_listed.unwrap_or(true) && bot.is_none(),
         allow_spectate: req.allow_spectate.unwrap_or(true),
+        ai_level: bot.map(|(level, _)| level.id.to_string()),
+        rated: req.rated.unwrap_or(true),
+        rules: rules.id().to_string(),
+        result: None,
+        events: serde_json::Value::Array(vec![]),
         created_at: chrono::Utc::now(),
         last_activity_at: chrono::Utc::now(),
     };
@@ -233,12 +338,155 @@ pub async fn ai_genmove(
     match genmove_with_katago(req).await {
         Ok(resp) => Ok((StatusCode::OK, Json(resp))),
         Err(err) => Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
+            katago_error_status(&err),
             Json(serde_json::json!({ "error": format!("katago error: {}", err) })),
         )),
     }
 }
 
+// 局面分析：候选着（胜率、目差、访问数、主变化）与归属图，供讲解使用
+#[axum::debug_handler]
+pub async fn ai_analyze(
+    _state: State<crate::ws::AppState>,
+    Json(req): Json<AnalyzeRequest>,
+) -> ApiResult<AnalyzeResponse> {
+    match katago_analysis::analyze_position(req).await {
+        Ok(resp) => Ok((StatusCode::OK, Json(resp))),
+        Err(err) => Err((
+            katago_error_status(&err),
+            Json(serde_json::json!({ "error": format!("katago analysis error: {}", err) })),
+        )),
+    }
+}
+
+// 量子规则搜索：在两个现实上展开着法，返回选点与两盘各自的估值
+#[axum::debug_handler]
+pub async fn ai_quantum_search(
+    _state: State<crate::ws::AppState>,
+    Json(req): Json<QuantumSearchRequest>,
+) -> ApiResult<QuantumSearchResponse> {
+    match quantum_search::quantum_search(req).await {
+        Ok(resp) => Ok((StatusCode::OK, Json(resp))),
+        Err(err) => Err((
+            katago_error_status(&err),
+            Json(serde_json::json!({ "error": format!("quantum search error: {}", err) })),
+        )),
+    }
+}
+
+// 引擎池排队已满或等待超时时返回 503，客户端可稍后重试；参数错误（如未知档位）返回 400
+fn katago_error_status(err: &std::io::Error) -> StatusCode {
+    if katago_pool::is_overloaded(err) {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else if err.kind() == std::io::ErrorKind::InvalidInput {
+        StatusCode::BAD_REQUEST
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
+
+// 自动匹配：长轮询，匹配成功返回房间号与执子颜色，否则返回 waiting 由客户端再次请求
+#[axum::debug_handler]
+pub async fn match_queue(
+    State(state): State<crate::ws::AppState>,
+    Json(req): Json<MatchQueueRequest>,
+) -> ApiResult<MatchStatus> {
+    let request = QueueRequest {
+        user_id: req.user_id,
+        model: req.model,
+        time_control: req.time_control,
+        rated: req.rated.unwrap_or(true),
+    };
+    match matchmaking::poll(&state.db, request).await {
+        Ok(status) => Ok((StatusCode::OK, Json(status))),
+        Err(err) => {
+            // 未知用户返回 404，参数错误返回 400
+            let status = match err.kind() {
+                std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+                std::io::ErrorKind::InvalidInput => StatusCode::BAD_REQUEST,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            Err((status, Json(serde_json::json!({ "error": format!("match error: {}", err) }))))
+        }
+    }
+}
+
+#[axum::debug_handler]
+pub async fn match_cancel(
+    _state: State<crate::ws::AppState>,
+    Json(req): Json<MatchCancelRequest>,
+) -> ApiResult<serde_json::Value> {
+    let cancelled = matchmaking::cancel(req.user_id);
+    Ok((StatusCode::OK, Json(serde_json::json!({ "cancelled": cancelled }))))
+}
+
+// 赛后复盘：对局结束后排队分析，重复请求返回当前状态；完成后 review 字段为复盘结果
+#[axum::debug_handler]
+pub async fn game_review(
+    State(state): State<crate::ws::AppState>,
+    Json(req): Json<GameReviewRequest>,
+) -> ApiResult<ReviewStatus> {
+    match review::request_review(state.db.clone(), req.room_id).await {
+        Ok(status) => Ok((StatusCode::OK, Json(status))),
+        Err(err) => Err((
+            review_error_status(&err),
+            Json(serde_json::json!({ "error": format!("review error: {}", err) })),
+        )),
+    }
+}
+
+#[axum::debug_handler]
+pub async fn game_review_status(
+    State(state): State<crate::ws::AppState>,
+    Json(req): Json<GameReviewRequest>,
+) -> ApiResult<ReviewStatus> {
+    match review::review_status(&state.db, req.room_id).await {
+        Ok(status) => Ok((StatusCode::OK, Json(status))),
+        Err(err) => Err((
+            review_error_status(&err),
+            Json(serde_json::json!({ "error": format!("review error: {}", err) })),
+        )),
+    }
+}
+
+// 房间或复盘不存在返回 404，对局未结束返回 400，复盘队列已满返回 503
+fn review_error_status(err: &std::io::Error) -> StatusCode {
+    if err.kind() == std::io::ErrorKind::NotFound {
+        StatusCode::NOT_FOUND
+    } else {
+        katago_error_status(err)
+    }
+}
+
+#[axum::debug_handler]
+pub async fn ai_pool_stats(
+    _state: State<crate::ws::AppState>,
+) -> ApiResult<Vec<katago_pool::PoolStats>> {
+    Ok((StatusCode::OK, Json(katago_pool::all_stats().await)))
+}
+
+#[axum::debug_handler]
+pub async fn ai_cache_stats(
+    _state: State<crate::ws::AppState>,
+) -> ApiResult<analysis_cache::CacheStats> {
+    Ok((StatusCode::OK, Json(analysis_cache::stats())))
+}
+
+// 各引擎池与引擎进程的健康状况；引擎在重启退避中时 status 为 degraded
+#[axum::debug_handler]
+pub async fn health(
+    _state: State<crate::ws::AppState>,
+) -> ApiResult<katago_pool::HealthReport> {
+    Ok((StatusCode::OK, Json(katago_pool::health_report().await)))
+}
+
+#[axum::debug_handler]
+pub async fn ai_levels(
+    _state: State<crate::ws::AppState>,
+) -> ApiResult<Vec<ai_level::LevelInfo>> {
+    Ok((StatusCode::OK, Json(ai_level::describe_levels())))
+}
+
 #[axum::debug_handler]
 pub async fn list_rooms(
     State(state): State<crate::ws::AppState>,
@@ -310,6 +558,16 @@ pub async fn get_user_profile(
         },
     };
 
+    let rankings = match state.db.list_user_rankings(&user.user_id).await {
+        Ok(r) => r,
+        Err(e) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": format!("Failed to get rankings: {}", e) })),
+            ));
+        }
+    };
+
     let resp = UserProfileResponse {
         user_id: user.user_id,
         username: user.username,
@@ -319,6 +577,8 @@ pub async fn get_user_profile(
         wins: ranking.wins,
         losses: ranking.losses,
         draws: ranking.draws,
+        overall: crate::rating::overall_rating(&rankings),
+        rankings: rankings.iter().map(SizeRatingEntry::from).collect(),
     };
 
     Ok((StatusCode::OK, Json(resp)))
@@ -332,7 +592,7 @@ pub async fn score_estimate(
     match estimate_with_score_estimator(req).await {
         Ok(resp) => Ok((StatusCode::OK, Json(resp))),
         Err(err) => Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
+            katago_error_status(&err),
             Json(serde_json::json!({ "error": format!("score estimate error: {}", err) })),
         )),
     }
@@ -346,7 +606,7 @@ pub async fn ai_genmove_dual(
     match genmove_dual_with_katago(req).await {
         Ok(resp) => Ok((StatusCode::OK, Json(resp))),
         Err(err) => Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
+            katago_error_status(&err),
             Json(serde_json::json!({ "error": format!("katago dual-genmove error: {}", err) })),
         )),
     }
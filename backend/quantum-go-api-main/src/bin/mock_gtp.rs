@@ -0,0 +1,162 @@
+// 用于测试的假 GTP 引擎：按脚本返回固定回复，不需要 KataGo 和模型
+//
+// 参数：
+//   --genmove D4,C3,...   依次作为 genmove 的回复；`!crash` 表示直接退出，`!hang` 表示不再回复，`?msg` 表示返回 GTP 错误
+//   --counter FILE        genmove 序号保存在文件里，重启进程后继续往下走（配合 respawn 测试）
+//   --info "LINE"         kata-genmove_analyze 默认输出的 info 行（可重复）
+//   --after MOVE=WR,SL    上一手是 MOVE 时，kata-genmove_analyze 只输出一条 winrate/scoreLead 为给定值的 info
+//   --log FILE            把收到的每条命令追加写入文件
+//
+// 输出格式与 KataGo 一致：`=` 后跟 info 行，最后一行 `play <move>`，空行结束。
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, Write};
+
+struct Script {
+    genmove: Vec<String>,
+    counter: Option<String>,
+    info: Vec<String>,
+    after: HashMap<String, (f32, f32)>,
+    log: Option<String>,
+}
+
+fn parse_args() -> Script {
+    let mut script = Script { genmove: Vec::new(), counter: None, info: Vec::new(), after: HashMap::new(), log: None };
+    let mut args = std::env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let value = args.next().unwrap_or_default();
+        match flag.as_str() {
+            "--genmove" => script.genmove = value.split(',').map(|s| s.trim().to_string()).collect(),
+            "--counter" => script.counter = Some(value),
+            "--info" => script.info.push(value),
+            "--after" => {
+                if let Some((mv, nums)) = value.split_once('=') {
+                    let mut parts = nums.split(',').map(|n| n.trim().parse::<f32>().unwrap_or(0.0));
+                    let wr = parts.next().unwrap_or(0.5);
+                    let sl = parts.next().unwrap_or(0.0);
+                    script.after.insert(mv.to_ascii_uppercase(), (wr, sl));
+                }
+            }
+            "--log" => script.log = Some(value),
+            _ => {}
+        }
+    }
+    script
+}
+
+// Next genmove index; persisted when a counter file is given so respawned processes continue the script
+fn next_index(script: &Script, local: &mut usize) -> usize {
+    match &script.counter {
+        Some(path) => {
+            let current = std::fs::read_to_string(path).ok().and_then(|s| s.trim().parse().ok()).unwrap_or(0usize);
+            let _ = std::fs::write(path, (current + 1).to_string());
+            current
+        }
+        None => {
+            *local += 1;
+            *local - 1
+        }
+    }
+}
+
+fn valid_vertex(v: &str) -> bool {
+    if v.eq_ignore_ascii_case("pass") {
+        return true;
+    }
+    let mut chars = v.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_uppercase() && c != 'I' => {}
+        _ => return false,
+    }
+    chars.as_str().parse::<u8>().is_ok_and(|n| (1..=25).contains(&n))
+}
+
+fn main() {
+    let script = parse_args();
+    let stdin = io::stdin();
+    let mut out = io::stdout();
+    let mut local_counter = 0usize;
+    let mut last_move: Option<String> = None;
+
+    for line in stdin.lock().lines() {
+        let Ok(line) = line else { break };
+        let cmd = line.trim();
+        if cmd.is_empty() {
+            continue;
+        }
+        if let Some(path) = &script.log {
+            if let Ok(mut f) = OpenOptions::new().create(true).append(true).open(path) {
+                let _ = writeln!(f, "{}", cmd);
+            }
+        }
+
+        let mut parts = cmd.split_whitespace();
+        let name = parts.next().unwrap_or("");
+        let reply = match name {
+            "boardsize" | "komi" => Ok(String::new()),
+            "kata-set-rules" => match parts.next() {
+                Some(_) => Ok(String::new()),
+                None => Err("expected rules".to_string()),
+            },
+            "clear_board" => {
+                last_move = None;
+                Ok(String::new())
+            }
+            "play" => {
+                let _color = parts.next();
+                match parts.next() {
+                    Some(v) if valid_vertex(v) => {
+                        last_move = Some(v.to_ascii_uppercase());
+                        Ok(String::new())
+                    }
+                    _ => Err("illegal move".to_string()),
+                }
+            }
+            "genmove" => {
+                let idx = next_index(&script, &mut local_counter);
+                let mv = script.genmove.get(idx).or(script.genmove.last()).cloned().unwrap_or_else(|| "pass".to_string());
+                if mv == "!crash" {
+                    std::process::exit(1);
+                }
+                if mv == "!hang" {
+                    eprintln!("mock engine hanging on genmove");
+                    loop {
+                        std::thread::sleep(std::time::Duration::from_secs(60));
+                    }
+                }
+                match mv.strip_prefix('?') {
+                    Some(msg) => Err(msg.to_string()),
+                    None => Ok(mv),
+                }
+            }
+            "kata-genmove_analyze" => {
+                let info: Vec<String> = match last_move.as_ref().and_then(|m| script.after.get(m)) {
+                    Some((wr, sl)) => vec![format!("info move pass visits 100 winrate {} scoreLead {} order 0 pv pass", wr, sl)],
+                    None => script.info.clone(),
+                };
+                let played = info
+                    .first()
+                    .and_then(|l| l.split_whitespace().skip_while(|t| *t != "move").nth(1))
+                    .unwrap_or("pass")
+                    .to_string();
+                let _ = write!(out, "=\n{}\nplay {}\n\n", info.join("\n"), played);
+                let _ = out.flush();
+                continue;
+            }
+            "name" => Ok("MockGTP".to_string()),
+            "version" => Ok("0.0".to_string()),
+            "quit" => {
+                let _ = write!(out, "=\n\n");
+                let _ = out.flush();
+                break;
+            }
+            _ => Err("unknown command".to_string()),
+        };
+        let _ = match reply {
+            Ok(r) if r.is_empty() => write!(out, "=\n\n"),
+            Ok(r) => write!(out, "= {}\n\n", r),
+            Err(e) => write!(out, "? {}\n\n", e),
+        };
+        let _ = out.flush();
+    }
+}
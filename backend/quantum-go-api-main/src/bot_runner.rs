@@ -0,0 +1,390 @@
+// 大厅机器人
+//
+// 定期查看大厅中等待已久的公开房间，以普通客户端身份连接 /ws/{user_id}/{room_id} 加入对局，
+// 收发与浏览器客户端相同的消息（updateChess / stoneRemoval* / resign 等），落子交给 KataGo。
+// 因为完全走 WebSocket 协议，它同时也是协议的端到端测试客户端。
+//
+// BOT_RUNNER_ENABLED=1 时随服务启动，其余配置：
+//   BOT_RUNNER_LEVEL           机器人档位（默认 intermediate），使用该档位的机器人账号与固定评分
+//   BOT_RUNNER_MAX_GAMES       同时进行的对局数上限（默认 2）
+//   BOT_RUNNER_POLL_SECS       查看大厅的间隔（默认 15 秒）
+//   BOT_RUNNER_JOIN_AFTER_SECS 房间等待多久无人加入后机器人才接手（默认 60 秒）
+//   BOT_RUNNER_MODELS          接受的棋盘尺寸，逗号分隔（默认全部）
+//   BOT_RUNNER_IDLE_SECS       对局中长时间收不到消息时离开（默认 900 秒）
+//   BOT_RUNNER_WS_URL          WebSocket 地址前缀（默认 ws://127.0.0.1:$PORT/ws）
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::ai_level::{self, AiLevel};
+use crate::ai_room::{board_entries, choose_move, pass_chessman, BotMove, LiveGame};
+use crate::db::Database;
+use crate::entity::{Chessman, RoomInfo};
+use crate::quantum::Stone;
+use crate::rules::Ruleset;
+use crate::seating::{self, Players};
+
+const DEFAULT_LEVEL: &str = "intermediate";
+
+fn env_u64(key: &str, default: u64) -> u64 {
+    std::env::var(key).ok().and_then(|v| v.trim().parse().ok()).unwrap_or(default)
+}
+
+pub struct RunnerConfig {
+    pub level: &'static AiLevel,
+    pub max_games: usize,
+    pub poll: Duration,
+    pub join_after: Duration,
+    pub models: Vec<i32>,
+    pub idle: Duration,
+    pub ws_url: String,
+}
+
+impl RunnerConfig {
+    /// 未启用时返回 None；档位无效时返回错误
+    pub fn from_env(port: &str) -> io::Result<Option<Self>> {
+        let enabled = std::env::var("BOT_RUNNER_ENABLED").is_ok_and(|v| v == "1" || v.eq_ignore_ascii_case("true"));
+        if !enabled {
+            return Ok(None);
+        }
+        let level = ai_level::resolve(Some(&std::env::var("BOT_RUNNER_LEVEL").unwrap_or_else(|_| DEFAULT_LEVEL.to_string())))?;
+        let models = std::env::var("BOT_RUNNER_MODELS")
+            .ok()
+            .map(|v| v.split(',').filter_map(|m| m.trim().parse().ok()).collect::<Vec<i32>>())
+            .filter(|m| !m.is_empty())
+            .unwrap_or_else(|| vec![7, 9, 13, 19]);
+        Ok(Some(Self {
+            level,
+            max_games: env_u64("BOT_RUNNER_MAX_GAMES", 2).max(1) as usize,
+            poll: Duration::from_secs(env_u64("BOT_RUNNER_POLL_SECS", 15).max(1)),
+            join_after: Duration::from_secs(env_u64("BOT_RUNNER_JOIN_AFTER_SECS", 60)),
+            models,
+            idle: Duration::from_secs(env_u64("BOT_RUNNER_IDLE_SECS", 900).max(1)),
+            ws_url: std::env::var("BOT_RUNNER_WS_URL").unwrap_or_else(|_| format!("ws://127.0.0.1:{}/ws", port)),
+        }))
+    }
+}
+
+/// 机器人一侧的对局状态机：输入服务端推送的消息，输出要发送的消息
+pub(crate) struct BotSession {
+    live: LiveGame,
+    bot_id: Uuid,
+    color: Stone,
+    komi: f64,
+    rules: Ruleset,
+    level: &'static AiLevel,
+    my_pass: bool,
+    // Dead stones the server proposed when scoring started ([A, B])
+    proposal: Option<[HashSet<usize>; 2]>,
+    pub finished: bool,
+}
+
+fn message(mode: &str, data: Value) -> Value {
+    json!({ "type": mode, "data": data })
+}
+
+impl BotSession {
+    /// 机器人作为访客加入，执房主留下的颜色；猜先房间以 startGame 中的颜色为准
+    pub fn new(room: &RoomInfo, bot_id: Uuid, level: &'static AiLevel) -> io::Result<Self> {
+        Ok(Self {
+            live: LiveGame::from_room(room)?,
+            bot_id,
+            color: seating::color_of(room, bot_id),
+            komi: room.komi,
+            rules: Ruleset::from_id(&room.rules),
+            level,
+            my_pass: false,
+            proposal: None,
+            finished: false,
+        })
+    }
+
+    // Marked points of a stoneRemovalStart / stoneRemovalUpdate, per board
+    fn marks(&self, data: &Value) -> [HashSet<usize>; 2] {
+        ["board1", "board2"].map(|key| {
+            data.get(key)
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(|pos| self.live.game.index_of(pos.as_str()?))
+                .collect()
+        })
+    }
+
+    // A marking is acceptable if it is no worse for the bot than the server's proposal:
+    // none of its stones newly dead and none of the opponent's proposed dead stones revived
+    fn acceptable(&self, marks: &[HashSet<usize>; 2]) -> bool {
+        let Some(proposal) = &self.proposal else { return false };
+        (0..2).all(|b| {
+            let color = |idx: &usize| self.live.game.boards[b].get(*idx);
+            marks[b].difference(&proposal[b]).all(|idx| color(idx) != Some(self.color))
+                && proposal[b].difference(&marks[b]).all(|idx| color(idx) != Some(self.color.opposite()))
+        })
+    }
+
+    fn on_removal(&mut self, mode: &str, data: &Value) -> Vec<Value> {
+        let marks = self.marks(data);
+        if mode == "stoneRemovalStart" {
+            self.proposal = Some(marks.clone());
+        }
+        if self.acceptable(&marks) {
+            // Carry the marks so the accept only counts if they are still the current ones
+            let accepted = json!({ "board1": data.get("board1"), "board2": data.get("board2") });
+            return vec![message("stoneRemovalAccept", accepted)];
+        }
+        // The opponent marked live bot stones dead (or revived its own dead ones): play it out instead
+        self.proposal = None;
+        self.my_pass = false;
+        vec![message("stoneRemovalExit", json!({}))]
+    }
+
+    pub async fn on_message(&mut self, mode: &str, data: &Value) -> io::Result<Vec<Value>> {
+        match mode {
+            "startGame" => {
+                if let Ok(players) = serde_json::from_value::<Players>(data.clone()) {
+                    if players.black_id == Some(self.bot_id) {
+                        self.color = Stone::Black;
+                    } else if players.white_id == Some(self.bot_id) {
+                        self.color = Stone::White;
+                    }
+                }
+                // The bot opens when it plays Black, or White in a handicap game
+                if self.live.game.to_move == self.color && self.live.records.is_empty() {
+                    return self.take_turn().await;
+                }
+                Ok(Vec::new())
+            }
+            "updateChess" => {
+                let chessman: Chessman = serde_json::from_value(data.get("putChess").cloned().unwrap_or(Value::Null))
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                if chessman.position == "0,0" {
+                    if self.my_pass {
+                        // Both passed: the server opens the scoring phase
+                        return Ok(Vec::new());
+                    }
+                } else {
+                    let idx = self
+                        .live
+                        .game
+                        .index_of(&chessman.position)
+                        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad position"))?;
+                    self.live.game.to_move = self.color.opposite();
+                    self.live.play(idx)?;
+                }
+                self.take_turn().await
+            }
+            // Accept the server's proposal, and changes that are no worse for the bot
+            "stoneRemovalStart" | "stoneRemovalUpdate" => Ok(self.on_removal(mode, data)),
+            "stoneRemovalExit" => {
+                self.my_pass = false;
+                self.take_turn().await
+            }
+            "backChessApply" => Ok(vec![message("backChessResult", json!({ "operation": false }))]),
+            "setWinner" | "gameOver" => {
+                self.finished = true;
+                Ok(Vec::new())
+            }
+            "error" => Err(io::Error::other(data.get("message").and_then(Value::as_str).unwrap_or("server error").to_string())),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    async fn take_turn(&mut self) -> io::Result<Vec<Value>> {
+        self.live.game.to_move = self.color;
+        match choose_move(&self.live.game, self.komi, self.rules, self.level).await? {
+            BotMove::Play(idx) => {
+                let put_chess = self.live.play(idx)?;
+                self.my_pass = false;
+                let update = self.live.update(put_chess);
+                let mut data = serde_json::to_value(&update).map_err(io::Error::other)?;
+                data["board2"] = board_entries(&self.live.game, 1);
+                Ok(vec![message("updateChess", data)])
+            }
+            // A pass after the opponent's pass ends play; the server then starts scoring
+            BotMove::Pass => {
+                self.my_pass = true;
+                let data = json!({
+                    "putChess": pass_chessman(self.color),
+                    "board": board_entries(&self.live.game, 0),
+                    "black_lost": 0,
+                    "white_lost": 0,
+                    "chessman_records": self.live.records,
+                });
+                Ok(vec![message("updateChess", data)])
+            }
+            BotMove::Resign => {
+                self.finished = true;
+                Ok(vec![message("resign", json!({}))])
+            }
+        }
+    }
+}
+
+async fn play_room(db: &Database, config: &RunnerConfig, bot_id: Uuid, room_id: Uuid) -> io::Result<()> {
+    let room = db.get_room_by_room_id(room_id).await.map_err(io::Error::other)?;
+    if room.status != "waiting" || room.visitor_id.is_some() {
+        return Ok(());
+    }
+    let mut session = BotSession::new(&room, bot_id, config.level)?;
+    let url = format!("{}/{}/{}", config.ws_url.trim_end_matches('/'), bot_id, room_id);
+    let (mut socket, _) = tokio_tungstenite::connect_async(url.as_str()).await.map_err(io::Error::other)?;
+    info!("bot {} joined room {}", config.level.id, room_id);
+
+    while !session.finished {
+        let frame = match tokio::time::timeout(config.idle, socket.next()).await {
+            Ok(Some(frame)) => frame.map_err(io::Error::other)?,
+            Ok(None) => break,
+            Err(_) => return Err(io::Error::new(io::ErrorKind::TimedOut, "opponent idle")),
+        };
+        let Message::Text(text) = frame else { continue };
+        let Ok(msg) = serde_json::from_str::<Value>(text.as_str()) else { continue };
+        let mode = msg.get("type").and_then(Value::as_str).unwrap_or_default();
+        for reply in session.on_message(mode, msg.get("data").unwrap_or(&Value::Null)).await? {
+            socket.send(Message::Text(reply.to_string().into())).await.map_err(io::Error::other)?;
+        }
+    }
+    let _ = socket.close(None).await;
+    Ok(())
+}
+
+async fn run(db: Arc<Database>, config: Arc<RunnerConfig>) {
+    let slots = Arc::new(Semaphore::new(config.max_games));
+    let joined: Arc<Mutex<HashSet<Uuid>>> = Arc::new(Mutex::new(HashSet::new()));
+    let mut ticker = tokio::time::interval(config.poll);
+    loop {
+        ticker.tick().await;
+        let bot_id = match db.ensure_bot_user(&config.level.bot_username(), config.level.rating).await {
+            Ok(user) => user.user_id,
+            Err(err) => {
+                info!("bot runner: failed to prepare bot account: {}", err);
+                continue;
+            }
+        };
+        let rooms = match db.list_public_waiting_rooms_summary(None, 50, 0).await {
+            Ok(rooms) => rooms,
+            Err(err) => {
+                info!("bot runner: failed to list rooms: {}", err);
+                continue;
+            }
+        };
+        let join_before = chrono::Utc::now() - chrono::Duration::from_std(config.join_after).unwrap_or_default();
+        // Oldest rooms first: they have waited longest for a human opponent
+        for room in rooms.iter().rev() {
+            if room.created_at > join_before || !config.models.contains(&room.model) || room.owner_id == bot_id {
+                continue;
+            }
+            if joined.lock().unwrap().contains(&room.room_id) {
+                continue;
+            }
+            let Ok(permit) = slots.clone().try_acquire_owned() else { break };
+            joined.lock().unwrap().insert(room.room_id);
+            let (db, config, room_id) = (db.clone(), config.clone(), room.room_id);
+            tokio::spawn(async move {
+                if let Err(err) = play_room(&db, &config, bot_id, room_id).await {
+                    info!("bot runner: game in room {} ended with error: {}", room_id, err);
+                }
+                drop(permit);
+            });
+        }
+    }
+}
+
+/// 在后台启动大厅机器人
+pub fn spawn(db: Arc<Database>, config: RunnerConfig) {
+    info!(
+        "bot runner enabled: level={}, max_games={}, join_after={:?}",
+        config.level.id, config.max_games, config.join_after
+    );
+    tokio::spawn(run(db, Arc::new(config)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn room() -> RoomInfo {
+        RoomInfo {
+            id: 1,
+            room_id: Uuid::new_v4(),
+            owner_id: Uuid::new_v4(),
+            visitor_id: None,
+            black_id: None,
+            white_id: None,
+            status: "waiting".to_string(),
+            round: "black".to_string(),
+            winner: None,
+            board: json!({}),
+            countdown: 30,
+            moves: 0,
+            black_lost: 0,
+            white_lost: 0,
+            model: 9,
+            chessman_records: json!([]),
+            phase: None,
+            komi: 7.5,
+            handicap: 0,
+            time_control: None,
+            is_public: true,
+            is_listed: true,
+            allow_spectate: true,
+            ai_level: None,
+            rated: true,
+            rules: "quantum".to_string(),
+            result: None,
+            events: json!([]),
+            created_at: chrono::Utc::now(),
+            last_activity_at: chrono::Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_session_answers_protocol_messages_without_engine() {
+        let (room, bot_id) = (room(), Uuid::new_v4());
+        let mut session = BotSession::new(&room, bot_id, ai_level::resolve(Some("beginner")).unwrap()).unwrap();
+        // Nigiri gave the bot White: Black (the owner) moves first, so startGame needs no engine call
+        let players = json!({ "black_id": room.owner_id, "white_id": bot_id });
+        assert!(session.on_message("startGame", &players).await.unwrap().is_empty());
+        assert_eq!(session.color, Stone::White);
+
+        let replies = session.on_message("backChessApply", &json!({})).await.unwrap();
+        assert_eq!(replies, vec![json!({ "type": "backChessResult", "data": { "operation": false } })]);
+
+        session.on_message("gameOver", &json!({})).await.unwrap();
+        assert!(session.finished);
+        assert!(session.on_message("error", &json!({ "message": "Room is full" })).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_session_only_accepts_markings_no_worse_than_the_proposal() {
+        let (room, bot_id) = (room(), Uuid::new_v4());
+        let mut session = BotSession::new(&room, bot_id, ai_level::resolve(Some("beginner")).unwrap()).unwrap();
+        session.color = Stone::White;
+        // Black (the opponent) at 3,3 and the bot at 7,7 on board A
+        for pos in ["3,3", "7,7"] {
+            let idx = session.live.game.index_of(pos).unwrap();
+            session.live.play(idx).unwrap();
+        }
+        let marks = |board1: &[&str]| json!({ "board1": board1, "board2": [] });
+        let reply = |replies: Vec<Value>| replies[0]["type"].as_str().unwrap().to_string();
+
+        // The server's proposal is accepted with its marks attached
+        let replies = session.on_message("stoneRemovalStart", &marks(&["3,3"])).await.unwrap();
+        assert_eq!(replies[0], json!({ "type": "stoneRemovalAccept", "data": marks(&["3,3"]) }));
+        assert_eq!(reply(session.on_message("stoneRemovalUpdate", &marks(&["3,3"])).await.unwrap()), "stoneRemovalAccept");
+        // Killing the bot's live stone is refused by resuming play
+        assert_eq!(reply(session.on_message("stoneRemovalUpdate", &marks(&["3,3", "7,7"])).await.unwrap()), "stoneRemovalExit");
+
+        // So is reviving the opponent's dead stone; marking more opponent stones dead is fine
+        session.on_message("stoneRemovalStart", &marks(&["3,3"])).await.unwrap();
+        assert_eq!(reply(session.on_message("stoneRemovalUpdate", &marks(&[])).await.unwrap()), "stoneRemovalExit");
+        session.on_message("stoneRemovalStart", &marks(&[])).await.unwrap();
+        assert_eq!(reply(session.on_message("stoneRemovalUpdate", &marks(&["3,3"])).await.unwrap()), "stoneRemovalAccept");
+    }
+}
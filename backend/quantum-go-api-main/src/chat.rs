@@ -0,0 +1,293 @@
+// 房间聊天：玩家与观众分频道，落库、限流、过滤，可举报
+//
+// - 对局双方在 players 频道，观众（allow_spectate 的房间中第三方连接）在 spectators 频道；
+//   频道由身份决定，互不可见（观众的讨论不会传给对局者）
+// - chat {message}：过滤、限流后写入 room_chat，以 chat 推送给本频道所有连接（含发送方，带 id 便于举报）
+// - 旧版客户端的 sendMessage 走同一流程，过滤后的文本仍以 sendMessage 转发给对手
+// - reportChat {id, reason}：举报本频道中他人的消息，写入 chat_reports 供管理员处理
+// - 连接时推送本频道最近的聊天（chatHistory）
+// 过滤器可替换：实现 WordFilter 并在启动时 set_filter；默认按环境变量中的词表过滤。
+use axum::extract::ws::Message;
+use futures::sink::SinkExt;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::entity::{ChatMessage, Room, RoomInfo, WsSender};
+use crate::ws::{AppState, Data};
+
+const MAX_CHARS: usize = 300;
+const HISTORY_LIMIT: i64 = 50;
+// At most RATE_LIMIT messages per RATE_WINDOW and user
+const RATE_LIMIT: usize = 5;
+const RATE_WINDOW: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    Players,
+    Spectators,
+}
+
+impl Channel {
+    fn id(self) -> &'static str {
+        match self {
+            Channel::Players => "players",
+            Channel::Spectators => "spectators",
+        }
+    }
+}
+
+/// 对局双方在 players 频道，其余连接都是观众
+pub fn channel_of(room: &RoomInfo, user_id: Uuid) -> Channel {
+    if user_id == room.owner_id || room.visitor_id == Some(user_id) {
+        Channel::Players
+    } else {
+        Channel::Spectators
+    }
+}
+
+/// 是否为聊天消息
+pub fn handles(mode: &str) -> bool {
+    matches!(mode, "chat" | "sendMessage" | "reportChat")
+}
+
+pub enum Verdict {
+    Allow,
+    Mask(String),
+    Reject,
+}
+
+/// 聊天过滤器
+pub trait WordFilter: Send + Sync {
+    fn check(&self, text: &str) -> Verdict;
+}
+
+/// 默认词表过滤（不区分大小写）：CHAT_MASKED_WORDS 中的词替换为 *，
+/// 含 CHAT_BLOCKED_WORDS 中的词的消息整条拒绝；两者均为逗号分隔
+pub struct WordList {
+    masked: Vec<Vec<char>>,
+    blocked: Vec<Vec<char>>,
+}
+
+// One char per char, so positions in the folded text match the original
+fn fold(text: &str) -> Vec<char> {
+    text.chars().map(|c| c.to_lowercase().next().unwrap_or(c)).collect()
+}
+
+fn contains(text: &[char], word: &[char]) -> bool {
+    text.windows(word.len()).any(|w| w == word)
+}
+
+impl WordList {
+    pub fn new(masked: &[&str], blocked: &[&str]) -> Self {
+        let words = |list: &[&str]| list.iter().map(|w| fold(w.trim())).filter(|w| !w.is_empty()).collect();
+        Self { masked: words(masked), blocked: words(blocked) }
+    }
+
+    fn from_env() -> Self {
+        let var = |key: &str| std::env::var(key).unwrap_or_default();
+        let (masked, blocked) = (var("CHAT_MASKED_WORDS"), var("CHAT_BLOCKED_WORDS"));
+        Self::new(&masked.split(',').collect::<Vec<_>>(), &blocked.split(',').collect::<Vec<_>>())
+    }
+}
+
+impl WordFilter for WordList {
+    fn check(&self, text: &str) -> Verdict {
+        let folded = fold(text);
+        if self.blocked.iter().any(|word| contains(&folded, word)) {
+            return Verdict::Reject;
+        }
+        let mut chars: Vec<char> = text.chars().collect();
+        let mut masked = false;
+        for word in &self.masked {
+            for start in 0..folded.len() {
+                if folded[start..].starts_with(word) {
+                    chars[start..start + word.len()].fill('*');
+                    masked = true;
+                }
+            }
+        }
+        if masked {
+            Verdict::Mask(chars.into_iter().collect())
+        } else {
+            Verdict::Allow
+        }
+    }
+}
+
+static FILTER: OnceCell<Box<dyn WordFilter>> = OnceCell::new();
+
+/// 替换默认过滤器；须在第一条聊天之前调用，之后返回 false
+#[allow(dead_code)]
+pub fn set_filter(filter: Box<dyn WordFilter>) -> bool {
+    FILTER.set(filter).is_ok()
+}
+
+fn filter() -> &'static dyn WordFilter {
+    FILTER.get_or_init(|| Box::new(WordList::from_env())).as_ref()
+}
+
+/// 房间内各用户最近的发言时间（限流）
+#[derive(Default)]
+pub struct RoomChat {
+    sent: HashMap<Uuid, VecDeque<Instant>>,
+}
+
+impl RoomChat {
+    fn allow(&mut self, user_id: Uuid, now: Instant) -> bool {
+        let sent = self.sent.entry(user_id).or_default();
+        while sent.front().is_some_and(|&t| now.duration_since(t) >= RATE_WINDOW) {
+            sent.pop_front();
+        }
+        if sent.len() >= RATE_LIMIT {
+            return false;
+        }
+        sent.push_back(now);
+        true
+    }
+}
+
+#[derive(Deserialize)]
+struct ChatData {
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct ReportData {
+    id: i64,
+    reason: Option<String>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.to_string())
+}
+
+fn recipients(room: &Room, channel: Channel) -> Vec<WsSender> {
+    match channel {
+        Channel::Players => [&room.user1, &room.user2].into_iter().flatten().cloned().collect(),
+        Channel::Spectators => room.spectators.iter().map(|(_, sender)| sender.clone()).collect(),
+    }
+}
+
+async fn send<T: Serialize>(sender: &WsSender, mode: &str, data: T) {
+    let Ok(text) = serde_json::to_string(&Data { mode: mode.to_string(), data }) else { return };
+    let _ = sender.lock().await.send(Message::Text(text.into())).await;
+}
+
+// Checks, filters and stores one message; returns the stored row
+async fn post(state: &AppState, room: &mut Room, room_info: &RoomInfo, user_id: Uuid, text: &str) -> io::Result<ChatMessage> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(invalid("message is empty"));
+    }
+    if text.chars().count() > MAX_CHARS {
+        return Err(invalid(&format!("messages are limited to {} characters", MAX_CHARS)));
+    }
+    if !room.chat.allow(user_id, Instant::now()) {
+        return Err(invalid("you are sending messages too fast"));
+    }
+    let (message, original) = match filter().check(text) {
+        Verdict::Allow => (text.to_string(), None),
+        Verdict::Mask(masked) => (masked, Some(text)),
+        Verdict::Reject => return Err(invalid("message was blocked by the chat filter")),
+    };
+    let channel = channel_of(room_info, user_id);
+    state
+        .db
+        .insert_chat_message(room_info.room_id, user_id, channel.id(), &message, original)
+        .await
+        .map_err(io::Error::other)
+}
+
+async fn report(state: &AppState, room_info: &RoomInfo, user_id: Uuid, data: &Value) -> io::Result<i64> {
+    let data = serde_json::from_value::<ReportData>(data.clone()).map_err(|_| invalid("invalid report"))?;
+    let message = state.db.get_chat_message(data.id).await.map_err(io::Error::other)?;
+    // Only messages the reporter could see, and not their own
+    let message = message
+        .filter(|m| m.room_id == room_info.room_id && m.channel == channel_of(room_info, user_id).id() && m.user_id != user_id)
+        .ok_or_else(|| invalid("no such message"))?;
+    let reason = data.reason.as_deref().map(|r| r.chars().take(MAX_CHARS).collect::<String>());
+    if state.db.report_chat_message(message.id, user_id, reason.as_deref()).await.map_err(io::Error::other)? {
+        warn!(
+            "chat message {} by {} in room {} reported by {}: {}",
+            message.id,
+            message.user_id,
+            room_info.room_id,
+            user_id,
+            reason.as_deref().unwrap_or("-")
+        );
+    }
+    Ok(message.id)
+}
+
+async fn handle(state: &AppState, room: &mut Room, room_info: &RoomInfo, user_id: Uuid, own: &WsSender, mode: &str, data: &Value) -> io::Result<()> {
+    if mode == "reportChat" {
+        let id = report(state, room_info, user_id, data).await?;
+        send(own, "chatReported", json!({ "id": id })).await;
+        return Ok(());
+    }
+    let chat = serde_json::from_value::<ChatData>(data.clone()).map_err(|_| invalid("invalid chat message"))?;
+    let stored = post(state, room, room_info, user_id, &chat.message).await?;
+    for sender in recipients(room, channel_of(room_info, user_id)) {
+        if mode == "chat" {
+            send(&sender, "chat", &stored).await;
+        } else if !Arc::ptr_eq(&sender, own) {
+            // Legacy clients show their own message locally
+            send(&sender, "sendMessage", json!({ "message": stored.message })).await;
+        }
+    }
+    Ok(())
+}
+
+/// 处理 chat / sendMessage / reportChat；own 为发送方的连接，错误只回复给它
+pub async fn on_message(state: &AppState, room: &mut Room, room_info: &RoomInfo, user_id: Uuid, own: &WsSender, mode: &str, data: &Value) {
+    if let Err(err) = handle(state, room, room_info, user_id, own, mode, data).await {
+        send(own, "error", json!({ "message": err.to_string() })).await;
+    }
+}
+
+/// 连接时推送本频道最近的聊天
+pub async fn send_history(state: &AppState, room_info: &RoomInfo, user_id: Uuid, own: &WsSender) {
+    let channel = channel_of(room_info, user_id);
+    match state.db.list_room_chat(room_info.room_id, channel.id(), HISTORY_LIMIT).await {
+        Ok(messages) if !messages.is_empty() => send(own, "chatHistory", json!({ "channel": channel, "messages": messages })).await,
+        Ok(_) => {}
+        Err(err) => info!("Failed to load chat of room {}: {}", room_info.room_id, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_word_list_masks_and_blocks() {
+        let filter = WordList::new(&["dang", " 笨蛋 "], &["spam.example"]);
+        match filter.check("DANG it, 你这个笨蛋") {
+            Verdict::Mask(text) => assert_eq!(text, "**** it, 你这个**"),
+            _ => panic!("expected a masked message"),
+        }
+        assert!(matches!(filter.check("good game"), Verdict::Allow));
+        assert!(matches!(filter.check("visit SPAM.example now"), Verdict::Reject));
+        assert!(matches!(WordList::new(&[""], &[]).check("anything"), Verdict::Allow));
+    }
+
+    #[test]
+    fn test_rate_limit_is_per_user_and_window() {
+        let (mut chat, now) = (RoomChat::default(), Instant::now());
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        for _ in 0..RATE_LIMIT {
+            assert!(chat.allow(alice, now));
+        }
+        assert!(!chat.allow(alice, now + Duration::from_secs(1)));
+        assert!(chat.allow(bob, now));
+        assert!(chat.allow(alice, now + RATE_WINDOW));
+    }
+}
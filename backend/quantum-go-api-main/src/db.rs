@@ -1,4 +1,5 @@
-use crate::entity::{RoomInfo, RoomSummary, User, UserRanking, LeaderboardEntry};
+use crate::entity::{ChatMessage, GameReview, RoomInfo, RoomSummary, User, UserRanking, LeaderboardEntry};
+use crate::seating::Players;
 use bcrypt::{DEFAULT_COST, hash, verify};
 use sqlx::postgres::PgPoolOptions;
 use sqlx::{Error, PgPool};
@@ -8,6 +9,9 @@ use sqlx::Row;
 
 const MAX_CONNECTIONS: u32 = 50;
 
+// 机器人账号评分固定，RD 取较小值表示实力确定
+const BOT_RD: f64 = 50.0;
+
 /// Database connection and operations handler
 #[derive(Debug)]
 pub struct Database {
@@ -96,6 +100,19 @@ impl Database {
             println!("Komi column added successfully");
         }
 
+        // Ensure handicap column exists
+        let result_handicap = sqlx::query(
+            "SELECT column_name FROM information_schema.columns WHERE table_name = 'room_infos' AND column_name = 'handicap'"
+        )
+        .fetch_optional(pool)
+        .await?;
+        if result_handicap.is_none() {
+            println!("Adding handicap column to room_infos table...");
+            sqlx::query("ALTER TABLE room_infos ADD COLUMN handicap INTEGER NOT NULL DEFAULT 0")
+                .execute(pool)
+                .await?;
+        }
+
         // Ensure time_control column exists
         let result_time_control = sqlx::query(
             "SELECT column_name FROM information_schema.columns WHERE table_name = 'room_infos' AND column_name = 'time_control'"
@@ -169,6 +186,97 @@ impl Database {
                 .await?;
         }
 
+        // Ensure AI room columns exist
+        let result_ai_level = sqlx::query(
+            "SELECT column_name FROM information_schema.columns WHERE table_name = 'room_infos' AND column_name = 'ai_level'"
+        )
+        .fetch_optional(pool)
+        .await?;
+        if result_ai_level.is_none() {
+            println!("Adding ai_level column to room_infos table...");
+            sqlx::query("ALTER TABLE room_infos ADD COLUMN ai_level VARCHAR(32)")
+                .execute(pool)
+                .await?;
+        }
+        let result_rated = sqlx::query(
+            "SELECT column_name FROM information_schema.columns WHERE table_name = 'room_infos' AND column_name = 'rated'"
+        )
+        .fetch_optional(pool)
+        .await?;
+        if result_rated.is_none() {
+            println!("Adding rated column to room_infos table...");
+            sqlx::query("ALTER TABLE room_infos ADD COLUMN rated BOOLEAN NOT NULL DEFAULT TRUE")
+                .execute(pool)
+                .await?;
+        }
+
+        let result_rules = sqlx::query(
+            "SELECT column_name FROM information_schema.columns WHERE table_name = 'room_infos' AND column_name = 'rules'"
+        )
+        .fetch_optional(pool)
+        .await?;
+        if result_rules.is_none() {
+            println!("Adding rules column to room_infos table...");
+            sqlx::query("ALTER TABLE room_infos ADD COLUMN rules VARCHAR(20) NOT NULL DEFAULT 'quantum'")
+                .execute(pool)
+                .await?;
+        }
+
+        // Structured game result (outcome::GameOutcome)
+        let result_result = sqlx::query(
+            "SELECT column_name FROM information_schema.columns WHERE table_name = 'room_infos' AND column_name = 'result'"
+        )
+        .fetch_optional(pool)
+        .await?;
+        if result_result.is_none() {
+            println!("Adding result column to room_infos table...");
+            sqlx::query("ALTER TABLE room_infos ADD COLUMN result JSONB")
+                .execute(pool)
+                .await?;
+        }
+
+        // Players by colour; existing rooms were created with the owner playing Black
+        let result_black_id = sqlx::query(
+            "SELECT column_name FROM information_schema.columns WHERE table_name = 'room_infos' AND column_name = 'black_id'"
+        )
+        .fetch_optional(pool)
+        .await?;
+        if result_black_id.is_none() {
+            println!("Adding black_id / white_id columns to room_infos table...");
+            sqlx::query("ALTER TABLE room_infos ADD COLUMN black_id UUID, ADD COLUMN white_id UUID")
+                .execute(pool)
+                .await?;
+            sqlx::query("UPDATE room_infos SET black_id = owner_id, white_id = visitor_id")
+                .execute(pool)
+                .await?;
+        }
+
+        // Negotiation log kept next to chessman_records (which only holds moves)
+        let result_events = sqlx::query(
+            "SELECT column_name FROM information_schema.columns WHERE table_name = 'room_infos' AND column_name = 'events'"
+        )
+        .fetch_optional(pool)
+        .await?;
+        if result_events.is_none() {
+            println!("Adding events column to room_infos table...");
+            sqlx::query("ALTER TABLE room_infos ADD COLUMN events JSONB NOT NULL DEFAULT '[]'::jsonb")
+                .execute(pool)
+                .await?;
+        }
+
+        // Bot accounts (one per AI level) are regular users flagged with is_bot
+        let result_is_bot = sqlx::query(
+            "SELECT column_name FROM information_schema.columns WHERE table_name = 'users' AND column_name = 'is_bot'"
+        )
+        .fetch_optional(pool)
+        .await?;
+        if result_is_bot.is_none() {
+            println!("Adding is_bot column to users table...");
+            sqlx::query("ALTER TABLE users ADD COLUMN is_bot BOOLEAN NOT NULL DEFAULT FALSE")
+                .execute(pool)
+                .await?;
+        }
+
         // Create user_rankings table
         sqlx::query(
             r#"
@@ -192,6 +300,75 @@ impl Database {
         .execute(pool)
         .await?;
 
+        // Create game_reviews table (one review per finished room)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS game_reviews (
+                room_id UUID PRIMARY KEY,
+                status VARCHAR(20) NOT NULL,
+                review JSONB,
+                error TEXT,
+                created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+                updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        // Create room_chat table (players' and spectators' chat, see chat.rs)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS room_chat (
+                id BIGSERIAL PRIMARY KEY,
+                room_id UUID NOT NULL,
+                user_id UUID NOT NULL,
+                channel VARCHAR(16) NOT NULL,
+                message TEXT NOT NULL,
+                original TEXT,
+                created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS room_chat_room_idx ON room_chat (room_id, channel, id)")
+            .execute(pool)
+            .await?;
+
+        // Create chat_reports table (chat messages reported to moderators)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS chat_reports (
+                id SERIAL PRIMARY KEY,
+                chat_id BIGINT NOT NULL REFERENCES room_chat(id) ON DELETE CASCADE,
+                reporter_id UUID NOT NULL,
+                reason TEXT,
+                status VARCHAR(20) NOT NULL DEFAULT 'open',
+                created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+                UNIQUE(chat_id, reporter_id)
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        // Create analysis_cache table (second tier of the KataGo analysis cache)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS analysis_cache (
+                cache_key TEXT PRIMARY KEY,
+                entries JSONB NOT NULL,
+                created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS analysis_cache_created_idx ON analysis_cache (created_at)")
+            .execute(pool)
+            .await?;
+
         Ok(())
     }
 
@@ -217,6 +394,46 @@ impl Database {
         Ok(user)
     }
 
+    // AI 档位对应的机器人账号：不存在时创建（密码随机，无法登录），各尺寸评分固定为档位评分
+    pub async fn ensure_bot_user(&self, username: &str, rating: f64) -> Result<User, Error> {
+        let existing = sqlx::query_as::<_, User>("SELECT * FROM users WHERE username = $1 AND is_bot = TRUE")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await?;
+        let user = match existing {
+            Some(user) => user,
+            None => {
+                let user = sqlx::query_as::<_, User>(
+                    "INSERT INTO users (user_id, username, password, is_bot) VALUES ($1, $2, $3, TRUE) RETURNING *",
+                )
+                .bind(Uuid::new_v4())
+                .bind(username)
+                .bind(hash_password(&Uuid::new_v4().to_string())?)
+                .fetch_one(&self.pool)
+                .await?;
+                for model in [7, 9, 13, 19] {
+                    self.create_user_ranking(&user.user_id, model).await?;
+                }
+                user
+            }
+        };
+        sqlx::query("UPDATE user_rankings SET rating = $2, rd = $3, updated_at = NOW() WHERE user_id = $1")
+            .bind(user.user_id)
+            .bind(rating)
+            .bind(BOT_RD)
+            .execute(&self.pool)
+            .await?;
+        Ok(user)
+    }
+
+    pub async fn is_bot_user(&self, user_id: Uuid) -> Result<bool, Error> {
+        let row = sqlx::query("SELECT is_bot FROM users WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.is_some_and(|r| r.get::<bool, _>("is_bot")))
+    }
+
     pub async fn verify_user(&self, username: &str, password: &str) -> Result<User, Error> {
         let user = self.get_user_by_username(username).await?;
 
@@ -243,7 +460,6 @@ impl Database {
             .await
     }
 
-    #[allow(dead_code)]
     pub async fn get_user_by_user_id(&self, user_id: Uuid) -> Result<User, Error> {
         sqlx::query_as::<_, User>("SELECT * FROM users WHERE user_id = $1")
             .bind(user_id)
@@ -280,9 +496,9 @@ impl Database {
         sqlx::query_as::<_, RoomInfo>(
             r#"
             INSERT INTO room_infos (
-                room_id, owner_id, visitor_id, status, round, winner, board, countdown, moves, black_lost, white_lost, model, chessman_records, phase, komi, time_control, is_public, is_listed, allow_spectate
+                room_id, owner_id, visitor_id, status, round, winner, board, countdown, moves, black_lost, white_lost, model, chessman_records, phase, komi, time_control, is_public, is_listed, allow_spectate, handicap, ai_level, rated, rules, black_id, white_id
             ) VALUES (
-                $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19
+                $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24, $25
             ) RETURNING *
             "#,
         )
@@ -300,11 +516,17 @@ impl Database {
         .bind(room_info.model)
         .bind(&room_info.chessman_records)
         .bind(&room_info.phase)
-        .bind(room_info.komi as f64)
+        .bind(room_info.komi)
         .bind(&room_info.time_control)
         .bind(room_info.is_public)
         .bind(room_info.is_listed)
         .bind(room_info.allow_spectate)
+        .bind(room_info.handicap)
+        .bind(&room_info.ai_level)
+        .bind(room_info.rated)
+        .bind(&room_info.rules)
+        .bind(room_info.black_id)
+        .bind(room_info.white_id)
         .fetch_one(&self.pool)
         .await
     }
@@ -399,8 +621,9 @@ impl Database {
                 phase = $12,
                 last_activity_at = NOW(),
                 komi = $13,
-                time_control = $14
-            WHERE id = $15 RETURNING *
+                time_control = $14,
+                result = $15
+            WHERE id = $16 RETURNING *
             "#,
         )
         .bind(room_info.visitor_id)       // $1
@@ -415,38 +638,64 @@ impl Database {
         .bind(room_info.model)            // $10
         .bind(&room_info.chessman_records)// $11
         .bind(&room_info.phase)           // $12
-        .bind(room_info.komi as f64)      // $13
+        .bind(room_info.komi)             // $13
         .bind(&room_info.time_control)    // $14
-        .bind(room_info.id)               // $15
+        .bind(&room_info.result)          // $15
+        .bind(room_info.id)               // $16
         .fetch_one(&self.pool)
         .await
     }
 
-    // 简化版：仅在访客加入时更新 visitor_id 与 status，避免其它字段绑定差异导致失败
-    pub async fn update_room_visitor_simple(&self, id: i32, visitor_id: Option<Uuid>, status: &str) -> Result<RoomInfo, Error> {
+    // 虚手不改棋盘与手数，只交换行棋方并刷新 last_activity_at，超时判定据此计时
+    pub async fn record_pass(&self, id: i32, round: &str) -> Result<(), Error> {
+        sqlx::query("UPDATE room_infos SET round = $1, last_activity_at = NOW() WHERE id = $2")
+            .bind(round)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    // 简化版：仅在访客加入时更新 visitor_id、双方颜色与 status，避免其它字段绑定差异导致失败
+    // （颜色只在建房与入座时写入，update_room 不覆盖）
+    pub async fn update_room_visitor_simple(&self, id: i32, visitor_id: Option<Uuid>, players: Players, status: &str) -> Result<RoomInfo, Error> {
         sqlx::query_as::<_, RoomInfo>(
             r#"
             UPDATE room_infos SET
                 visitor_id = $1,
-                status = $2,
+                black_id = $2,
+                white_id = $3,
+                status = $4,
                 last_activity_at = NOW()
-            WHERE id = $3 RETURNING *
+            WHERE id = $5 RETURNING *
             "#,
         )
         .bind(visitor_id)
+        .bind(players.black_id)
+        .bind(players.white_id)
         .bind(status)
         .bind(id)
         .fetch_one(&self.pool)
         .await
     }
 
+    // 追加一条协商记录（只追加，不随 update_room 覆盖）
+    pub async fn append_room_event(&self, room_id: Uuid, event: &serde_json::Value) -> Result<(), Error> {
+        sqlx::query("UPDATE room_infos SET events = events || jsonb_build_array($1::jsonb) WHERE room_id = $2")
+            .bind(event)
+            .bind(room_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
     // Mark rooms as finished if no activity for more than 24 hours
     pub async fn finish_expired_rooms_24h(&self) -> Result<i64, Error> {
         let rows = sqlx::query(
             r#"
             UPDATE room_infos
             SET status = 'finished'
-            WHERE status <> 'finished'
+            WHERE status NOT IN ('finished', 'aborted')
               AND COALESCE(last_activity_at, created_at) < NOW() - INTERVAL '24 hours'
             "#,
         )
@@ -463,10 +712,13 @@ impl Database {
                    ou.username AS owner_username,
                    r.visitor_id,
                    vu.username AS visitor_username,
+                   r.black_id,
+                   r.white_id,
                    r.status,
                    r.model,
                    r.moves,
                    r.winner,
+                   r.result,
                    r.created_at,
                    r.last_activity_at
             FROM room_infos r
@@ -538,6 +790,15 @@ impl Database {
         .await
     }
 
+    pub async fn list_user_rankings(&self, user_id: &Uuid) -> Result<Vec<UserRanking>, Error> {
+        sqlx::query_as::<_, UserRanking>(
+            "SELECT * FROM user_rankings WHERE user_id = $1 ORDER BY model"
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
     pub async fn update_user_ranking(&self, ranking: &UserRanking) -> Result<UserRanking, Error> {
         sqlx::query_as::<_, UserRanking>(
             r#"
@@ -559,6 +820,18 @@ impl Database {
         .await
     }
 
+    // 排行榜名次：同尺寸下评分更高的已参赛玩家数 + 1
+    pub async fn get_user_rank(&self, model: i32, rating: f64) -> Result<i64, Error> {
+        let row = sqlx::query(
+            "SELECT COUNT(*) AS higher FROM user_rankings WHERE model = $1 AND games_played > 0 AND rating > $2"
+        )
+        .bind(model)
+        .bind(rating)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(row.get::<i64, _>("higher") + 1)
+    }
+
     pub async fn get_leaderboard(&self, model: i32, limit: i32) -> Result<Vec<LeaderboardEntry>, Error> {
         let rows = sqlx::query(
             r#"
@@ -598,23 +871,142 @@ impl Database {
 
         Ok(leaderboard)
     }
+
+    pub async fn get_game_review(&self, room_id: Uuid) -> Result<Option<GameReview>, Error> {
+        sqlx::query_as::<_, GameReview>("SELECT * FROM game_reviews WHERE room_id = $1")
+            .bind(room_id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    // 新建或更新复盘任务状态（queued / running / failed），不改动已有结果
+    pub async fn upsert_game_review_status(&self, room_id: Uuid, status: &str, error: Option<&str>) -> Result<(), Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO game_reviews (room_id, status, error) VALUES ($1, $2, $3)
+            ON CONFLICT (room_id) DO UPDATE SET status = $2, error = $3, updated_at = NOW()
+            "#,
+        )
+        .bind(room_id)
+        .bind(status)
+        .bind(error)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn save_game_review(&self, room_id: Uuid, review: &serde_json::Value) -> Result<(), Error> {
+        sqlx::query("UPDATE game_reviews SET status = 'done', review = $2, error = NULL, updated_at = NOW() WHERE room_id = $1")
+            .bind(room_id)
+            .bind(review)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn insert_chat_message(&self, room_id: Uuid, user_id: Uuid, channel: &str, message: &str, original: Option<&str>) -> Result<ChatMessage, Error> {
+        sqlx::query_as::<_, ChatMessage>(
+            "INSERT INTO room_chat (room_id, user_id, channel, message, original) VALUES ($1, $2, $3, $4, $5) RETURNING *",
+        )
+        .bind(room_id)
+        .bind(user_id)
+        .bind(channel)
+        .bind(message)
+        .bind(original)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    // 某频道最近的聊天，按时间正序
+    pub async fn list_room_chat(&self, room_id: Uuid, channel: &str, limit: i64) -> Result<Vec<ChatMessage>, Error> {
+        let mut messages = sqlx::query_as::<_, ChatMessage>(
+            "SELECT * FROM room_chat WHERE room_id = $1 AND channel = $2 ORDER BY id DESC LIMIT $3",
+        )
+        .bind(room_id)
+        .bind(channel)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        messages.reverse();
+        Ok(messages)
+    }
+
+    pub async fn get_chat_message(&self, id: i64) -> Result<Option<ChatMessage>, Error> {
+        sqlx::query_as::<_, ChatMessage>("SELECT * FROM room_chat WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    // 举报聊天消息；同一用户重复举报同一条时返回 false
+    pub async fn report_chat_message(&self, chat_id: i64, reporter_id: Uuid, reason: Option<&str>) -> Result<bool, Error> {
+        let rows = sqlx::query(
+            "INSERT INTO chat_reports (chat_id, reporter_id, reason) VALUES ($1, $2, $3) ON CONFLICT (chat_id, reporter_id) DO NOTHING",
+        )
+        .bind(chat_id)
+        .bind(reporter_id)
+        .bind(reason)
+        .execute(&self.pool)
+        .await?;
+        Ok(rows.rows_affected() > 0)
+    }
+
+    // Rows older than max_age_secs count as missing even before the next prune
+    pub async fn get_analysis_cache(&self, key: &str, max_age_secs: i64) -> Result<Option<serde_json::Value>, Error> {
+        let row: Option<(serde_json::Value,)> = sqlx::query_as(
+            "SELECT entries FROM analysis_cache WHERE cache_key = $1 AND created_at >= NOW() - make_interval(secs => $2)"
+        )
+        .bind(key)
+        .bind(max_age_secs as f64)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|(entries,)| entries))
+    }
+
+    pub async fn put_analysis_cache(&self, key: &str, entries: &serde_json::Value) -> Result<(), Error> {
+        // An expired row with the same key is refreshed rather than kept
+        sqlx::query(
+            r#"
+            INSERT INTO analysis_cache (cache_key, entries) VALUES ($1, $2)
+            ON CONFLICT (cache_key) DO UPDATE SET entries = EXCLUDED.entries, created_at = NOW()
+            "#,
+        )
+        .bind(key)
+        .bind(entries)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// 删除过期的分析缓存，并只保留最新的 max_rows 行；返回删除的行数
+    pub async fn prune_analysis_cache(&self, max_age_secs: i64, max_rows: i64) -> Result<u64, Error> {
+        let expired = sqlx::query("DELETE FROM analysis_cache WHERE created_at < NOW() - make_interval(secs => $1)")
+            .bind(max_age_secs as f64)
+            .execute(&self.pool)
+            .await?;
+        let overflow = sqlx::query(
+            r#"
+            DELETE FROM analysis_cache WHERE cache_key IN (
+                SELECT cache_key FROM analysis_cache ORDER BY created_at DESC OFFSET $1
+            )
+            "#,
+        )
+        .bind(max_rows)
+        .execute(&self.pool)
+        .await?;
+        Ok(expired.rows_affected() + overflow.rows_affected())
+    }
 }
 
 // Helper functions for password hashing
 fn hash_password(password: &str) -> Result<String, Error> {
     hash(password, DEFAULT_COST).map_err(|e| {
-        Error::Io(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            e.to_string(),
-        ))
+        Error::Io(std::io::Error::other(e.to_string()))
     })
 }
 
 fn verify_password(password: &str, hash: &str) -> Result<bool, Error> {
     verify(password, hash).map_err(|e| {
-        Error::Io(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            e.to_string(),
-        ))
+        Error::Io(std::io::Error::other(e.to_string()))
     })
 }
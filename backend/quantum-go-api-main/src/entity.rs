@@ -12,6 +12,14 @@ pub type WsSender = Arc<Mutex<futures::stream::SplitSink<WebSocket, Message>>>;
 pub struct Room {
     pub user1: Option<WsSender>,
     pub user2: Option<WsSender>,
+    // Pass tracking and the dead-stone agreement of the scoring phase
+    pub scoring: crate::scoring::RoomScoring,
+    // Spectator connections (user id, sender); they only take part in the spectators' chat
+    pub spectators: Vec<(Uuid, WsSender)>,
+    // Chat rate limiting
+    pub chat: crate::chat::RoomChat,
+    // Pending draw / takeback / abort request
+    pub offers: crate::negotiation::RoomOffers,
 }
 
 #[derive(Clone, Deserialize, Serialize, FromRow, Debug)]
@@ -21,6 +29,9 @@ pub struct RoomInfo {
     pub room_id: Uuid,
     pub owner_id: Uuid,
     pub visitor_id: Option<Uuid>,
+    // Players by colour (see seating); both None until a nigiri room is joined
+    pub black_id: Option<Uuid>,
+    pub white_id: Option<Uuid>,
     pub status: String,
     pub round: String,
     pub winner: Option<String>,
@@ -33,17 +44,56 @@ pub struct RoomInfo {
     pub chessman_records: serde_json::Value,
     pub phase: Option<String>,
     pub komi: f64,
+    // Number of handicap stones (0 = even game)
+    pub handicap: i32,
     // Govariants-style time control configuration (JSON), optional
     pub time_control: Option<serde_json::Value>,
     // Lobby visibility (Phase 1)
     pub is_public: bool,
     pub is_listed: bool,
     pub allow_spectate: bool,
+    // AI room: difficulty level of the bot in the visitor seat (None = PvP)
+    pub ai_level: Option<String>,
+    // Whether the result counts towards ratings
+    pub rated: bool,
+    // Ruleset id (see rules::Ruleset), used for engine requests and server-side scoring
+    pub rules: String,
+    // Validated result (winner colour and seat, reason, margin); None while the game is on
+    pub result: Option<sqlx::types::Json<crate::outcome::GameOutcome>>,
+    // Negotiation log (draw / takeback / abort offers and replies), see negotiation::LogEntry
+    pub events: serde_json::Value,
     pub created_at: chrono::DateTime<chrono::Utc>,
     // Last activity timestamp (room creation, join, or latest move)
     pub last_activity_at: chrono::DateTime<chrono::Utc>,
 }
 
+// 房间聊天（channel 为 players / spectators，见 chat::Channel）
+#[derive(Clone, Deserialize, Serialize, FromRow, Debug)]
+pub struct ChatMessage {
+    pub id: i64,
+    pub room_id: Uuid,
+    pub user_id: Uuid,
+    pub channel: String,
+    // Text as delivered (after the word filter)
+    pub message: String,
+    // Text as typed when the filter changed it; kept for moderators only
+    #[serde(skip_serializing)]
+    #[allow(dead_code)]
+    pub original: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+// 赛后复盘任务与结果（review 为 review::ReviewDocument 的 JSON）
+#[derive(Clone, Deserialize, Serialize, FromRow, Debug)]
+pub struct GameReview {
+    pub room_id: Uuid,
+    pub status: String,
+    pub review: Option<serde_json::Value>,
+    pub error: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
 // Lightweight lobby summary with host username
 #[derive(Clone, Deserialize, Serialize, FromRow, Debug)]
 pub struct RoomSummary {
@@ -63,10 +113,13 @@ pub struct RecentRoomSummary {
     pub owner_username: String,
     pub visitor_id: Option<Uuid>,
     pub visitor_username: Option<String>,
+    pub black_id: Option<Uuid>,
+    pub white_id: Option<Uuid>,
     pub status: String,
     pub model: i32,
     pub moves: i32,
     pub winner: Option<String>,
+    pub result: Option<sqlx::types::Json<crate::outcome::GameOutcome>>,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub last_activity_at: chrono::DateTime<chrono::Utc>,
 }
@@ -100,6 +153,44 @@ pub struct UserRanking {
     pub updated_at: chrono::DateTime<chrono::Utc>,
 }
 
+// 跨棋盘尺寸的综合评分（由各尺寸评分按 RD 加权聚合，不落库）
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct OverallRating {
+    pub rating: f64,
+    pub rd: f64,
+    pub vol: f64,
+    pub games_played: i32,
+    pub wins: i32,
+    pub losses: i32,
+    pub draws: i32,
+}
+
+// 个人资料中的单尺寸评分条目
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct SizeRatingEntry {
+    pub model: i32,
+    pub rating: f64,
+    pub rd: f64,
+    pub games_played: i32,
+    pub wins: i32,
+    pub losses: i32,
+    pub draws: i32,
+}
+
+impl From<&UserRanking> for SizeRatingEntry {
+    fn from(r: &UserRanking) -> Self {
+        Self {
+            model: r.model,
+            rating: r.rating,
+            rd: r.rd,
+            games_played: r.games_played,
+            wins: r.wins,
+            losses: r.losses,
+            draws: r.draws,
+        }
+    }
+}
+
 // 新增：排行榜条目
 #[derive(Clone, Deserialize, Serialize)]
 pub struct LeaderboardEntry {
@@ -119,6 +210,24 @@ pub struct GameResult {
     pub black_score: i32,
     pub white_score: i32,
     pub model: i32,
+    pub handicap: i32,
+    pub komi: f64,
+}
+
+// 单个玩家在一局结束后的评分变化
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct PlayerRatingChange {
+    pub user_id: Uuid,
+    pub old_rating: f64,
+    pub new_rating: f64,
+    pub delta: f64,
+    pub new_rank: i64, // 该尺寸排行榜上的名次（从 1 开始）
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct RatingUpdate {
+    pub black: PlayerRatingChange,
+    pub white: PlayerRatingChange,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -0,0 +1,189 @@
+// score-estimator 回归测试：katago/score-estimator-master/test_games 下标注好的终局局面
+//
+// .game 格式（与 C++ 的 main.cc 相同）：'#' 注释行、height / width / player_to_move（1 黑，-1 白），
+// 然后是 height 行盘面（1 黑，-1 白，0 空）与同尺寸的提子标注（1 = 该子应判死，或该空点是单官）。
+// 判定规则沿用 main.cc 的 check_stone_removal：标注的子归属应为对方、单官应为 0、未标注的子归属不变；
+// 死子列表须包含所有标注的子；getDead 把归属不明的子也列为死子（归属图则保留它们），
+// 所以多出的死子只计入报告。各类别的通过率低于下限时测试失败。
+// 测试走 score_estimator::estimate_board_score，启用 rust-estimator feature 时即检验 Rust 实现。
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use crate::score_estimator::estimate_board_score;
+
+const TRIALS: i32 = 1000;
+const TOLERANCE: f32 = 0.4;
+
+#[derive(Debug)]
+struct TestGame {
+    size: usize,
+    player_to_move: i32,
+    board: Vec<i32>,   // y * size + x
+    removal: Vec<i32>, // y * size + x
+}
+
+fn parse_game(text: &str) -> Result<TestGame, String> {
+    let mut lines = text.lines();
+    if !lines.next().is_some_and(|l| l.starts_with('#')) {
+        return Err("missing header comment".to_string());
+    }
+    let mut header = |name: &str| -> Result<i32, String> {
+        let line = lines.next().ok_or(format!("missing {}", name))?;
+        line.strip_prefix(name)
+            .and_then(|v| v.trim().parse().ok())
+            .ok_or(format!("bad {} line: {}", name, line))
+    };
+    let (height, width, player_to_move) = (header("height")?, header("width")?, header("player_to_move")?);
+    if height != width || !(1..=25).contains(&height) {
+        return Err(format!("unsupported board {}x{}", width, height));
+    }
+    if player_to_move != 1 && player_to_move != -1 {
+        return Err(format!("bad player_to_move {}", player_to_move));
+    }
+    let size = height as usize;
+    let values = lines
+        .flat_map(str::split_whitespace)
+        .map(|v| v.parse::<i32>().map_err(|_| format!("bad cell {}", v)))
+        .collect::<Result<Vec<_>, _>>()?;
+    if values.len() != 2 * size * size {
+        return Err(format!("expected {} cells, found {}", 2 * size * size, values.len()));
+    }
+    let (board, removal) = values.split_at(size * size);
+    Ok(TestGame { size, player_to_move, board: board.to_vec(), removal: removal.to_vec() })
+}
+
+/// 错误点数（check_stone_removal）、漏判的死子数与多判的死子数
+fn check(game: &TestGame, ownership: &[f32], dead: &[(i32, i32)]) -> (usize, usize, usize) {
+    let errors = (0..game.size * game.size)
+        .filter(|&p| {
+            let (stone, est) = (game.board[p], ownership[p] as i32);
+            match (game.removal[p] != 0, stone) {
+                (true, 0) => est != 0,
+                (true, _) => est != -stone,
+                (false, 0) => false,
+                (false, _) => est != stone,
+            }
+        })
+        .count();
+    let expected: BTreeSet<(i32, i32)> = (0..game.size * game.size)
+        .filter(|&p| game.removal[p] != 0 && game.board[p] != 0)
+        .map(|p| ((p % game.size) as i32, (p / game.size) as i32))
+        .collect();
+    let found: BTreeSet<(i32, i32)> = dead.iter().copied().collect();
+    (errors, expected.difference(&found).count(), found.difference(&expected).count())
+}
+
+fn games_in(category: &str) -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("katago/score-estimator-master/test_games").join(category);
+    let mut files: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("{}: {}", dir.display(), e))
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "game"))
+        .collect();
+    files.sort();
+    files
+}
+
+// Runs every game of a category; prints a report and fails below either floor
+fn run_category(category: &str, min_pass_rate: f32, min_point_accuracy: f32) {
+    let files = games_in(category);
+    assert!(!files.is_empty(), "no games in {}", category);
+    let (mut passed, mut points, mut wrong_points, mut extra_dead) = (0, 0, 0, 0);
+    let mut failures = Vec::new();
+    for path in &files {
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        let game = parse_game(&std::fs::read_to_string(path).unwrap()).unwrap_or_else(|e| panic!("{}: {}", name, e));
+        let stones = |color: i32| {
+            (0..game.size * game.size)
+                .filter(|&p| game.board[p] == color)
+                .map(|p| format!("{},{}", p % game.size + 1, p / game.size + 1))
+                .collect::<Vec<_>>()
+        };
+        let next = if game.player_to_move == 1 { "black" } else { "white" };
+        let (ownership, _, dead) =
+            estimate_board_score(game.size as u8, &stones(1), &stones(-1), Some(next), TRIALS, TOLERANCE).unwrap();
+        let (errors, missed, extra) = check(&game, &ownership, &dead);
+        points += game.size * game.size;
+        wrong_points += errors;
+        extra_dead += extra;
+        if errors == 0 && missed == 0 {
+            passed += 1;
+        } else {
+            failures.push(format!("{}: {} wrong points, {} dead stones missed", name, errors, missed));
+        }
+    }
+    let pass_rate = passed as f32 / files.len() as f32;
+    let point_accuracy = (points - wrong_points) as f32 / points as f32;
+    println!(
+        "score estimator [{}]: {}/{} games passed ({:.0}%), point accuracy {:.2}%, {} uncertain stones listed dead",
+        category,
+        passed,
+        files.len(),
+        pass_rate * 100.0,
+        point_accuracy * 100.0,
+        extra_dead
+    );
+    for failure in &failures {
+        println!("  failed: {}", failure);
+    }
+    assert!(
+        pass_rate >= min_pass_rate,
+        "[{}] pass rate {:.0}% below {:.0}%",
+        category,
+        pass_rate * 100.0,
+        min_pass_rate * 100.0
+    );
+    assert!(
+        point_accuracy >= min_point_accuracy,
+        "[{}] point accuracy {:.2}% below {:.2}%",
+        category,
+        point_accuracy * 100.0,
+        min_point_accuracy * 100.0
+    );
+}
+
+#[test]
+fn test_parse_game_file() {
+    let text = "# 1=black -1=white 0=open\nheight 2\nwidth 2\nplayer_to_move -1\n 1 0\n-1 0\n\n 0 0\n 1 0\n";
+    let game = parse_game(text).unwrap();
+    assert_eq!((game.size, game.player_to_move), (2, -1));
+    assert_eq!(game.board, vec![1, 0, -1, 0]);
+    assert_eq!(game.removal, vec![0, 0, 1, 0]);
+    // White stone at (0,1) removed, black kept, dame left open
+    assert_eq!(check(&game, &[1.0, 1.0, 1.0, 0.0], &[(0, 1)]), (0, 0, 0));
+    assert_eq!(check(&game, &[1.0, 1.0, -1.0, 0.0], &[(0, 0)]), (1, 1, 1));
+    assert!(parse_game("height 2\n").is_err());
+    assert!(parse_game("#\nheight 2\nwidth 3\nplayer_to_move 1\n").is_err());
+}
+
+// Floors sit a little under the current C++ results (Monte Carlo noise); the point
+// accuracy floor is what still guards the categories where whole games rarely pass
+#[test]
+fn test_easy_games() {
+    run_category("easy", 0.9, 0.999);
+}
+
+#[test]
+fn test_mid_games() {
+    run_category("mid", 0.8, 0.995);
+}
+
+#[test]
+fn test_hard_games() {
+    run_category("hard", 0.65, 0.99);
+}
+
+#[test]
+fn test_really_hard_games() {
+    run_category("really_hard", 0.0, 0.95);
+}
+
+#[test]
+fn test_pattern_games() {
+    run_category("patterns", 0.85, 0.995);
+}
+
+#[test]
+fn test_no_removal_games() {
+    run_category("no_removals", 1.0, 1.0);
+}
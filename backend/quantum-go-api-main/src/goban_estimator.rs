@@ -0,0 +1,606 @@
+// score-estimator（Goban.cc）的纯 Rust 移植：同样的蒙特卡洛随机对局、归属与死子判定
+//
+// 流程与 C++ 版一致：填假眼 → 无偏置模拟找双活 → 计算实地与“强活”区域（模拟时不在其中落子）→
+// 马蹄形偏置 → 第二轮模拟统计每点归属 → 判死子、按 tolerance 定归属、未定的空白区域按邻接着色。
+// 坐标与 C++ 相同：(x, y) 从 0 开始，数组下标为 y * width + x；黑 = 1，白 = -1。
+// 随机数与遍历顺序不同，所以结果只在统计意义上与 C++ 版一致。
+//
+// 启用 `rust-estimator` feature 时 score_estimator 使用本实现且不再编译 C++；
+// 未启用时只在测试中编译，用来与 C++ 版对比。
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::score_estimator::BoardEstimate;
+
+const BLACK: i32 = 1;
+const WHITE: i32 = -1;
+const EMPTY: i32 = 0;
+
+// Tolerance of the seki scan, fixed in Goban::_estimate
+const SEKI_TOLERANCE: f32 = 0.2;
+// Moves per random playout before giving up
+const PLAYOUT_SANITY: i32 = 1000;
+
+// Per-point inputs of a rollout: strongly alive and seki points are never played,
+// bias is the starting count
+struct RolloutMaps<'a> {
+    life_map: &'a [i32],
+    bias: &'a [i32],
+    seki: &'a [i32],
+}
+
+#[derive(Clone)]
+struct Position {
+    width: usize,
+    height: usize,
+    board: Vec<i32>,
+    // Point that may not be played next (a single stone was just captured there)
+    ko: Option<usize>,
+}
+
+pub struct Goban {
+    position: Position,
+    rng: StdRng,
+}
+
+impl Goban {
+    pub fn new(width: i32, height: i32) -> Self {
+        Self::with_seed(width, height, rand::random())
+    }
+
+    pub fn with_seed(width: i32, height: i32, seed: u64) -> Self {
+        let (width, height) = (width.max(1) as usize, height.max(1) as usize);
+        Self {
+            position: Position { width, height, board: vec![EMPTY; width * height], ko: None },
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// 与 C 接口相同：坐标从 0 开始，color 1 = 黑、2 = 白、其它 = 清空；越界的点忽略
+    pub fn set_stone(&mut self, x: i32, y: i32, color: i32) {
+        let p = &mut self.position;
+        if x < 0 || y < 0 || x as usize >= p.width || y as usize >= p.height {
+            return;
+        }
+        p.board[y as usize * p.width + x as usize] = match color {
+            1 => BLACK,
+            2 | -1 => WHITE,
+            _ => EMPTY,
+        };
+    }
+
+    /// 一次模拟得到归属（-1/0/1）、地域与死子坐标
+    pub fn estimate(&mut self, player_to_move: i32, trials: i32, tolerance: f32) -> BoardEstimate {
+        let player = if matches!(player_to_move, 2 | -1) { WHITE } else { BLACK };
+        let (ownership, dead) = self.position.estimate(&mut self.rng, player, trials.max(1), tolerance);
+        let territory = self.position.compute_territory();
+        let width = self.position.width;
+        (
+            ownership.into_iter().map(|v| v as f32).collect(),
+            territory.into_iter().map(|v| v as f32).collect(),
+            dead.into_iter().map(|p| ((p % width) as i32, (p / width) as i32)).collect(),
+        )
+    }
+}
+
+impl Position {
+    fn len(&self) -> usize {
+        self.width * self.height
+    }
+
+    // Left, right, up, down: the order of TGrid::getNeighbors
+    fn neighbors(&self, p: usize) -> impl Iterator<Item = usize> {
+        let (x, y, w, h) = (p % self.width, p / self.width, self.width, self.height);
+        let mut out = [usize::MAX; 4];
+        if x > 0 { out[0] = p - 1; }
+        if x + 1 < w { out[1] = p + 1; }
+        if y > 0 { out[2] = p - w; }
+        if y + 1 < h { out[3] = p + w; }
+        out.into_iter().filter(|&q| q != usize::MAX)
+    }
+
+    fn corners(&self, p: usize) -> impl Iterator<Item = usize> {
+        let (x, y, w, h) = (p % self.width, p / self.width, self.width, self.height);
+        let mut out = [usize::MAX; 4];
+        if x > 0 && y > 0 { out[0] = p - w - 1; }
+        if x + 1 < w && y > 0 { out[1] = p - w + 1; }
+        if x > 0 && y + 1 < h { out[2] = p + w - 1; }
+        if x + 1 < w && y + 1 < h { out[3] = p + w + 1; }
+        out.into_iter().filter(|&q| q != usize::MAX)
+    }
+
+    // Flood fill of equal values in `grid` from `start`; returns the region (start first)
+    // and the distinct bordering points
+    fn group_and_neighbors(&self, grid: &[i32], start: usize) -> (Vec<usize>, Vec<usize>) {
+        let value = grid[start];
+        let mut visited = vec![false; self.len()];
+        let (mut group, mut border) = (Vec::new(), Vec::new());
+        let mut stack = vec![start];
+        visited[start] = true;
+        while let Some(p) = stack.pop() {
+            if grid[p] != value {
+                border.push(p);
+                continue;
+            }
+            group.push(p);
+            for q in self.neighbors(p) {
+                if !visited[q] {
+                    visited[q] = true;
+                    stack.push(q);
+                }
+            }
+        }
+        (group, border)
+    }
+
+    fn count(&self, points: &[usize], value: i32) -> usize {
+        points.iter().filter(|&&p| self.board[p] == value).count()
+    }
+
+    fn min_liberties_of_surrounding_groups(&self, p: usize) -> usize {
+        self.neighbors(p)
+            .map(|q| {
+                let (_, border) = self.group_and_neighbors(&self.board, q);
+                self.count(&border, EMPTY)
+            })
+            .min()
+            .unwrap_or(usize::MAX)
+    }
+
+    // Enough enemy stones on the diagonals and a weak surrounding group: looks like a false eye
+    fn looks_false(&self, p: usize, player: i32) -> bool {
+        let corners: Vec<usize> = self.corners(p).collect();
+        self.count(&corners, -player) >= corners.len() / 2 && self.min_liberties_of_surrounding_groups(p) <= 1
+    }
+
+    fn is_eye(&self, p: usize, player: i32) -> bool {
+        self.neighbors(p).all(|q| self.board[q] == player) && !self.looks_false(p, player)
+    }
+
+    // U shape of `player` stones with an open side but no enemy neighbour
+    fn is_safe_horseshoe(&self, p: usize, player: i32) -> bool {
+        let neighbors: Vec<usize> = self.neighbors(p).collect();
+        self.count(&neighbors, player) + 1 >= neighbors.len()
+            && self.count(&neighbors, -player) == 0
+            && !self.looks_false(p, player)
+    }
+
+    fn false_eyes(&self) -> Vec<usize> {
+        (0..self.len())
+            .filter(|&p| self.board[p] == EMPTY && self.min_liberties_of_surrounding_groups(p) <= 1)
+            .filter(|&p| {
+                let neighbors: Vec<usize> = self.neighbors(p).collect();
+                let corners: Vec<usize> = self.corners(p).collect();
+                [BLACK, WHITE].into_iter().any(|color| {
+                    self.count(&neighbors, color) == neighbors.len() && self.count(&corners, -color) >= corners.len() / 2
+                })
+            })
+            .collect()
+    }
+
+    fn fill_false_eyes(&mut self) {
+        let mut ignored = Vec::new();
+        for p in self.false_eyes() {
+            let Some(color) = self.neighbors(p).next().map(|q| self.board[q]) else { continue };
+            if color != EMPTY {
+                self.place_and_remove(p, color, &mut ignored);
+            }
+        }
+    }
+
+    // Empty region reachable from `p` touches only `player` stones (and at least one)
+    fn is_territory(&self, p: usize, player: i32) -> bool {
+        let mut visited = vec![false; self.len()];
+        let mut stack = vec![p];
+        let mut adjacent = 0;
+        visited[p] = true;
+        while let Some(q) = stack.pop() {
+            match self.board[q] {
+                EMPTY => {
+                    for r in self.neighbors(q) {
+                        if !visited[r] {
+                            visited[r] = true;
+                            stack.push(r);
+                        }
+                    }
+                }
+                color if color != player => return false,
+                _ => adjacent += 1,
+            }
+        }
+        adjacent > 0
+    }
+
+    fn fill_territory(&mut self, p: usize, player: i32) {
+        let mut stack = vec![p];
+        while let Some(q) = stack.pop() {
+            if self.board[q] == EMPTY {
+                self.board[q] = player;
+                stack.extend(self.neighbors(q));
+            }
+        }
+    }
+
+    fn has_liberties(&self, p: usize) -> bool {
+        let color = self.board[p];
+        let mut visited = vec![false; self.len()];
+        let mut stack = vec![p];
+        visited[p] = true;
+        while let Some(q) = stack.pop() {
+            for r in self.neighbors(q) {
+                if self.board[r] == EMPTY {
+                    return true;
+                }
+                if self.board[r] == color && !visited[r] {
+                    visited[r] = true;
+                    stack.push(r);
+                }
+            }
+        }
+        false
+    }
+
+    // Removes the group at `p`; freed points become playable again
+    fn remove_group(&mut self, p: usize, possible_moves: &mut Vec<usize>) -> usize {
+        let color = self.board[p];
+        let mut stack = vec![p];
+        let mut removed = 0;
+        self.board[p] = EMPTY;
+        while let Some(q) = stack.pop() {
+            possible_moves.push(q);
+            removed += 1;
+            for r in self.neighbors(q) {
+                if self.board[r] == color {
+                    self.board[r] = EMPTY;
+                    stack.push(r);
+                }
+            }
+        }
+        removed
+    }
+
+    // Plays `mv` with captures; false (board unchanged) for ko or suicide
+    fn place_and_remove(&mut self, mv: usize, player: i32, possible_moves: &mut Vec<usize>) -> bool {
+        if self.ko == Some(mv) {
+            return false;
+        }
+        let mut ko = None;
+        let mut removed = false;
+        self.board[mv] = player;
+        let neighbors: Vec<usize> = self.neighbors(mv).collect();
+        for q in neighbors {
+            if self.board[q] == -player && !self.has_liberties(q) {
+                if self.remove_group(q, possible_moves) == 1 {
+                    ko = Some(q);
+                }
+                removed = true;
+            }
+        }
+        if !removed && !self.has_liberties(mv) {
+            self.board[mv] = EMPTY;
+            return false;
+        }
+        self.ko = ko;
+        true
+    }
+
+    // Random game until both sides run out of moves; never fills own eyes or plays in
+    // `life_map` / `seki` points
+    fn play_out_position(&mut self, rng: &mut StdRng, mut player: i32, life_map: &[i32], seki: &[i32]) {
+        self.ko = None;
+        let mut possible: Vec<usize> = (0..self.len())
+            .filter(|&p| self.board[p] == EMPTY && seki[p] == 0 && life_map[p] == 0)
+            .collect();
+        let mut illegal = Vec::new();
+        let mut sanity = PLAYOUT_SANITY;
+        let mut passed = false;
+
+        while !possible.is_empty() {
+            sanity -= 1;
+            if sanity <= 0 {
+                break;
+            }
+            let idx = rng.gen_range(0..possible.len());
+            let mv = possible[idx];
+            if !self.is_eye(mv, player) && self.place_and_remove(mv, player, &mut possible) {
+                passed = false;
+                possible.swap_remove(idx);
+                player = -player;
+                possible.append(&mut illegal);
+                continue;
+            }
+            illegal.push(possible.swap_remove(idx));
+            if possible.is_empty() {
+                if passed {
+                    break;
+                }
+                passed = true;
+                possible.append(&mut illegal);
+                player = -player;
+            }
+        }
+    }
+
+    // Sum over `iterations` playouts of the final owner of every point (+1 black, -1 white),
+    // starting from `bias`; stone groups then take their most extreme value
+    fn rollout(&self, rng: &mut StdRng, iterations: i32, player: i32, pullup_life: bool, maps: &RolloutMaps<'_>) -> Vec<i32> {
+        let mut ret = maps.bias.to_vec();
+        for _ in 0..iterations {
+            let mut t = self.clone();
+            t.play_out_position(rng, player, maps.life_map, maps.seki);
+            for p in 0..t.len() {
+                if t.board[p] == EMPTY {
+                    if t.is_territory(p, BLACK) {
+                        t.fill_territory(p, BLACK);
+                    }
+                    if t.is_territory(p, WHITE) {
+                        t.fill_territory(p, WHITE);
+                    }
+                }
+            }
+            for (total, owner) in ret.iter_mut().zip(&t.board) {
+                *total += owner;
+            }
+        }
+
+        let mut visited = vec![false; self.len()];
+        for p in 0..self.len() {
+            if visited[p] || self.board[p] == EMPTY {
+                continue;
+            }
+            let (group, border) = self.group_and_neighbors(&self.board, p);
+            let mut minmax = group.iter().map(|&q| ret[q]).fold(ret[p], |m, v| if v.abs() > m.abs() { v } else { m });
+            group.iter().for_each(|&q| visited[q] = true);
+            if pullup_life && !border.is_empty() {
+                // A group next to stronger territory is at least that alive
+                if minmax < 0 {
+                    minmax = minmax.min(border.iter().map(|&q| ret[q]).min().unwrap_or(minmax));
+                }
+                if minmax > 0 {
+                    minmax = minmax.max(border.iter().map(|&q| ret[q]).max().unwrap_or(minmax));
+                }
+            }
+            group.iter().for_each(|&q| ret[q] = minmax);
+        }
+        ret
+    }
+
+    // Groups of uncertain status next to equally uncertain enemy groups with the same
+    // number of liberties are treated as seki, together with their liberties
+    fn scan_for_seki(&self, iterations: i32, tolerance: f32, rollout_pass: &[i32]) -> Vec<i32> {
+        let limit = iterations as f32 * tolerance;
+        let limit_int = limit as i32;
+        let mut seki = vec![0; self.len()];
+        let mut visited = vec![false; self.len()];
+        for p in 0..self.len() {
+            if visited[p] {
+                continue;
+            }
+            let (group, border) = self.group_and_neighbors(&self.board, p);
+            group.iter().for_each(|&q| visited[q] = true);
+            let color = self.board[p];
+            if color == EMPTY || !group.iter().all(|&q| rollout_pass[q].abs() <= limit_int) {
+                continue;
+            }
+            let enemies: Vec<usize> = border.iter().copied().filter(|&q| self.board[q] == -color).collect();
+            let my_liberties = self.count(&border, EMPTY);
+            if !enemies.iter().any(|&q| rollout_pass[q].abs() <= limit_int) {
+                continue;
+            }
+            let in_seki = enemies
+                .iter()
+                .filter(|&&q| (rollout_pass[q].abs() as f32) < limit)
+                .all(|&q| self.count(&self.group_and_neighbors(&self.board, q).1, EMPTY) == my_liberties);
+            if in_seki {
+                group.iter().for_each(|&q| seki[q] = 1);
+                border.iter().filter(|&&q| self.board[q] == EMPTY).for_each(|&q| seki[q] = 1);
+            }
+        }
+        seki
+    }
+
+    /// 实地：只与一方相邻的空白区域，每点记为区域大小（黑正白负）
+    fn compute_territory(&self) -> Vec<i32> {
+        let mut ret = vec![0; self.len()];
+        for p in 0..self.len() {
+            if ret[p] != 0 || self.board[p] != EMPTY {
+                continue;
+            }
+            for color in [BLACK, WHITE] {
+                if self.is_territory(p, color) {
+                    let (region, _) = self.group_and_neighbors(&self.board, p);
+                    let size = region.len() as i32;
+                    region.into_iter().for_each(|q| ret[q] = size * color);
+                    break;
+                }
+            }
+        }
+        ret
+    }
+
+    // Stones and territory of one colour with two or more eyes, or at least five points of
+    // territory, count as alive; playouts leave them alone
+    fn compute_strong_life(&self, territory: &[i32]) -> Vec<i32> {
+        let unified: Vec<i32> = (0..self.len())
+            .map(|p| if self.board[p] == EMPTY { territory[p].signum() } else { self.board[p] })
+            .collect();
+        let mut ret = vec![0; self.len()];
+        let mut visited = vec![false; self.len()];
+        for p in 0..self.len() {
+            if visited[p] {
+                continue;
+            }
+            let (group, _) = self.group_and_neighbors(&unified, p);
+            let (mut eyes, mut points) = (0, 0);
+            for &q in &group {
+                if visited[q] || territory[q] == 0 {
+                    continue;
+                }
+                let (eye, _) = self.group_and_neighbors(&self.board, q);
+                eye.iter().for_each(|&r| visited[r] = true);
+                eyes += 1;
+                points += eye.len() as i32;
+            }
+            group.iter().for_each(|&q| visited[q] = true);
+            if eyes >= 2 || points >= 5 {
+                group.iter().for_each(|&q| ret[q] = points);
+            }
+        }
+        ret
+    }
+
+    // Stones on points the playouts give to the opponent, or to no one
+    fn get_dead(&self, iterations: i32, tolerance: f32, rollout_pass: &[i32]) -> Vec<usize> {
+        let limit = iterations as f32 * tolerance;
+        (0..self.len())
+            .filter(|&p| {
+                let v = rollout_pass[p] as f32;
+                match self.board[p] {
+                    EMPTY => false,
+                    WHITE if v > limit => true,
+                    BLACK if v < -limit => true,
+                    _ => v.abs() <= limit,
+                }
+            })
+            .collect()
+    }
+
+    // Goban::_estimate
+    fn estimate(&self, rng: &mut StdRng, player: i32, iterations: i32, tolerance: f32) -> (Vec<i32>, Vec<usize>) {
+        let mut t = self.clone();
+        t.fill_false_eyes();
+        let len = t.len();
+        let zeros = vec![0; len];
+
+        let unbiased = RolloutMaps { life_map: &zeros, bias: &zeros, seki: &zeros };
+        let seki_pass = t.rollout(rng, iterations, player, false, &unbiased);
+        let seki = t.scan_for_seki(iterations, SEKI_TOLERANCE, &seki_pass);
+
+        let mut bias = vec![0; len];
+        for p in 0..len {
+            if t.board[p] == EMPTY && (t.is_safe_horseshoe(p, BLACK) || t.is_safe_horseshoe(p, WHITE)) {
+                for q in t.neighbors(p) {
+                    t.group_and_neighbors(&t.board, q).0.into_iter().for_each(|r| bias[r] += 1);
+                }
+            }
+        }
+        let scale = (iterations as f32 * (tolerance / 4.0)) as i32;
+        for (b, stone) in bias.iter_mut().zip(&t.board) {
+            *b *= stone * scale;
+        }
+
+        let territory = t.compute_territory();
+        let strong_life = t.compute_strong_life(&territory);
+        let maps = RolloutMaps { life_map: &strong_life, bias: &bias, seki: &seki };
+        let pass1 = t.rollout(rng, iterations, player, true, &maps);
+        let dead = t.get_dead(iterations, tolerance, &pass1);
+
+        let limit = iterations as f32 * tolerance;
+        let mut ret: Vec<i32> = (0..len)
+            .map(|p| {
+                let v = pass1[p] as f32;
+                if v > limit {
+                    BLACK
+                } else if v < -limit {
+                    WHITE
+                } else if t.board[p] != EMPTY && v.abs() >= limit / 3.0 {
+                    pass1[p].signum()
+                } else {
+                    EMPTY
+                }
+            })
+            .collect();
+
+        // Undecided regions bordered by only one colour go to that colour
+        for p in 0..len {
+            if ret[p] != EMPTY {
+                continue;
+            }
+            let (region, border) = t.group_and_neighbors(&t.board, p);
+            let owner = if border.iter().all(|&q| ret[q] != WHITE) {
+                BLACK
+            } else if border.iter().all(|&q| ret[q] != BLACK) {
+                WHITE
+            } else {
+                continue;
+            };
+            region.into_iter().for_each(|q| ret[q] = owner);
+        }
+        (ret, dead)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 9x9: black wall on column 3, white wall on column 5, an invader on each side
+    fn walls(set: &mut dyn FnMut(i32, i32, i32)) {
+        for y in 0..9 {
+            set(3, y, 1);
+            set(5, y, 2);
+        }
+        set(1, 4, 2);
+        set(7, 4, 1);
+    }
+
+    #[test]
+    fn test_ko_and_suicide_are_rejected() {
+        let at = |x: usize, y: usize| y * 5 + x;
+        let mut moves = Vec::new();
+
+        let mut corner = Goban::with_seed(5, 5, 1).position;
+        corner.board[at(1, 0)] = BLACK;
+        corner.board[at(0, 1)] = BLACK;
+        assert!(!corner.place_and_remove(at(0, 0), WHITE, &mut moves), "suicide");
+        assert_eq!(corner.board[at(0, 0)], EMPTY);
+
+        let mut ko = Goban::with_seed(5, 5, 1).position;
+        for (x, y) in [(1, 0), (0, 1), (1, 2)] {
+            ko.board[at(x, y)] = BLACK;
+        }
+        for (x, y) in [(2, 0), (3, 1), (2, 2), (1, 1)] {
+            ko.board[at(x, y)] = WHITE;
+        }
+        assert!(ko.place_and_remove(at(2, 1), BLACK, &mut moves));
+        assert_eq!(ko.board[at(1, 1)], EMPTY);
+        assert_eq!(moves, vec![at(1, 1)]);
+        // White may not retake at once
+        assert!(!ko.place_and_remove(at(1, 1), WHITE, &mut moves));
+        assert_eq!(ko.board[at(2, 1)], BLACK);
+    }
+
+    #[test]
+    fn test_invaders_are_dead() {
+        let mut goban = Goban::with_seed(9, 9, 7);
+        walls(&mut |x, y, c| goban.set_stone(x, y, c));
+        let (ownership, territory, mut dead) = goban.estimate(1, 200, 0.4);
+        dead.sort();
+        assert_eq!(dead, vec![(1, 4), (7, 4)]);
+        assert_eq!(ownership[4 * 9 + 1], 1.0);
+        assert_eq!(ownership[4 * 9 + 7], -1.0);
+        assert!(ownership.iter().take(3).all(|&v| v == 1.0));
+        // Territory ignores dead stones: every empty area touches both colours
+        assert!(territory.iter().all(|&v| v == 0.0));
+    }
+
+    #[cfg(not(feature = "rust-estimator"))]
+    #[test]
+    fn test_agrees_with_cpp_estimator() {
+        use crate::score_estimator::ScoreEstimator;
+
+        let mut cpp = ScoreEstimator::new(9, 9);
+        walls(&mut |x, y, c| cpp.set_stone(x, y, c));
+        let mut port = Goban::new(9, 9);
+        walls(&mut |x, y, c| port.set_stone(x, y, c));
+
+        let (own_cpp, terr_cpp, mut dead_cpp) = cpp.estimate(2, 300, 0.4);
+        let (own_rs, terr_rs, mut dead_rs) = port.estimate(2, 300, 0.4);
+        dead_cpp.sort();
+        dead_rs.sort();
+        assert_eq!(dead_rs, dead_cpp);
+        assert_eq!(terr_rs, terr_cpp);
+        let agree = own_cpp.iter().zip(&own_rs).filter(|(a, b)| a == b).count();
+        assert!(agree >= 78, "ownership agrees on {} of 81 points", agree);
+    }
+}
@@ -0,0 +1,110 @@
+// 让子棋：固定置子位置与量子规则下的映射
+//
+// 量子围棋中让子按"经典子"处理：同一坐标同时出现在两个现实中，颜色都是黑，
+// brother 指向自身（position == brother），因此不会参与量子开局的纠缠配对。
+// 让子 ≥ 2 时由白方先行，随后的前两手（白、黑）照常构成量子子对。
+// 让 1 子表示不摆子、黑先、贴目默认 0.5。
+use crate::entity::Chessman;
+
+/// 让子局默认贴目
+pub const HANDICAP_KOMI: f64 = 0.5;
+
+/// 各尺寸允许的最大让子数
+pub fn max_handicap(model: i32) -> i32 {
+    match model {
+        7 => 4,
+        9 => 5,
+        13 | 19 => 9,
+        _ => 0,
+    }
+}
+
+/// 固定置子位置（"x,y"，1-based，x 为行、y 为列，与前端一致）
+///
+/// 摆放顺序与常规围棋相同：2 子对角（右上、左下），3 子加右下，4 子四角，
+/// 5 子加天元，6 子加左右边星，7 子再加天元，8 子四角加四边，9 子全部星位。
+pub fn handicap_points(model: i32, handicap: i32) -> Option<Vec<String>> {
+    if handicap < 2 || handicap > max_handicap(model) {
+        return None;
+    }
+    // 角星距边的行/列号
+    let edge = match model {
+        7 | 9 => 3,
+        13 | 19 => 4,
+        _ => return None,
+    };
+    let size = model;
+    let (near, far, mid) = (edge, size - edge + 1, (size + 1) / 2);
+
+    let upper_right = (near, far);
+    let lower_left = (far, near);
+    let lower_right = (far, far);
+    let upper_left = (near, near);
+    let center = (mid, mid);
+    let mid_left = (mid, near);
+    let mid_right = (mid, far);
+    let mid_top = (near, mid);
+    let mid_bottom = (far, mid);
+
+    let corners = [upper_right, lower_left, lower_right, upper_left];
+    let points: Vec<(i32, i32)> = match handicap {
+        2 => corners[..2].to_vec(),
+        3 => corners[..3].to_vec(),
+        4 => corners.to_vec(),
+        5 => [&corners[..], &[center]].concat(),
+        6 => [&corners[..], &[mid_left, mid_right]].concat(),
+        7 => [&corners[..], &[mid_left, mid_right, center]].concat(),
+        8 => [&corners[..], &[mid_left, mid_right, mid_top, mid_bottom]].concat(),
+        _ => [&corners[..], &[mid_left, mid_right, mid_top, mid_bottom, center]].concat(),
+    };
+    Some(points.into_iter().map(|(x, y)| format!("{},{}", x, y)).collect())
+}
+
+/// 初始棋盘（与前端 `[...board1]` 的序列化格式一致：[[pos, chessman], ...]）
+pub fn initial_board(model: i32, handicap: i32) -> serde_json::Value {
+    let stones = handicap_points(model, handicap).unwrap_or_default();
+    let entries = stones
+        .into_iter()
+        .map(|pos| {
+            let chessman = Chessman {
+                position: pos.clone(),
+                color: "black".to_string(),
+                brother: pos.clone(),
+            };
+            serde_json::json!([pos, chessman])
+        })
+        .collect::<Vec<_>>();
+    if entries.is_empty() {
+        serde_json::Value::Object(serde_json::Map::new())
+    } else {
+        serde_json::Value::Array(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handicap_points_per_size() {
+        assert_eq!(handicap_points(19, 2).unwrap(), vec!["4,16", "16,4"]);
+        assert_eq!(handicap_points(9, 5).unwrap(), vec!["3,7", "7,3", "7,7", "3,3", "5,5"]);
+        assert_eq!(handicap_points(13, 9).unwrap().len(), 9);
+        assert_eq!(handicap_points(7, 4).unwrap().len(), 4);
+        assert!(handicap_points(9, 6).is_none());
+        assert!(handicap_points(19, 1).is_none());
+    }
+
+    #[test]
+    fn test_initial_board_uses_self_brothers() {
+        let board = initial_board(9, 2);
+        let entries = board.as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        for entry in entries {
+            assert_eq!(entry[0], entry[1]["position"]);
+            assert_eq!(entry[1]["position"], entry[1]["brother"]);
+            assert_eq!(entry[1]["type"], "black");
+        }
+        assert!(initial_board(9, 0).as_object().unwrap().is_empty());
+    }
+}
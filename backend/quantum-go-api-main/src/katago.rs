@@ -1,14 +1,20 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
-    process::{Child, ChildStdin, ChildStdout, Command},
-    sync::Mutex,
+    process::{Child, ChildStderr, ChildStdin, ChildStdout, Command},
 };
-use std::sync::Arc;
-use once_cell::sync::Lazy;
-use crate::score_estimator;
+
+use crate::ai_level::{self, AiLevel};
+use crate::analysis_cache::{self, AnalysisScope};
+use crate::katago_analysis;
+use crate::katago_pool::{self, EnginePool, PooledEngine};
+use crate::quantum_estimate::{self, CombinedEstimate};
+use crate::rules::Ruleset;
+use crate::score_estimator::{self, EstimateParams};
 
 #[derive(Debug, Deserialize)]
 pub struct AiGenmoveRequest {
@@ -20,9 +26,11 @@ pub struct AiGenmoveRequest {
     // Optional list of moves ("x,y") that should be avoided under
     // Quantum dual-board + SSK legality computed on the frontend.
     pub forbidden: Option<Vec<String>>,
+    // Difficulty level id (see /ai/levels); defaults to full strength
+    pub level: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct MoveItem {
     pub color: String,   // "black" | "white"
     pub position: String // "x,y" (1-based)
@@ -46,6 +54,7 @@ pub struct AiDualGenmoveRequest {
     pub k: Option<usize>,               // optional top-K candidates to consider from board A
     // Optional metric selection: "winrate" (default) or "score_lead"
     pub metric: Option<String>,
+    pub level: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -56,6 +65,13 @@ pub struct AiDualGenmoveResponse {
 #[derive(Debug, Deserialize)]
 pub struct ScoreEstimateRequest {
     pub boards: Vec<ScoreEstimateBoardRequest>,
+    // "independent" (default: every board on its own) | "quantum" (boards A and B scored together)
+    pub mode: Option<String>,
+    pub komi: Option<f32>,       // quantum mode, default 7.5
+    // quantum mode: entangled pairs as [position on A, position on B]
+    pub brothers: Option<Vec<(String, String)>>,
+    pub trials: Option<i32>,     // default 1000, capped by SCORE_ESTIMATE_MAX_TRIALS
+    pub tolerance: Option<f32>,  // default 0.4, in (0, 1)
 }
 
 #[derive(Debug, Deserialize)]
@@ -69,6 +85,8 @@ pub struct ScoreEstimateBoardRequest {
 #[derive(Debug, Serialize)]
 pub struct ScoreEstimateResponse {
     pub boards: Vec<ScoreEstimateBoardResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub combined: Option<CombinedEstimate>, // quantum mode only
 }
 
 #[derive(Debug, Serialize)]
@@ -78,10 +96,11 @@ pub struct ScoreEstimateBoardResponse {
     pub ownership: Vec<f32>,
     pub winrate: f32,
     pub score_lead: f32,
+    pub confidence: f32,       // 归属已定的点所占比例
     pub dead_stones: Vec<i32>, // 死子坐标列表 [x1, y1, x2, y2, ...]
 }
 
-fn xy_to_gtp(pos: &str, size: u8) -> io::Result<String> {
+pub(crate) fn xy_to_gtp(pos: &str, size: u8) -> io::Result<String> {
     let (x_s, y_s) = pos
         .split_once(',')
         .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid pos"))?;
@@ -91,7 +110,7 @@ fn xy_to_gtp(pos: &str, size: u8) -> io::Result<String> {
         return Err(io::Error::new(io::ErrorKind::InvalidInput, "pos out of bounds"));
     }
     let gtp_row = (size as i32) - x + 1;
-    let mut col_idx = y as u8;
+    let col_idx = y as u8;
     let col_char = if col_idx <= 8 { // A..H
         (b'A' + (col_idx - 1)) as char
     } else { // skip 'I'
@@ -100,7 +119,7 @@ fn xy_to_gtp(pos: &str, size: u8) -> io::Result<String> {
     Ok(format!("{}{}", col_char, gtp_row))
 }
 
-fn color_to_gtp(color: &str) -> &'static str {
+pub(crate) fn color_to_gtp(color: &str) -> &'static str {
     match color.to_ascii_lowercase().as_str() {
         "b" | "black" => "B",
         "w" | "white" => "W",
@@ -108,7 +127,7 @@ fn color_to_gtp(color: &str) -> &'static str {
     }
 }
 
-fn gtp_to_xy(coord: &str, size: u8) -> io::Result<String> {
+pub fn gtp_to_xy(coord: &str, size: u8) -> io::Result<String> {
     let c = coord.trim().to_ascii_uppercase();
     if c == "PASS" || c == "RESIGN" { return Ok(c); }
     if c.len() < 2 { return Err(io::Error::new(io::ErrorKind::InvalidInput, "bad gtp")); }
@@ -140,10 +159,58 @@ async fn read_gtp_reply(reader: &mut (impl AsyncBufReadExt + Unpin)) -> io::Resu
     Ok(first_line[1..].trim().to_string())
 }
 
+// Read a kata-genmove_analyze reply. KataGo prints "=", then streams info lines and finishes
+// with "play <move>"; info lines printed before the status line are accepted as well.
+async fn read_gtp_reply_with_info(reader: &mut (impl AsyncBufReadExt + Unpin)) -> io::Result<(String, Vec<String>)> {
+    let mut info_lines: Vec<String> = Vec::new();
+    let mut first_line = String::new();
+    loop {
+        first_line.clear();
+        let n = reader.read_line(&mut first_line).await?;
+        if n == 0 { return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "gtp closed")); }
+        let trimmed = first_line.trim();
+        if trimmed.starts_with('=') || trimmed.starts_with('?') {
+            break;
+        } else if trimmed.starts_with("info") {
+            info_lines.push(trimmed.to_string());
+        }
+    }
+    let mut reply = first_line.trim()[1..].trim().to_string();
+    loop {
+        let mut line = String::new();
+        let n = reader.read_line(&mut line).await?;
+        let trimmed = line.trim();
+        if n == 0 || trimmed.is_empty() { break; }
+        if trimmed.starts_with("info") {
+            info_lines.push(trimmed.to_string());
+        } else if let Some(mv) = trimmed.strip_prefix("play ") {
+            reply = mv.trim().to_string();
+        }
+    }
+    Ok((reply, info_lines))
+}
+
+fn timed_out(deadline: Duration) -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, format!("katago did not answer within {:?}", deadline))
+}
+
+// KataGo logs model loading, tuning and fatal errors to stderr
+pub(crate) async fn forward_stderr(id: u64, stderr: ChildStderr) {
+    let mut lines = BufReader::new(stderr).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let lower = line.to_ascii_lowercase();
+        if lower.contains("error") || lower.contains("exception") {
+            tracing::warn!("katago[{}] {}", id, line);
+        } else {
+            tracing::debug!("katago[{}] {}", id, line);
+        }
+    }
+}
+
 // Which metric to optimize when reading analysis output
-enum Metric { Winrate, ScoreLead }
+pub(crate) enum Metric { Winrate, ScoreLead }
 
-fn parse_metric(m: Option<&str>) -> Metric {
+pub(crate) fn parse_metric(m: Option<&str>) -> Metric {
     match m.map(|s| s.to_ascii_lowercase()) {
         Some(ref s) if s == "winrate" => Metric::Winrate,
         // Default to score lead if unspecified or any of these aliases
@@ -151,119 +218,283 @@ fn parse_metric(m: Option<&str>) -> Metric {
     }
 }
 
-struct KataGoEngine {
+// A candidate move evaluated on both boards, each from the mover's perspective
+pub(crate) struct DualCandidate {
+    pub move_coord: String,
+    pub a_metric: f32,
+    pub a_winrate: f32,
+    pub b_metric: f32,
+    pub b_winrate: f32,
+}
+
+// Combine per-board evaluations: winrate → maximize the worse board;
+// score lead → maximize the total among moves that keep both boards above a winrate floor,
+// falling back to the plain total if none qualify.
+pub(crate) fn pick_dual_candidate(candidates: &[DualCandidate], metric: &Metric) -> Option<String> {
+    const MIN_WR_THRESHOLD: f32 = 0.35;
+    let best_by = |filter: &dyn Fn(&DualCandidate) -> bool, score: &dyn Fn(&DualCandidate) -> f32| {
+        let mut best: Option<(&DualCandidate, f32)> = None;
+        for c in candidates.iter().filter(|c| filter(c)) {
+            let s = score(c);
+            if best.is_none_or(|(_, b)| s > b) {
+                best = Some((c, s));
+            }
+        }
+        best.map(|(c, _)| c.move_coord.clone())
+    };
+    match metric {
+        Metric::Winrate => best_by(&|_| true, &|c| c.a_metric.min(c.b_metric)),
+        Metric::ScoreLead => best_by(
+            &|c| c.a_winrate >= MIN_WR_THRESHOLD && c.b_winrate >= MIN_WR_THRESHOLD,
+            &|c| c.a_metric + c.b_metric,
+        )
+        .or_else(|| best_by(&|_| true, &|c| c.a_metric + c.b_metric)),
+    }
+}
+
+// One candidate parsed from analysis info output
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InfoEntry {
+    mv: String,
+    visits: i64,
+    winrate: Option<f32>,
+    score_lead: Option<f32>,
+}
+
+// kata-analyze reports winrate as 0..1; lz-style output uses 0..10000 or a percentage
+fn parse_winrate(token: &str) -> Option<f32> {
+    if let Some(pct) = token.strip_suffix('%') {
+        return pct.parse::<f32>().ok().map(|v| v / 100.0);
+    }
+    let v = token.parse::<f32>().ok()?;
+    Some(if v > 1.0 { v / 10000.0 } else { v })
+}
+
+// Parse info lines into one entry per move (highest visit count wins), sorted by visits desc.
+// A single line may hold several "info move ..." segments.
+fn parse_info_lines(lines: &[String]) -> Vec<InfoEntry> {
+    let mut by_move: std::collections::HashMap<String, InfoEntry> = std::collections::HashMap::new();
+    for line in lines {
+        for segment in line.split("info ") {
+            let tokens: Vec<&str> = segment.split_whitespace().collect();
+            let mut entry = InfoEntry { mv: String::new(), visits: 0, winrate: None, score_lead: None };
+            let mut i = 0usize;
+            while i + 1 < tokens.len() {
+                match tokens[i] {
+                    "move" => entry.mv = tokens[i + 1].to_string(),
+                    "visits" => entry.visits = tokens[i + 1].parse().unwrap_or(0),
+                    "winrate" => entry.winrate = parse_winrate(tokens[i + 1]),
+                    "scorelead" | "scoreLead" | "score_lead" => entry.score_lead = tokens[i + 1].parse().ok(),
+                    // Principal variation and ownership run to the end of the segment
+                    "pv" | "ownership" => break,
+                    _ => { i += 1; continue; }
+                }
+                i += 2;
+            }
+            if entry.mv.is_empty() { continue; }
+            match by_move.get(&entry.mv) {
+                Some(existing) if existing.visits >= entry.visits => {}
+                _ => { by_move.insert(entry.mv.clone(), entry); }
+            }
+        }
+    }
+    let mut entries: Vec<InfoEntry> = by_move.into_values().collect();
+    entries.sort_by(|a, b| b.visits.cmp(&a.visits).then_with(|| a.mv.cmp(&b.mv)));
+    entries
+}
+
+/// 启动 GTP 引擎的命令行；默认由 KATAGO_* 环境变量拼出，测试时可换成假引擎
+#[derive(Debug, Clone)]
+pub struct EngineCommand {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+impl EngineCommand {
+    pub fn for_level(level: &AiLevel) -> io::Result<Self> {
+        // KATAGO_GTP_COMMAND replaces the whole command line (e.g. a mock engine for local development)
+        if let Ok(cmd) = std::env::var("KATAGO_GTP_COMMAND") {
+            let mut parts = cmd.split_whitespace().map(str::to_string);
+            if let Some(program) = parts.next() {
+                return Ok(Self { program, args: parts.collect() });
+            }
+        }
+        // Prefer a pre-extracted binary if present to avoid AppImage extraction cost
+        let mut bin = std::env::var("KATAGO_BIN").map_err(|_| io::Error::new(io::ErrorKind::NotFound, "KATAGO_BIN not set"))?;
+        if let Ok(real) = std::env::var("KATAGO_BIN_REAL") {
+            if !real.trim().is_empty() {
+                bin = real;
+            }
+        }
+        let model = std::env::var("KATAGO_MODEL").map_err(|_| io::Error::new(io::ErrorKind::NotFound, "KATAGO_MODEL not set"))?;
+        let cfg = std::env::var("KATAGO_GTP_CONFIG").map_err(|_| io::Error::new(io::ErrorKind::NotFound, "KATAGO_GTP_CONFIG not set"))?;
+        let overrides = std::env::var("KATAGO_OVERRIDES").unwrap_or_else(|_| "ponderingEnabled=false".to_string());
+
+        let human_like = level.human_like();
+        let mut args = vec!["gtp".to_string(), "-model".to_string(), model];
+        if let (true, Some(human_model)) = (human_like, ai_level::human_model()) {
+            args.extend(["-human-model".to_string(), human_model]);
+        }
+        args.extend([
+            "-config".to_string(),
+            level.config_path(&cfg),
+            "-override-config".to_string(),
+            ai_level::merge_overrides(&overrides, &level.overrides(human_like)),
+        ]);
+        Ok(Self { program: bin, args })
+    }
+}
+
+/// GTP 回复的期限：普通命令、搜索命令（genmove / kata-genmove_analyze）、启动（加载模型）与预热
+#[derive(Debug, Clone)]
+pub struct EngineTimeouts {
+    pub command: Duration,
+    pub search: Duration,
+    pub startup: Duration,
+}
+
+static NEXT_ENGINE_ID: AtomicU64 = AtomicU64::new(1);
+
+// Engine ids are shared with the analysis engine so log lines stay unambiguous
+pub(crate) fn next_engine_id() -> u64 {
+    NEXT_ENGINE_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+pub struct KataGoEngine {
+    id: u64,
+    name: String,
+    version: String,
+    // Spawned with kill_on_drop: dropping the engine kills the process
     child: Child,
     stdin: ChildStdin,
     reader: BufReader<ChildStdout>,
+    timeouts: EngineTimeouts,
+    // A command was sent whose reply has not been fully read yet
+    mid_reply: bool,
 }
 
 impl KataGoEngine {
-    async fn setup_position(&mut self, size: u8, komi: f32, moves: &Vec<MoveItem>) -> io::Result<()> {
+    pub fn is_mid_reply(&self) -> bool {
+        self.mid_reply
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn pid(&self) -> Option<u32> {
+        self.child.id()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    async fn read_reply(&mut self) -> io::Result<String> {
+        self.read_reply_within(self.timeouts.command).await
+    }
+
+    // 超时后 mid_reply 保持为 true，归还时进程会被丢弃
+    async fn read_reply_within(&mut self, deadline: Duration) -> io::Result<String> {
+        let reply = tokio::time::timeout(deadline, read_gtp_reply(&mut self.reader))
+            .await
+            .map_err(|_| timed_out(deadline))??;
+        self.mid_reply = false;
+        Ok(reply)
+    }
+
+    /// 健康探测：name / version 都要在命令期限内回复
+    pub async fn probe(&mut self) -> io::Result<()> {
+        self.send("name").await?;
+        let _ = self.read_reply().await?;
+        self.send("version").await?;
+        let _ = self.read_reply().await?;
+        Ok(())
+    }
+
+    /// 预热：空棋盘上搜索一手，让神经网络完成初始化，之后的第一个请求不再承担这部分耗时
+    pub async fn warm_up(&mut self, size: u8) -> io::Result<()> {
+        let deadline = self.timeouts.startup;
         self.send(&format!("boardsize {}", size)).await?;
-        let _ = read_gtp_reply(&mut self.reader).await?;
+        let _ = self.read_reply().await?;
+        self.send("clear_board").await?;
+        let _ = self.read_reply().await?;
+        self.send("genmove b").await?;
+        let _ = self.read_reply_within(deadline).await?;
+        self.send("clear_board").await?;
+        let _ = self.read_reply().await?;
+        Ok(())
+    }
+
+    async fn setup_position(&mut self, size: u8, komi: f32, rules: Ruleset, moves: &[MoveItem]) -> io::Result<()> {
+        self.send(&format!("boardsize {}", size)).await?;
+        let _ = self.read_reply().await?;
+        // Set rules before komi: KataGo may reset komi to the ruleset's default
+        self.send(&format!("kata-set-rules {}", rules.katago_name())).await?;
+        let _ = self.read_reply().await?;
         self.send(&format!("komi {}", komi)).await?;
-        let _ = read_gtp_reply(&mut self.reader).await?;
+        let _ = self.read_reply().await?;
         self.send("clear_board").await?;
-        let _ = read_gtp_reply(&mut self.reader).await?;
+        let _ = self.read_reply().await?;
         for m in moves {
             let color = color_to_gtp(&m.color);
             let coord = xy_to_gtp(&m.position, size)?;
             self.send(&format!("play {} {}", color, coord)).await?;
-            let _ = read_gtp_reply(&mut self.reader).await?;
+            let _ = self.read_reply().await?;
         }
         Ok(())
     }
 
-    // Read a GTP reply, collecting any analysis/info lines printed before the final '=' line.
     async fn read_reply_with_info(&mut self) -> io::Result<(String, Vec<String>)> {
-        let mut info_lines: Vec<String> = Vec::new();
-        let mut first_line = String::new();
-        loop {
-            first_line.clear();
-            let n = self.reader.read_line(&mut first_line).await?;
-            if n == 0 { return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "gtp closed")); }
-            let trimmed = first_line.trim().to_string();
-            if trimmed.starts_with('=') || trimmed.starts_with('?') {
-                break;
-            } else if !trimmed.is_empty() {
-                info_lines.push(trimmed);
-            }
+        let deadline = self.timeouts.search;
+        let reply = tokio::time::timeout(deadline, read_gtp_reply_with_info(&mut self.reader))
+            .await
+            .map_err(|_| timed_out(deadline))??;
+        self.mid_reply = false;
+        Ok(reply)
+    }
+
+    // kata-genmove_analyze entries for `color` to move, served from the analysis cache when possible
+    async fn analyze_cached(&mut self, scope: &AnalysisScope<'_>, moves: &[MoveItem], color: &str) -> io::Result<Arc<Vec<InfoEntry>>> {
+        let key = analysis_cache::key(scope, moves, color);
+        if let Some(hit) = match &key { Some(key) => analysis_cache::get(key).await, None => None } {
+            return Ok(hit);
         }
-        // consume until blank line
-        loop {
-            let mut line = String::new();
-            let n = self.reader.read_line(&mut line).await?;
-            if n == 0 || line.trim().is_empty() { break; }
+        self.setup_position(scope.size, scope.komi, scope.rules, moves).await?;
+        self.send(&format!("kata-genmove_analyze {}", color_to_gtp(color))).await?;
+        let (_reply, info_lines) = self.read_reply_with_info().await?;
+        let entries = Arc::new(parse_info_lines(&info_lines));
+        if let (Some(key), false) = (key, entries.is_empty()) {
+            analysis_cache::put(key, entries.clone());
         }
-        Ok((first_line[1..].trim().to_string(), info_lines))
+        Ok(entries)
     }
 
     // Run kata-genmove_analyze to obtain candidate moves with their first-board metric (winrate or scoreLead),
     // and also return the winrate for optional thresholding.
-    async fn analyze_candidates(&mut self, size: u8, komi: f32, moves: &Vec<MoveItem>, color: &str, k: usize, metric: &Metric) -> io::Result<Vec<(String, f32, f32)>> {
-        self.setup_position(size, komi, moves).await?;
-        // Trigger a single search that also returns a normal GTP reply, while streaming info lines.
-        self.send(&format!("kata-genmove_analyze {}", color_to_gtp(color))).await?;
-        let (_reply, info_lines) = self.read_reply_with_info().await?;
-
-        // Parse LZ-style info lines: e.g., "info move D16 visits 123 winrate 54.32% scoreLead 2.3 ..."
-        let mut by_move: std::collections::HashMap<String, (i64, f32, f32)> = std::collections::HashMap::new();
-        for line in info_lines {
-            // Quick-and-robust parse: find tokens
-            let tokens: Vec<&str> = line.split_whitespace().collect();
-            let mut mv: Option<String> = None;
-            let mut visits: Option<i64> = None;
-            let mut wr: Option<f32> = None;
-            let mut sl: Option<f32> = None;
-            let mut i = 0usize;
-            while i + 1 < tokens.len() {
-                match tokens[i] {
-                    "move" => {
-                        mv = Some(tokens[i+1].to_string());
-                        i += 2; continue;
-                    }
-                    "visits" => {
-                        if let Ok(v) = tokens[i+1].parse::<i64>() { visits = Some(v); }
-                        i += 2; continue;
-                    }
-                    "winrate" => {
-                        let t = tokens[i+1].trim_end_matches('%');
-                        if let Ok(v) = t.parse::<f32>() { wr = Some(v / 100.0); }
-                        i += 2; continue;
-                    }
-                    "scorelead" | "scoreLead" | "score_lead" => {
-                        if let Ok(v) = tokens[i+1].parse::<f32>() { sl = Some(v); }
-                        i += 2; continue;
-                    }
-                    _ => { i += 1; }
-                }
-            }
-            if let Some(m) = mv {
-                let v = visits.unwrap_or(0);
-                // Compute both metric-specific value and winrate value with fallbacks
-                let wr_val = wr.or(sl.map(|s| 0.5 + (s / 100.0))).unwrap_or(0.5);
-                let met_val = match metric {
-                    Metric::Winrate => wr_val,
-                    Metric::ScoreLead => sl.or(wr.map(|w| (w - 0.5) * 100.0)).unwrap_or(0.0),
-                };
-                // Keep the latest/best by visits
-                let entry = by_move.entry(m).or_insert((v, met_val, wr_val));
-                if v > entry.0 { *entry = (v, met_val, wr_val); }
-            }
-        }
+    async fn analyze_candidates(&mut self, scope: &AnalysisScope<'_>, moves: &[MoveItem], color: &str, k: usize, metric: &Metric) -> io::Result<Vec<(String, f32, f32)>> {
+        let entries = self.analyze_cached(scope, moves, color).await?;
 
-        let mut items: Vec<(String, i64, f32, f32)> = by_move.into_iter().map(|(m, (v, met, wr))| (m, v, met, wr)).collect();
-        // Sort by visits desc as a proxy of strength
-        items.sort_by(|a, b| b.1.cmp(&a.1));
         let mut result: Vec<(String, f32, f32)> = Vec::new();
-        for (m, _v, met, wr) in items.into_iter().take(k) {
-            result.push((m, met, wr));
+        // Entries come back sorted by visits, a proxy of strength
+        for entry in entries.iter().take(k) {
+            // Compute both metric-specific value and winrate value with fallbacks
+            let wr_val = entry.winrate.or(entry.score_lead.map(|s| 0.5 + (s / 100.0))).unwrap_or(0.5);
+            let met_val = match metric {
+                Metric::Winrate => wr_val,
+                Metric::ScoreLead => entry.score_lead.or(entry.winrate.map(|w| (w - 0.5) * 100.0)).unwrap_or(0.0),
+            };
+            result.push((entry.mv.clone(), met_val, wr_val));
         }
         // Fallback: if nothing parsed, do a simple genmove
         if result.is_empty() {
-            self.setup_position(size, komi, moves).await?;
+            self.setup_position(scope.size, scope.komi, scope.rules, moves).await?;
             self.send(&format!("genmove {}", color_to_gtp(color))).await?;
-            let ans = read_gtp_reply(&mut self.reader).await?;
+            let ans = self.read_reply_within(self.timeouts.search).await?;
             // Default neutral values
             return Ok(vec![(ans, match metric { Metric::Winrate => 0.5, Metric::ScoreLead => 0.0 }, 0.5)]);
         }
@@ -272,8 +503,7 @@ impl KataGoEngine {
 
     // Estimate our side's metric (winrate or scoreLead) on board B after playing a candidate,
     // and also compute our winrate, by running a short analyze for the opponent.
-    async fn evaluate_on_second_board(&mut self, size: u8, komi: f32, moves: &Vec<MoveItem>, our_color: &str, candidate_gtp: &str, metric: &Metric) -> io::Result<(f32, f32)> {
-        self.setup_position(size, komi, moves).await?;
+    async fn evaluate_on_second_board(&mut self, scope: &AnalysisScope<'_>, moves: &[MoveItem], our_color: &str, candidate_gtp: &str, metric: &Metric) -> io::Result<(f32, f32)> {
         if candidate_gtp.eq_ignore_ascii_case("PASS") || candidate_gtp.eq_ignore_ascii_case("RESIGN") {
             // PASS/RESIGN: neutral/very bad depending on metric. For winrate return also wr.
             return Ok(match metric {
@@ -281,121 +511,99 @@ impl KataGoEngine {
                 Metric::ScoreLead => (if candidate_gtp.eq_ignore_ascii_case("PASS") { 0.0 } else { -1000.0 }, if candidate_gtp.eq_ignore_ascii_case("PASS") { 0.5 } else { 0.0 }),
             });
         }
-        // Play our candidate
-        self.send(&format!("play {} {}", color_to_gtp(our_color), candidate_gtp)).await?;
-        let _ = read_gtp_reply(&mut self.reader).await?;
-
-        // Opponent to move now
-        let opp = match our_color.to_ascii_lowercase().as_str() {
-            "black" | "b" => "W",
-            _ => "B",
+        // Play our candidate; the opponent is to move in the resulting position
+        let mut after = moves.to_vec();
+        after.push(MoveItem { color: our_color.to_string(), position: gtp_to_xy(candidate_gtp, scope.size)? });
+        let opp = match color_to_gtp(our_color) {
+            "B" => "white",
+            _ => "black",
         };
-        self.send(&format!("kata-genmove_analyze {}", opp)).await?;
-        let (_reply, info_lines) = self.read_reply_with_info().await?;
+        let entries = self.analyze_cached(scope, &after, opp).await?;
 
-        // Parse last seen metric for opponent
-        let mut last_wr: Option<f32> = None;
-        let mut last_sl: Option<f32> = None;
-        for line in info_lines {
-            let tokens: Vec<&str> = line.split_whitespace().collect();
-            let mut i = 0usize;
-            let mut wr: Option<f32> = None;
-            let mut sl: Option<f32> = None;
-            while i + 1 < tokens.len() {
-                match tokens[i] {
-                    "winrate" => {
-                        let t = tokens[i+1].trim_end_matches('%');
-                        if let Ok(v) = t.parse::<f32>() { wr = Some(v / 100.0); }
-                        i += 2; continue;
-                    }
-                    "scorelead" | "scoreLead" | "score_lead" => {
-                        if let Ok(v) = tokens[i+1].parse::<f32>() { sl = Some(v); }
-                        i += 2; continue;
-                    }
-                    _ => { i += 1; }
-                }
-            }
-            if let Some(w) = wr { last_wr = Some(w); }
-            if let Some(s) = sl { last_sl = Some(s); }
-        }
-        let our_wr = 1.0 - last_wr.unwrap_or(0.5);
+        // The opponent's most-visited reply stands for the position value
+        let best = entries.first();
+        let our_wr = 1.0 - best.and_then(|e| e.winrate).unwrap_or(0.5);
         let met_val = match metric {
             Metric::Winrate => our_wr,
             // Opponent's scoreLead is from opponent perspective; invert to get ours
-            Metric::ScoreLead => -(last_sl.unwrap_or(0.0)),
+            Metric::ScoreLead => -(best.and_then(|e| e.score_lead).unwrap_or(0.0)),
         };
         Ok((met_val, our_wr))
     }
-    async fn new() -> io::Result<Self> {
-        // Prefer a pre-extracted binary if present to avoid AppImage extraction cost
-        let mut bin = std::env::var("KATAGO_BIN").map_err(|_| io::Error::new(io::ErrorKind::NotFound, "KATAGO_BIN not set"))?;
-        if let Ok(real) = std::env::var("KATAGO_BIN_REAL") {
-            if !real.trim().is_empty() {
-                bin = real;
-            }
-        }
-        let model = std::env::var("KATAGO_MODEL").map_err(|_| io::Error::new(io::ErrorKind::NotFound, "KATAGO_MODEL not set"))?;
-        let cfg = std::env::var("KATAGO_GTP_CONFIG").map_err(|_| io::Error::new(io::ErrorKind::NotFound, "KATAGO_GTP_CONFIG not set"))?;
-        let overrides = std::env::var("KATAGO_OVERRIDES").unwrap_or_else(|_| "ponderingEnabled=false".to_string());
 
-        let mut child = Command::new(bin)
-            .arg("gtp")
-            .arg("-model").arg(model)
-            .arg("-config").arg(cfg)
-            .arg("-override-config").arg(overrides)
+    /// 启动引擎进程：stderr 转发到日志，并要求在启动期限内回复 name / version
+    pub async fn spawn(command: &EngineCommand, timeouts: EngineTimeouts) -> io::Result<Self> {
+        let mut child = Command::new(&command.program)
+            .args(&command.args)
             .stdin(std::process::Stdio::piped())
             .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
             .spawn()?;
 
-        let stdin = child.stdin.take().ok_or_else(|| io::Error::new(io::ErrorKind::Other, "no stdin"))?;
-        let stdout = child.stdout.take().ok_or_else(|| io::Error::new(io::ErrorKind::Other, "no stdout"))?;
+        let id = next_engine_id();
+        let stdin = child.stdin.take().ok_or_else(|| io::Error::other("no stdin"))?;
+        let stdout = child.stdout.take().ok_or_else(|| io::Error::other("no stdout"))?;
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(forward_stderr(id, stderr));
+        }
         let reader = BufReader::new(stdout);
 
-        Ok(KataGoEngine { child, stdin, reader })
+        let mut engine = KataGoEngine {
+            id,
+            name: String::new(),
+            version: String::new(),
+            child,
+            stdin,
+            reader,
+            timeouts,
+            mid_reply: false,
+        };
+        let startup = engine.timeouts.startup;
+        engine.send("name").await?;
+        engine.name = engine.read_reply_within(startup).await?;
+        engine.send("version").await?;
+        engine.version = engine.read_reply().await?;
+        tracing::info!("katago engine {} started: {} {} (pid {:?})", id, engine.name, engine.version, engine.pid());
+        Ok(engine)
     }
 
     async fn send(&mut self, cmd: &str) -> io::Result<()> {
+        self.mid_reply = true;
         self.stdin.write_all(cmd.as_bytes()).await?;
         self.stdin.write_all(b"\n").await?;
         self.stdin.flush().await
     }
 
-    async fn genmove(&mut self, req: &AiGenmoveRequest) -> io::Result<AiGenmoveResponse> {
-        self.send(&format!("boardsize {}", req.board_size)).await?;
-        let _ = read_gtp_reply(&mut self.reader).await?;
-        self.send(&format!("komi {}", req.komi.unwrap_or(7.5))).await?;
-        let _ = read_gtp_reply(&mut self.reader).await?;
-        self.send("clear_board").await?;
-        let _ = read_gtp_reply(&mut self.reader).await?;
-
-        for m in &req.moves {
-            let color = color_to_gtp(&m.color);
-            let coord = xy_to_gtp(&m.position, req.board_size)?;
-            self.send(&format!("play {} {}", color, coord)).await?;
-            let _ = read_gtp_reply(&mut self.reader).await?;
-        }
+    async fn genmove(&mut self, req: &AiGenmoveRequest, rules: Ruleset) -> io::Result<AiGenmoveResponse> {
+        self.setup_position(req.board_size, req.komi.unwrap_or(7.5), rules, &req.moves).await?;
 
         let color = color_to_gtp(&req.next_to_move);
         self.send(&format!("genmove {}", color)).await?;
-        let ans = read_gtp_reply(&mut self.reader).await?;
+        let ans = self.read_reply_within(self.timeouts.search).await?;
         Ok(AiGenmoveResponse { move_coord: ans })
     }
 }
 
-static ENGINE_POOL: Lazy<Mutex<HashMap<u8, Arc<Mutex<KataGoEngine>>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
-
 pub async fn genmove_with_katago(req: AiGenmoveRequest) -> Result<AiGenmoveResponse, io::Error> {
-    // Get or create a persistent engine for this board size
-    let mut engine_arc = {
-        let mut pool = ENGINE_POOL.lock().await;
-        if let Some(engine) = pool.get(&req.board_size) {
-            engine.clone()
-        } else {
-            let engine = Arc::new(Mutex::new(KataGoEngine::new().await?));
-            pool.insert(req.board_size, engine.clone());
-            engine
-        }
-    };
+    if katago_analysis::enabled() {
+        return katago_analysis::genmove(&req).await;
+    }
+    let level = ai_level::resolve(req.level.as_deref())?;
+    let pool = katago_pool::pool_for(req.board_size, level).await;
+    genmove_in_pool(&pool, &req).await
+}
+
+/// 在指定引擎池中选点（genmove_with_katago 按档位取池；测试可传入假引擎的池）
+pub async fn genmove_in_pool(pool: &Arc<EnginePool>, req: &AiGenmoveRequest) -> Result<AiGenmoveResponse, io::Error> {
+    let mut engine = pool.checkout().await?;
+    tokio::time::timeout(pool.request_timeout(), genmove_on_engine(&mut engine, req))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "katago request timed out"))?
+}
+
+async fn genmove_on_engine(engine: &mut PooledEngine, req: &AiGenmoveRequest) -> Result<AiGenmoveResponse, io::Error> {
+    let rules = Ruleset::resolve(req.rules.as_deref(), Ruleset::Chinese)?;
     // Build a quick lookup set of forbidden xy positions
     let forbidden: std::collections::HashSet<String> = req
         .forbidden
@@ -407,9 +615,8 @@ pub async fn genmove_with_katago(req: AiGenmoveRequest) -> Result<AiGenmoveRespo
     // Use the engine exclusively for this request; retry a few times if suggestion is forbidden.
     let mut attempts = 0usize;
     let max_attempts = 5usize;
-    let mut engine = engine_arc.lock().await;
     loop {
-        let res = engine.genmove(&req).await;
+        let res = engine.genmove(req, rules).await;
         match res {
             Ok(resp) => {
                 // If we don't have a forbidden set, return as-is
@@ -426,28 +633,16 @@ pub async fn genmove_with_katago(req: AiGenmoveRequest) -> Result<AiGenmoveRespo
                     return Ok(AiGenmoveResponse { move_coord: "pass".to_string() });
                 }
                 // Respawn engine to change random seed and try again
-                drop(engine);
-                let mut pool = ENGINE_POOL.lock().await;
-                let new_arc = Arc::new(Mutex::new(KataGoEngine::new().await?));
-                pool.insert(req.board_size, new_arc.clone());
-                drop(pool);
-                engine_arc = new_arc.clone();
-                engine = engine_arc.lock().await;
+                engine.respawn().await?;
                 continue;
             }
             Err(_) => {
                 // Engine error: respawn once per failure and retry
                 attempts += 1;
                 if attempts >= max_attempts {
-                    return Err(io::Error::new(io::ErrorKind::Other, "katago failed"));
+                    return Err(io::Error::other("katago failed"));
                 }
-                drop(engine);
-                let mut pool = ENGINE_POOL.lock().await;
-                let new_arc = Arc::new(Mutex::new(KataGoEngine::new().await?));
-                pool.insert(req.board_size, new_arc.clone());
-                drop(pool);
-                engine_arc = new_arc.clone();
-                engine = engine_arc.lock().await;
+                engine.respawn().await?;
                 continue;
             }
         }
@@ -455,29 +650,40 @@ pub async fn genmove_with_katago(req: AiGenmoveRequest) -> Result<AiGenmoveRespo
 }
 
 pub async fn genmove_dual_with_katago(req: AiDualGenmoveRequest) -> Result<AiDualGenmoveResponse, io::Error> {
-    let mut engine_arc = {
-        let mut pool = ENGINE_POOL.lock().await;
-        if let Some(engine) = pool.get(&req.board_size) {
-            engine.clone()
-        } else {
-            let engine = Arc::new(Mutex::new(KataGoEngine::new().await?));
-            pool.insert(req.board_size, engine.clone());
-            engine
-        }
-    };
+    if katago_analysis::enabled() {
+        return katago_analysis::genmove_dual(&req).await;
+    }
+    let level = ai_level::resolve(req.level.as_deref())?;
+    let pool = katago_pool::pool_for(req.board_size, level).await;
+    genmove_dual_in_pool(&pool, &req).await
+}
+
+/// 在指定引擎池中按两个现实选点
+pub async fn genmove_dual_in_pool(pool: &Arc<EnginePool>, req: &AiDualGenmoveRequest) -> Result<AiDualGenmoveResponse, io::Error> {
+    let mut engine = pool.checkout().await?;
+    tokio::time::timeout(pool.request_timeout(), genmove_dual_on_engine(&mut engine, req))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "katago request timed out"))?
+}
 
+async fn genmove_dual_on_engine(engine: &mut PooledEngine, req: &AiDualGenmoveRequest) -> Result<AiDualGenmoveResponse, io::Error> {
     let forbidden: std::collections::HashSet<String> = req.forbidden.clone().unwrap_or_default().into_iter().collect();
     let komi = req.komi.unwrap_or(7.5);
-    let k = req.k.unwrap_or(8).max(1).min(20);
+    let k = req.k.unwrap_or(8).clamp(1, 20);
     let metric = parse_metric(req.metric.as_deref());
+    let scope = AnalysisScope {
+        size: req.board_size,
+        komi,
+        rules: Ruleset::resolve(req.rules.as_deref(), Ruleset::Chinese)?,
+        level: engine.level().id,
+        cached: engine.caches_analysis(),
+    };
 
     let mut attempts = 0usize;
     let max_attempts = 3usize;
-    let mut engine = engine_arc.lock().await;
-
     loop {
         // 1) Get candidates from board A
-        let mut candidates = engine.analyze_candidates(req.board_size, komi, &req.board_a_moves, &req.next_to_move, k, &metric).await?;
+        let mut candidates = engine.analyze_candidates(&scope, &req.board_a_moves, &req.next_to_move, k, &metric).await?;
 
         // Filter forbidden and normalize coords → XY for checking
         candidates.retain(|(gtp, _, _)| {
@@ -490,60 +696,20 @@ pub async fn genmove_dual_with_katago(req: AiDualGenmoveRequest) -> Result<AiDua
         }
 
         // 2) Evaluate each candidate on board B and combine scores
-        let mut best: Option<(String, f32)> = None;
-
-        match metric {
-            Metric::Winrate => {
-                for (cand_gtp, a_wr, _a_wr_copy) in candidates.into_iter().map(|(m, met, wr)| (m, met, wr)) {
-                    let (b_wr, _b_wr_copy) = engine.evaluate_on_second_board(req.board_size, komi, &req.board_b_moves, &req.next_to_move, &cand_gtp, &metric).await?;
-                    let score = a_wr.min(b_wr); // as before
-                    match &mut best {
-                        None => best = Some((cand_gtp.clone(), score)),
-                        Some((_m, s)) => { if score > *s { *s = score; best.as_mut().unwrap().0 = cand_gtp.clone(); } }
-                    }
-                }
-            }
-            Metric::ScoreLead => {
-                const MIN_WR_THRESHOLD: f32 = 0.35;
-                // First pass: enforce winrate threshold on both boards
-                for (cand_gtp, a_sl, a_wr) in &candidates {
-                    let (b_sl, b_wr) = engine.evaluate_on_second_board(req.board_size, komi, &req.board_b_moves, &req.next_to_move, cand_gtp, &metric).await?;
-                    if *a_wr >= MIN_WR_THRESHOLD && b_wr >= MIN_WR_THRESHOLD {
-                        let score = a_sl + b_sl; // maximize total score lead
-                        match &mut best {
-                            None => best = Some((cand_gtp.clone(), score)),
-                            Some((_m, s)) => { if score > *s { *s = score; best.as_mut().unwrap().0 = cand_gtp.clone(); } }
-                        }
-                    }
-                }
-                // Fallback: if nothing passed threshold, choose the best by pure sum
-                if best.is_none() {
-                    for (cand_gtp, a_sl, _a_wr) in candidates {
-                        let (b_sl, _b_wr) = engine.evaluate_on_second_board(req.board_size, komi, &req.board_b_moves, &req.next_to_move, &cand_gtp, &metric).await?;
-                        let score = a_sl + b_sl;
-                        match &mut best {
-                            None => best = Some((cand_gtp.clone(), score)),
-                            Some((_m, s)) => { if score > *s { *s = score; best.as_mut().unwrap().0 = cand_gtp.clone(); } }
-                        }
-                    }
-                }
-            }
+        let mut evaluated = Vec::with_capacity(candidates.len());
+        for (cand_gtp, a_metric, a_winrate) in candidates {
+            let (b_metric, b_winrate) = engine.evaluate_on_second_board(&scope, &req.board_b_moves, &req.next_to_move, &cand_gtp, &metric).await?;
+            evaluated.push(DualCandidate { move_coord: cand_gtp, a_metric, a_winrate, b_metric, b_winrate });
         }
 
-        if let Some((best_move, _)) = best { return Ok(AiDualGenmoveResponse { move_coord: best_move }); }
+        if let Some(best_move) = pick_dual_candidate(&evaluated, &metric) { return Ok(AiDualGenmoveResponse { move_coord: best_move }); }
 
         // Respawn on failures a few times
         attempts += 1;
         if attempts >= max_attempts {
-            return Err(io::Error::new(io::ErrorKind::Other, "katago dual-genmove failed"));
+            return Err(io::Error::other("katago dual-genmove failed"));
         }
-        drop(engine);
-        let mut pool = ENGINE_POOL.lock().await;
-        let new_arc = Arc::new(Mutex::new(KataGoEngine::new().await?));
-        pool.insert(req.board_size, new_arc.clone());
-        drop(pool);
-        engine_arc = new_arc.clone();
-        engine = engine_arc.lock().await;
+        engine.respawn().await?;
     }
 }
 
@@ -554,20 +720,29 @@ pub async fn estimate_with_score_estimator(
     if req.boards.is_empty() {
         return Err(io::Error::new(io::ErrorKind::InvalidInput, "no boards provided"));
     }
-    
-    let mut responses = Vec::with_capacity(req.boards.len());
-    
-    for (idx, board) in req.boards.iter().enumerate() {
-        // 使用score-estimator进行估算
-        let (ownership, territory, dead_pairs) = score_estimator::estimate_board_score(
+    let params = EstimateParams::resolve(req.trials, req.tolerance)?;
+    match req.mode.as_deref().map(str::trim).unwrap_or("") {
+        "" | "independent" => {}
+        "quantum" => return quantum_estimate::estimate(&req, params).await,
+        other => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown estimate mode: {}", other))),
+    }
+
+    // 使用score-estimator进行估算（阻塞线程池，各盘并行）
+    let estimates = futures::future::try_join_all(req.boards.iter().map(|board| {
+        score_estimator::estimate_board_blocking(
             board.board_size,
             &board.black_stones,
             &board.white_stones,
             board.next_to_move.as_deref(),
-            1000, // trials
-            0.4,  // tolerance
-        ).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        
+            params,
+        )
+    }))
+    .await?;
+
+    let mut responses = Vec::with_capacity(req.boards.len());
+    
+    for (idx, (board, (ownership, territory, dead_pairs))) in req.boards.iter().zip(estimates).enumerate() {
+
         // 获取死子信息
         // 将死子坐标转换为扁平数组
         let mut dead_stones_flat = Vec::new();
@@ -591,15 +766,50 @@ pub async fn estimate_with_score_estimator(
         let score_lead = black_territory - white_territory;
         let winrate = if score_lead > 0.0 { 0.5 + (score_lead / 100.0).min(0.5) } else { 0.5 - (score_lead.abs() / 100.0).min(0.5) };
         
+        let confidence = ownership.iter().filter(|v| v.abs() >= 0.5).count() as f32 / ownership.len().max(1) as f32;
         responses.push(ScoreEstimateBoardResponse {
             board_index: idx,
             board_size: board.board_size,
             ownership,
-            winrate: winrate.max(0.0).min(1.0),
+            winrate: winrate.clamp(0.0, 1.0),
             score_lead,
+            confidence,
             dead_stones: dead_stones_flat,
         });
     }
     
-    Ok(ScoreEstimateResponse { boards: responses })
+    Ok(ScoreEstimateResponse { boards: responses, combined: None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coordinate_conversion_skips_i() {
+        assert_eq!(xy_to_gtp("1,1", 9).unwrap(), "A9");
+        assert_eq!(xy_to_gtp("9,9", 9).unwrap(), "J1");
+        assert_eq!(xy_to_gtp("5,8", 9).unwrap(), "H5");
+        assert!(xy_to_gtp("10,1", 9).is_err());
+        for x in 1..=19 {
+            for y in 1..=19 {
+                let xy = format!("{},{}", x, y);
+                assert_eq!(gtp_to_xy(&xy_to_gtp(&xy, 19).unwrap(), 19).unwrap(), xy);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_info_lines_splits_segments() {
+        let lines = vec![
+            "info move D4 visits 120 winrate 0.61 scoreLead 2.5 order 0 pv D4 C3 info move C3 visits 40 winrate 0.55 scoreLead 1.0 order 1 pv C3".to_string(),
+            "info move E5 visits 10 winrate 4800 scoreLead -0.5 order 2 pv E5".to_string(),
+        ];
+        let entries = parse_info_lines(&lines);
+        let moves: Vec<&str> = entries.iter().map(|e| e.mv.as_str()).collect();
+        assert_eq!(moves, vec!["D4", "C3", "E5"]);
+        assert_eq!(entries[0].winrate, Some(0.61));
+        assert_eq!(entries[1].score_lead, Some(1.0));
+        assert!((entries[2].winrate.unwrap() - 0.48).abs() < 1e-6);
+    }
 }
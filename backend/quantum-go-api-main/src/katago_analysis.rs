@@ -0,0 +1,695 @@
+// KataGo JSON 分析引擎（`katago analysis`）后端
+//
+// 与 GTP 后端不同，分析引擎一次接收整盘着法，不需要逐手 `play` 重放；
+// 多个查询可以同时在途，按 id 对应返回结果。两个现实作为同一批的两个查询发送。
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::{Child, ChildStdin, Command},
+    sync::{oneshot, Mutex},
+};
+
+use crate::ai_level::{self, AiLevel};
+use crate::quantum::{QuantumGame, Stone};
+use crate::quantum_search::{game_from_moves, QuantumMoveItem};
+use crate::rules::Ruleset;
+use crate::katago::{
+    self, color_to_gtp, gtp_to_xy, parse_metric, pick_dual_candidate, xy_to_gtp, AiDualGenmoveRequest,
+    AiDualGenmoveResponse, AiGenmoveRequest, AiGenmoveResponse, DualCandidate, Metric, MoveItem,
+};
+
+// A batch that gets no answer within this long fails with TimedOut and the engine is restarted
+const DEFAULT_QUERY_TIMEOUT_MS: u64 = 60_000;
+
+type Pending = Arc<std::sync::Mutex<HashMap<String, oneshot::Sender<io::Result<AnalysisResponse>>>>>;
+
+/// 一条分析查询（字段名与 KataGo analysis 协议一致）
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalysisQuery {
+    pub id: String,
+    pub moves: Vec<(String, String)>,
+    // Setup stones for positions that cannot be expressed as a move sequence (e.g. quantum captures)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initial_stones: Option<Vec<(String, String)>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initial_player: Option<String>,
+    pub rules: String,
+    pub komi: f32,
+    pub board_x_size: u8,
+    pub board_y_size: u8,
+    pub analyze_turns: Vec<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_visits: Option<u32>,
+    pub include_ownership: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_moves: Option<Vec<AllowMoves>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub override_settings: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AllowMoves {
+    pub player: String,
+    pub moves: Vec<String>,
+    pub until_depth: u32,
+}
+
+/// 单个候选着（winrate/scoreLead 已换算为行棋方视角）
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MoveInfo {
+    #[serde(rename = "move")]
+    pub move_coord: String,
+    pub visits: u64,
+    pub winrate: f64,
+    pub score_lead: f64,
+    #[serde(default)]
+    pub prior: f64,
+    #[serde(default)]
+    pub order: u32,
+    #[serde(default)]
+    pub pv: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RootInfo {
+    pub winrate: f64,
+    pub score_lead: f64,
+    pub visits: u64,
+    #[serde(default)]
+    pub current_player: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalysisResponse {
+    pub id: String,
+    #[serde(default)]
+    pub turn_number: usize,
+    #[serde(default)]
+    pub is_during_search: bool,
+    pub move_infos: Vec<MoveInfo>,
+    pub root_info: RootInfo,
+    #[serde(default)]
+    pub ownership: Option<Vec<f64>>,
+}
+
+pub struct AnalysisEngine {
+    // Spawned with kill_on_drop: dropping the last handle kills the process
+    #[allow(dead_code)]
+    child: Child,
+    stdin: Mutex<ChildStdin>,
+    pending: Pending,
+    next_id: AtomicU64,
+}
+
+impl AnalysisEngine {
+    pub async fn new() -> io::Result<Self> {
+        let mut bin = std::env::var("KATAGO_BIN").map_err(|_| io::Error::new(io::ErrorKind::NotFound, "KATAGO_BIN not set"))?;
+        if let Ok(real) = std::env::var("KATAGO_BIN_REAL") {
+            if !real.trim().is_empty() {
+                bin = real;
+            }
+        }
+        let model = std::env::var("KATAGO_MODEL").map_err(|_| io::Error::new(io::ErrorKind::NotFound, "KATAGO_MODEL not set"))?;
+        let cfg = std::env::var("KATAGO_ANALYSIS_CONFIG").map_err(|_| io::Error::new(io::ErrorKind::NotFound, "KATAGO_ANALYSIS_CONFIG not set"))?;
+
+        // Winrates are always requested from Black's perspective and converted locally
+        let mut overrides = "reportAnalysisWinratesAs=BLACK".to_string();
+        if let Ok(extra) = std::env::var("KATAGO_ANALYSIS_OVERRIDES") {
+            if !extra.trim().is_empty() {
+                overrides = format!("{},{}", extra.trim(), overrides);
+            }
+        }
+        let mut child = Command::new(bin)
+            .arg("analysis")
+            .arg("-model").arg(model)
+            .arg("-config").arg(cfg)
+            .args(ai_level::human_model().map(|m| vec!["-human-model".to_string(), m]).unwrap_or_default())
+            .arg("-override-config").arg(overrides)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        let stdin = child.stdin.take().ok_or_else(|| io::Error::other("no stdin"))?;
+        let stdout = child.stdout.take().ok_or_else(|| io::Error::other("no stdout"))?;
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(katago::forward_stderr(katago::next_engine_id(), stderr));
+        }
+        let pending: Pending = Arc::new(std::sync::Mutex::new(HashMap::new()));
+
+        let reader_pending = pending.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                dispatch_line(&reader_pending, &line);
+            }
+            // Engine exited: fail everything still waiting
+            for (_, tx) in reader_pending.lock().unwrap().drain() {
+                let _ = tx.send(Err(io::Error::new(io::ErrorKind::UnexpectedEof, "katago analysis engine exited")));
+            }
+        });
+
+        Ok(Self {
+            child,
+            stdin: Mutex::new(stdin),
+            pending,
+            next_id: AtomicU64::new(0),
+        })
+    }
+
+    pub fn next_query_id(&self, prefix: &str) -> String {
+        format!("{}-{}", prefix, self.next_id.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// 一次写入整批查询，然后等待全部返回（结果顺序与查询一致）
+    pub async fn analyze_batch(&self, queries: &[AnalysisQuery]) -> io::Result<Vec<AnalysisResponse>> {
+        let mut receivers = Vec::with_capacity(queries.len());
+        {
+            let mut pending = self.pending.lock().unwrap();
+            for q in queries {
+                let (tx, rx) = oneshot::channel();
+                pending.insert(q.id.clone(), tx);
+                receivers.push(rx);
+            }
+        }
+
+        let mut payload = String::new();
+        for q in queries {
+            payload.push_str(&serde_json::to_string(q).map_err(io::Error::other)?);
+            payload.push('\n');
+        }
+        {
+            let mut stdin = self.stdin.lock().await;
+            if let Err(err) = async {
+                stdin.write_all(payload.as_bytes()).await?;
+                stdin.flush().await
+            }
+            .await
+            {
+                let mut pending = self.pending.lock().unwrap();
+                for q in queries {
+                    pending.remove(&q.id);
+                }
+                return Err(err);
+            }
+        }
+
+        let deadline = tokio::time::Instant::now() + query_timeout();
+        let mut responses = Vec::with_capacity(receivers.len());
+        for (q, rx) in queries.iter().zip(receivers) {
+            let Ok(received) = tokio::time::timeout_at(deadline, rx).await else {
+                let mut pending = self.pending.lock().unwrap();
+                for q in queries {
+                    pending.remove(&q.id);
+                }
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "katago analysis engine did not answer in time"));
+            };
+            let mut resp = received.map_err(|_| io::Error::other("katago analysis engine dropped the query"))??;
+            to_side_to_move(&mut resp, side_to_move(q));
+            responses.push(resp);
+        }
+        Ok(responses)
+    }
+}
+
+fn dispatch_line(pending: &Pending, line: &str) {
+    let value: serde_json::Value = match serde_json::from_str(line) {
+        Ok(v) => v,
+        Err(_) => return,
+    };
+    let id = match value.get("id").and_then(|v| v.as_str()) {
+        Some(id) => id.to_string(),
+        None => {
+            tracing::warn!("katago analysis message without id: {}", line);
+            return;
+        }
+    };
+    if value.get("isDuringSearch").and_then(|v| v.as_bool()).unwrap_or(false) {
+        return;
+    }
+    let result = if let Some(err) = value.get("error") {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, format!("katago analysis error: {}", err)))
+    } else if value.get("warning").is_some() && value.get("moveInfos").is_none() {
+        tracing::warn!("katago analysis warning: {}", line);
+        return;
+    } else {
+        serde_json::from_value::<AnalysisResponse>(value).map_err(io::Error::other)
+    };
+    if let Some(tx) = pending.lock().unwrap().remove(&id) {
+        let _ = tx.send(result);
+    }
+}
+
+// The side to move after the analysed turn
+fn side_to_move(q: &AnalysisQuery) -> &'static str {
+    let turn = q.analyze_turns.first().copied().unwrap_or(q.moves.len());
+    match turn.checked_sub(1).and_then(|i| q.moves.get(i)) {
+        Some((color, _)) if color == "B" => "W",
+        Some(_) => "B",
+        None => if q.initial_player.as_deref() == Some("W") { "W" } else { "B" },
+    }
+}
+
+// Convert Black-perspective numbers to the side to move
+fn to_side_to_move(resp: &mut AnalysisResponse, player: &str) {
+    if player != "W" {
+        return;
+    }
+    for mi in &mut resp.move_infos {
+        mi.winrate = 1.0 - mi.winrate;
+        mi.score_lead = -mi.score_lead;
+    }
+    resp.root_info.winrate = 1.0 - resp.root_info.winrate;
+    resp.root_info.score_lead = -resp.root_info.score_lead;
+}
+
+static ANALYSIS_ENGINE: Lazy<Mutex<Option<Arc<AnalysisEngine>>>> = Lazy::new(|| Mutex::new(None));
+
+/// 共享的分析引擎（分析引擎自身支持并发查询，无需多进程）
+pub async fn shared_engine() -> io::Result<Arc<AnalysisEngine>> {
+    let mut slot = ANALYSIS_ENGINE.lock().await;
+    if let Some(engine) = slot.as_ref() {
+        return Ok(engine.clone());
+    }
+    let engine = Arc::new(AnalysisEngine::new().await?);
+    *slot = Some(engine.clone());
+    Ok(engine)
+}
+
+// Drop a broken or hung engine so the next request starts a fresh process
+// (the old one is killed once in-flight batches release it)
+async fn reset_engine(engine: &Arc<AnalysisEngine>) {
+    let mut slot = ANALYSIS_ENGINE.lock().await;
+    // Another request may already have replaced it
+    if slot.as_ref().is_some_and(|current| Arc::ptr_eq(current, engine)) {
+        slot.take();
+    }
+}
+
+/// KATAGO_BACKEND=analysis 时使用 JSON 分析引擎，否则走 GTP
+pub fn enabled() -> bool {
+    std::env::var("KATAGO_BACKEND")
+        .map(|v| v.eq_ignore_ascii_case("analysis"))
+        .unwrap_or(false)
+}
+
+// KATAGO_ANALYSIS_TIMEOUT_MS：一批查询的应答期限
+fn query_timeout() -> Duration {
+    let ms = std::env::var("KATAGO_ANALYSIS_TIMEOUT_MS").ok().and_then(|v| v.parse().ok());
+    Duration::from_millis(ms.unwrap_or(DEFAULT_QUERY_TIMEOUT_MS))
+}
+
+fn max_visits() -> Option<u32> {
+    std::env::var("KATAGO_ANALYSIS_MAX_VISITS").ok().and_then(|v| v.parse().ok())
+}
+
+/// 请求中规则字段对应的 KataGo 规则串，默认中国规则；未知规则返回 InvalidInput
+pub fn rules_name(rules: Option<&str>) -> io::Result<&'static str> {
+    Ruleset::resolve(rules, Ruleset::Chinese).map(Ruleset::katago_name)
+}
+
+pub fn build_query(id: String, size: u8, komi: f32, rules: &str, next_to_move: &str, moves: &[MoveItem], include_ownership: bool) -> io::Result<AnalysisQuery> {
+    let moves = moves
+        .iter()
+        .map(|m| Ok((color_to_gtp(&m.color).to_string(), xy_to_gtp(&m.position, size)?)))
+        .collect::<io::Result<Vec<_>>>()?;
+    Ok(AnalysisQuery {
+        id,
+        analyze_turns: vec![moves.len()],
+        initial_stones: None,
+        // Only needed when there is no history (e.g. White first after handicap stones)
+        initial_player: moves.is_empty().then(|| color_to_gtp(next_to_move).to_string()),
+        moves,
+        rules: rules.to_string(),
+        komi,
+        board_x_size: size,
+        board_y_size: size,
+        max_visits: max_visits(),
+        include_ownership,
+        allow_moves: None,
+        override_settings: None,
+    })
+}
+
+/// 应用难度档位：访问数上限，以及有 human 模型时的 humanSLProfile
+/// （分析引擎总是取最优候选，不支持选点温度）
+pub fn apply_level(query: &mut AnalysisQuery, level: &AiLevel) {
+    if level.max_visits.is_some() {
+        query.max_visits = level.max_visits;
+    }
+    if let (Some(profile), true) = (level.human_profile, level.human_like()) {
+        let settings = query.override_settings.get_or_insert_with(serde_json::Map::new);
+        settings.insert("humanSLProfile".to_string(), serde_json::Value::String(profile.to_string()));
+    }
+}
+
+/// 量子对局中某一盘的当前局面：以摆子方式给出（量子提子无法用着法序列表达）
+pub fn board_query(id: String, game: &QuantumGame, board: usize, rules: &str, komi: f32, max_visits: Option<u32>, include_ownership: bool) -> io::Result<AnalysisQuery> {
+    let size = game.size as u8;
+    let stones = game.boards[board]
+        .stones()
+        .map(|(idx, stone)| Ok((stone_to_gtp(stone).to_string(), xy_to_gtp(&game.position_of(idx), size)?)))
+        .collect::<io::Result<Vec<_>>>()?;
+    Ok(AnalysisQuery {
+        id,
+        moves: Vec::new(),
+        initial_stones: Some(stones),
+        initial_player: Some(stone_to_gtp(game.color_on(board, game.to_move)).to_string()),
+        rules: rules.to_string(),
+        komi,
+        board_x_size: size,
+        board_y_size: size,
+        analyze_turns: vec![0],
+        max_visits,
+        include_ownership,
+        allow_moves: None,
+        override_settings: None,
+    })
+}
+
+pub fn stone_to_gtp(stone: Stone) -> &'static str {
+    match stone {
+        Stone::Black => "B",
+        Stone::White => "W",
+    }
+}
+
+pub async fn run_batch(queries: &[AnalysisQuery]) -> io::Result<Vec<AnalysisResponse>> {
+    let engine = shared_engine().await?;
+    let result = engine.analyze_batch(queries).await;
+    if let Err(err) = &result {
+        if matches!(err.kind(), io::ErrorKind::UnexpectedEof | io::ErrorKind::BrokenPipe | io::ErrorKind::TimedOut) {
+            reset_engine(&engine).await;
+        }
+    }
+    result
+}
+
+fn is_forbidden(move_coord: &str, size: u8, forbidden: &HashSet<String>) -> bool {
+    match gtp_to_xy(move_coord, size) {
+        Ok(xy) => forbidden.contains(&xy),
+        Err(_) => false,
+    }
+}
+
+pub async fn genmove(req: &AiGenmoveRequest) -> io::Result<AiGenmoveResponse> {
+    let level = ai_level::resolve(req.level.as_deref())?;
+    let engine = shared_engine().await?;
+    let mut query = build_query(engine.next_query_id("genmove"), req.board_size, req.komi.unwrap_or(7.5), rules_name(req.rules.as_deref())?, &req.next_to_move, &req.moves, false)?;
+    apply_level(&mut query, level);
+    let resp = run_batch(std::slice::from_ref(&query)).await?.remove(0);
+
+    let forbidden: HashSet<String> = req.forbidden.clone().unwrap_or_default().into_iter().collect();
+    let mut infos = resp.move_infos;
+    infos.sort_by_key(|mi| mi.order);
+    let best = infos
+        .into_iter()
+        .find(|mi| !is_forbidden(&mi.move_coord, req.board_size, &forbidden))
+        .map(|mi| mi.move_coord)
+        .unwrap_or_else(|| "pass".to_string());
+    Ok(AiGenmoveResponse { move_coord: best })
+}
+
+fn metric_value(mi: &MoveInfo, metric: &Metric) -> f32 {
+    match metric {
+        Metric::Winrate => mi.winrate as f32,
+        Metric::ScoreLead => mi.score_lead as f32,
+    }
+}
+
+/// 双盘选点：两个现实作为一批查询；A 盘候选若不在 B 盘结果中，再用 allowMoves 在 B 盘补查
+pub async fn genmove_dual(req: &AiDualGenmoveRequest) -> io::Result<AiDualGenmoveResponse> {
+    let engine = shared_engine().await?;
+    let komi = req.komi.unwrap_or(7.5);
+    let k = req.k.unwrap_or(8).clamp(1, 20);
+    let metric = parse_metric(req.metric.as_deref());
+    let forbidden: HashSet<String> = req.forbidden.clone().unwrap_or_default().into_iter().collect();
+    let player = color_to_gtp(&req.next_to_move).to_string();
+    let rules = rules_name(req.rules.as_deref())?;
+
+    let level = ai_level::resolve(req.level.as_deref())?;
+    let mut qa = build_query(engine.next_query_id("dual-a"), req.board_size, komi, rules, &req.next_to_move, &req.board_a_moves, false)?;
+    let mut qb = build_query(engine.next_query_id("dual-b"), req.board_size, komi, rules, &req.next_to_move, &req.board_b_moves, false)?;
+    apply_level(&mut qa, level);
+    apply_level(&mut qb, level);
+    let mut batch = run_batch(&[qa, qb.clone()]).await?;
+    let resp_b = batch.pop().expect("two responses");
+    let resp_a = batch.pop().expect("two responses");
+
+    let mut candidates: Vec<MoveInfo> = resp_a
+        .move_infos
+        .into_iter()
+        .filter(|mi| !is_forbidden(&mi.move_coord, req.board_size, &forbidden))
+        .collect();
+    candidates.sort_by_key(|mi| mi.order);
+    candidates.truncate(k);
+    if candidates.is_empty() {
+        return Ok(AiDualGenmoveResponse { move_coord: "pass".to_string() });
+    }
+
+    let mut b_infos: HashMap<String, MoveInfo> = resp_b
+        .move_infos
+        .into_iter()
+        .map(|mi| (mi.move_coord.clone(), mi))
+        .collect();
+    let missing: Vec<String> = candidates
+        .iter()
+        .map(|mi| mi.move_coord.clone())
+        .filter(|m| !b_infos.contains_key(m))
+        .collect();
+    if !missing.is_empty() {
+        let mut follow_up = qb;
+        follow_up.id = engine.next_query_id("dual-b-allow");
+        follow_up.allow_moves = Some(vec![AllowMoves { player, moves: missing, until_depth: 1 }]);
+        for mi in run_batch(&[follow_up]).await?.remove(0).move_infos {
+            b_infos.entry(mi.move_coord.clone()).or_insert(mi);
+        }
+    }
+
+    let evaluated: Vec<DualCandidate> = candidates
+        .iter()
+        .filter_map(|a| {
+            let b = b_infos.get(&a.move_coord)?;
+            Some(DualCandidate {
+                move_coord: a.move_coord.clone(),
+                a_metric: metric_value(a, &metric),
+                a_winrate: a.winrate as f32,
+                b_metric: metric_value(b, &metric),
+                b_winrate: b.winrate as f32,
+            })
+        })
+        .collect();
+
+    let best = pick_dual_candidate(&evaluated, &metric)
+        .unwrap_or_else(|| candidates[0].move_coord.clone());
+    Ok(AiDualGenmoveResponse { move_coord: best })
+}
+
+const DEFAULT_ANALYZE_VISITS: u32 = 200;
+const DEFAULT_ANALYZE_CANDIDATES: usize = 10;
+const MAX_ANALYZE_CANDIDATES: usize = 30;
+
+#[derive(Debug, Deserialize)]
+pub struct AnalyzeRequest {
+    pub board_size: u8,
+    /// 对局记录（棋盘 A 上的落点），与 /ai/quantum_search 相同
+    pub moves: Vec<QuantumMoveItem>,
+    /// "a" | "b" | "both"（默认 both）
+    pub board: Option<String>,
+    pub komi: Option<f64>,
+    pub rules: Option<String>,
+    pub max_visits: Option<u32>,
+    pub candidates: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CandidateMove {
+    pub move_coord: String,
+    /// "x,y"（pass 时为空）
+    pub position: Option<String>,
+    pub winrate: f64,
+    pub score_lead: f64,
+    pub visits: u64,
+    pub prior: f64,
+    pub pv: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BoardAnalysis {
+    pub board: &'static str,
+    pub to_move: Stone,
+    pub winrate: f64,
+    pub score_lead: f64,
+    pub visits: u64,
+    pub candidates: Vec<CandidateMove>,
+    /// 行优先（x 从上到下），+1 为黑方，-1 为白方
+    pub ownership: Vec<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AnalyzeResponse {
+    pub boards: Vec<BoardAnalysis>,
+}
+
+// Each reality gets half the komi; KataGo only accepts multiples of 0.5, the remainder is applied to scoreLead
+pub(crate) fn split_komi(komi: f64) -> (f32, f64) {
+    let half = komi / 2.0;
+    let rounded = (half * 2.0).round() / 2.0;
+    (rounded as f32, half - rounded)
+}
+
+/// 分析量子对局某一盘或两盘的当前局面
+pub async fn analyze_position(req: AnalyzeRequest) -> io::Result<AnalyzeResponse> {
+    if !matches!(req.board_size, 7 | 9 | 13 | 19) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported board size"));
+    }
+    let boards: &[usize] = match req.board.as_deref().map(str::to_ascii_lowercase).as_deref() {
+        None | Some("both") => &[0, 1],
+        Some("a") => &[0],
+        Some("b") => &[1],
+        Some(other) => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown board: {}", other))),
+    };
+    let game = game_from_moves(req.board_size, &req.moves)?;
+    let rules = rules_name(req.rules.as_deref())?;
+    let visits_cap = std::env::var("KATAGO_ANALYZE_MAX_VISITS").ok().and_then(|v| v.parse().ok()).unwrap_or(DEFAULT_ANALYZE_VISITS);
+    let visits = req.max_visits.unwrap_or(visits_cap).clamp(1, visits_cap);
+    let top = req.candidates.unwrap_or(DEFAULT_ANALYZE_CANDIDATES).clamp(1, MAX_ANALYZE_CANDIDATES);
+    let (board_komi, komi_rest) = split_komi(req.komi.unwrap_or(7.5));
+
+    let engine = shared_engine().await?;
+    let queries = boards
+        .iter()
+        .map(|&b| board_query(engine.next_query_id("analyze"), &game, b, rules, board_komi, Some(visits), true))
+        .collect::<io::Result<Vec<_>>>()?;
+    let responses = run_batch(&queries).await?;
+
+    let size = req.board_size;
+    let to_xy = |m: &str| gtp_to_xy(m, size).ok().filter(|xy| xy != "PASS" && xy != "RESIGN");
+    let result = boards
+        .iter()
+        .zip(responses)
+        .map(|(&b, resp)| {
+            let to_move = game.color_on(b, game.to_move);
+            // Residual komi counts against Black, i.e. against the side to move when that is Black
+            let rest = if to_move == Stone::Black { -komi_rest } else { komi_rest };
+            let mut infos = resp.move_infos;
+            infos.sort_by_key(|mi| mi.order);
+            BoardAnalysis {
+                board: if b == 0 { "a" } else { "b" },
+                to_move,
+                winrate: resp.root_info.winrate,
+                score_lead: resp.root_info.score_lead + rest,
+                visits: resp.root_info.visits,
+                candidates: infos
+                    .into_iter()
+                    .take(top)
+                    .map(|mi| CandidateMove {
+                        position: to_xy(&mi.move_coord),
+                        move_coord: mi.move_coord,
+                        winrate: mi.winrate,
+                        score_lead: mi.score_lead + rest,
+                        visits: mi.visits,
+                        prior: mi.prior,
+                        pv: mi.pv,
+                    })
+                    .collect(),
+                ownership: resp.ownership.unwrap_or_default(),
+            }
+        })
+        .collect();
+    Ok(AnalyzeResponse { boards: result })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(moves: &[(&str, &str)]) -> AnalysisQuery {
+        AnalysisQuery {
+            id: "q".to_string(),
+            moves: moves.iter().map(|(c, m)| (c.to_string(), m.to_string())).collect(),
+            initial_stones: None,
+            initial_player: None,
+            rules: "chinese".to_string(),
+            komi: 7.5,
+            board_x_size: 9,
+            board_y_size: 9,
+            analyze_turns: vec![moves.len()],
+            max_visits: None,
+            include_ownership: false,
+            allow_moves: None,
+            override_settings: None,
+        }
+    }
+
+    #[test]
+    fn test_query_serializes_to_protocol_names() {
+        let mut q = query(&[("B", "E5")]);
+        q.allow_moves = Some(vec![AllowMoves { player: "W".to_string(), moves: vec!["C3".to_string()], until_depth: 1 }]);
+        let v = serde_json::to_value(&q).unwrap();
+        assert_eq!(v["boardXSize"], 9);
+        assert_eq!(v["analyzeTurns"], serde_json::json!([1]));
+        assert_eq!(v["moves"], serde_json::json!([["B", "E5"]]));
+        assert_eq!(v["allowMoves"][0]["untilDepth"], 1);
+        assert!(v.get("maxVisits").is_none());
+    }
+
+    #[test]
+    fn test_dispatch_correlates_by_id_and_flips_perspective() {
+        let pending: Pending = Arc::new(std::sync::Mutex::new(HashMap::new()));
+        let (tx, mut rx) = oneshot::channel();
+        pending.lock().unwrap().insert("q".to_string(), tx);
+
+        dispatch_line(&pending, r#"{"id":"other","moveInfos":[],"rootInfo":{"winrate":0.5,"scoreLead":0,"visits":1}}"#);
+        assert!(rx.try_recv().is_err());
+
+        dispatch_line(&pending, r#"{"id":"q","turnNumber":1,"moveInfos":[{"move":"C3","visits":10,"winrate":0.7,"scoreLead":3.5,"order":0,"pv":["C3","D4"]}],"rootInfo":{"winrate":0.7,"scoreLead":3.5,"visits":10,"currentPlayer":"W"}}"#);
+        let mut resp = rx.try_recv().unwrap().unwrap();
+        assert_eq!(resp.move_infos[0].pv, vec!["C3", "D4"]);
+
+        // Black played last, so White is to move and Black-perspective numbers flip
+        to_side_to_move(&mut resp, side_to_move(&query(&[("B", "E5")])));
+        assert!((resp.move_infos[0].winrate - 0.3).abs() < 1e-9);
+        assert_eq!(resp.root_info.score_lead, -3.5);
+    }
+
+    #[test]
+    fn test_split_komi_keeps_total() {
+        let (per_board, rest) = split_komi(7.5);
+        assert_eq!(per_board, 4.0);
+        assert!((2.0 * (per_board as f64 + rest) - 7.5).abs() < 1e-9);
+        assert_eq!(split_komi(6.0), (3.0, 0.0));
+    }
+
+    #[test]
+    fn test_board_query_uses_quantum_position() {
+        let mut game = QuantumGame::new(9);
+        game.play(game.index_of("1,1").unwrap()).unwrap();
+        // After Black's first move White moves next, but on board B the colours are swapped
+        let qb = board_query("b".to_string(), &game, 1, "chinese", 4.0, Some(10), true).unwrap();
+        assert_eq!(qb.initial_stones, Some(vec![("W".to_string(), "A9".to_string())]));
+        assert_eq!(qb.initial_player.as_deref(), Some("B"));
+        assert_eq!(side_to_move(&qb), "B");
+    }
+
+    #[test]
+    fn test_dispatch_reports_errors() {
+        let pending: Pending = Arc::new(std::sync::Mutex::new(HashMap::new()));
+        let (tx, mut rx) = oneshot::channel();
+        pending.lock().unwrap().insert("bad".to_string(), tx);
+        dispatch_line(&pending, r#"{"id":"bad","error":"Illegal move","field":"moves"}"#);
+        assert!(rx.try_recv().unwrap().is_err());
+    }
+}
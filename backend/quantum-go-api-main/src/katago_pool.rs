@@ -0,0 +1,534 @@
+// KataGo 引擎池：每个（棋盘尺寸, 难度档位）最多 N 个引擎进程，请求排队借用
+//
+// - 借用前先看排队深度，超过上限直接拒绝（WouldBlock → 503）
+// - 排队等待有超时（TimedOut → 503）
+// - 整个请求也有超时；超时或出错时引擎可能停在半条回复上，归还时直接丢弃（进程随之被杀掉）
+// - 每条 GTP 命令有回复期限；后台定期用 name / version 探测空闲引擎，无回复的杀掉重启
+// - 引擎连续出故障时，重启按指数退避等待，避免崩溃的进程被反复拉起
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, OwnedSemaphorePermit, Semaphore};
+
+use crate::ai_level::{self, AiLevel};
+use crate::katago::{EngineCommand, EngineTimeouts, KataGoEngine};
+
+const DEFAULT_POOL_SIZE: usize = 2;
+const DEFAULT_QUEUE_LIMIT: usize = 32;
+const DEFAULT_CHECKOUT_TIMEOUT_MS: u64 = 30_000;
+const DEFAULT_REQUEST_TIMEOUT_MS: u64 = 60_000;
+const DEFAULT_COMMAND_TIMEOUT_MS: u64 = 10_000;
+const DEFAULT_SEARCH_TIMEOUT_MS: u64 = 30_000;
+const DEFAULT_STARTUP_TIMEOUT_MS: u64 = 120_000;
+const DEFAULT_RESTART_BACKOFF_MS: u64 = 500;
+const DEFAULT_MAX_RESTART_BACKOFF_MS: u64 = 30_000;
+const DEFAULT_HEALTH_INTERVAL_SECS: u64 = 30;
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(default)
+}
+
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    pub capacity: usize,
+    pub queue_limit: usize,
+    pub checkout_timeout: Duration,
+    pub request_timeout: Duration,
+    pub timeouts: EngineTimeouts,
+    // Warm up engines spawned ahead of demand (startup prewarm, replacements after a failed probe)
+    pub warmup: bool,
+    pub restart_backoff: Duration,
+    pub max_restart_backoff: Duration,
+    // Serve repeated positions from the analysis cache
+    pub analysis_cache: bool,
+    // None: build the KataGo command line from KATAGO_* at spawn time
+    pub command: Option<EngineCommand>,
+}
+
+impl PoolConfig {
+    // KATAGO_POOL_SIZE / KATAGO_QUEUE_LIMIT / KATAGO_CHECKOUT_TIMEOUT_MS / KATAGO_REQUEST_TIMEOUT_MS
+    // KATAGO_COMMAND_TIMEOUT_MS / KATAGO_SEARCH_TIMEOUT_MS / KATAGO_STARTUP_TIMEOUT_MS / KATAGO_WARMUP
+    // KATAGO_RESTART_BACKOFF_MS / KATAGO_MAX_RESTART_BACKOFF_MS
+    pub fn from_env() -> Self {
+        Self {
+            capacity: env_or("KATAGO_POOL_SIZE", DEFAULT_POOL_SIZE).max(1),
+            queue_limit: env_or("KATAGO_QUEUE_LIMIT", DEFAULT_QUEUE_LIMIT),
+            checkout_timeout: Duration::from_millis(env_or("KATAGO_CHECKOUT_TIMEOUT_MS", DEFAULT_CHECKOUT_TIMEOUT_MS)),
+            request_timeout: Duration::from_millis(env_or("KATAGO_REQUEST_TIMEOUT_MS", DEFAULT_REQUEST_TIMEOUT_MS)),
+            timeouts: EngineTimeouts {
+                command: Duration::from_millis(env_or("KATAGO_COMMAND_TIMEOUT_MS", DEFAULT_COMMAND_TIMEOUT_MS)),
+                search: Duration::from_millis(env_or("KATAGO_SEARCH_TIMEOUT_MS", DEFAULT_SEARCH_TIMEOUT_MS)),
+                startup: Duration::from_millis(env_or("KATAGO_STARTUP_TIMEOUT_MS", DEFAULT_STARTUP_TIMEOUT_MS)),
+            },
+            warmup: env_or("KATAGO_WARMUP", true),
+            restart_backoff: Duration::from_millis(env_or("KATAGO_RESTART_BACKOFF_MS", DEFAULT_RESTART_BACKOFF_MS)),
+            max_restart_backoff: Duration::from_millis(env_or("KATAGO_MAX_RESTART_BACKOFF_MS", DEFAULT_MAX_RESTART_BACKOFF_MS)),
+            analysis_cache: true,
+            command: None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct PoolCounters {
+    checkouts: AtomicU64,
+    rejected: AtomicU64,
+    timeouts: AtomicU64,
+    discarded: AtomicU64,
+    spawned: AtomicU64,
+    total_wait_us: AtomicU64,
+    max_wait_us: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PoolStats {
+    pub board_size: u8,
+    pub level: &'static str,
+    pub capacity: usize,
+    pub idle: usize,
+    pub in_use: usize,
+    pub waiting: usize,
+    pub checkouts: u64,
+    pub rejected: u64,
+    pub timeouts: u64,
+    pub discarded: u64,
+    pub spawned: u64,
+    pub avg_wait_ms: f64,
+    pub max_wait_ms: f64,
+}
+
+/// 单个引擎进程的状态（/health）
+#[derive(Debug, Clone, Serialize)]
+pub struct EngineStatus {
+    pub id: u64,
+    pub pid: Option<u32>,
+    pub name: String,
+    pub version: String,
+    pub in_use: bool,
+    pub uptime_secs: u64,
+    pub last_probe_ok: Option<bool>,
+    pub last_probe_secs_ago: Option<u64>,
+}
+
+/// 引擎池的健康状况：重启退避中视为不健康
+#[derive(Debug, Serialize)]
+pub struct PoolHealth {
+    pub board_size: u8,
+    pub level: &'static str,
+    pub healthy: bool,
+    pub consecutive_failures: u32,
+    pub restart_in_ms: u64,
+    pub last_error: Option<String>,
+    pub engines: Vec<EngineStatus>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    pub status: &'static str,
+    pub pools: Vec<PoolHealth>,
+}
+
+struct EngineRecord {
+    status: EngineStatus,
+    started: Instant,
+    last_probe: Option<Instant>,
+}
+
+#[derive(Default)]
+struct RestartState {
+    failures: u32,
+    retry_at: Option<Instant>,
+    last_error: Option<String>,
+}
+
+pub struct EnginePool {
+    board_size: u8,
+    level: &'static AiLevel,
+    config: PoolConfig,
+    permits: Arc<Semaphore>,
+    idle: std::sync::Mutex<Vec<KataGoEngine>>,
+    engines: std::sync::Mutex<HashMap<u64, EngineRecord>>,
+    restart: std::sync::Mutex<RestartState>,
+    waiting: AtomicUsize,
+    counters: PoolCounters,
+}
+
+impl EnginePool {
+    pub fn new(board_size: u8, level: &'static AiLevel, config: PoolConfig) -> Self {
+        Self {
+            board_size,
+            level,
+            permits: Arc::new(Semaphore::new(config.capacity)),
+            config,
+            idle: std::sync::Mutex::new(Vec::new()),
+            engines: std::sync::Mutex::new(HashMap::new()),
+            restart: std::sync::Mutex::new(RestartState::default()),
+            waiting: AtomicUsize::new(0),
+            counters: PoolCounters::default(),
+        }
+    }
+
+    pub fn request_timeout(&self) -> Duration {
+        self.config.request_timeout
+    }
+
+    /// 借出一个引擎；没有空闲引擎时在容量内新建
+    pub async fn checkout(self: &Arc<Self>) -> io::Result<PooledEngine> {
+        let queued = self.waiting.fetch_add(1, Ordering::SeqCst);
+        if queued >= self.config.queue_limit {
+            self.waiting.fetch_sub(1, Ordering::SeqCst);
+            self.counters.rejected.fetch_add(1, Ordering::Relaxed);
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, "katago queue is full"));
+        }
+
+        let started = Instant::now();
+        let acquired = tokio::time::timeout(
+            self.config.checkout_timeout,
+            self.permits.clone().acquire_owned(),
+        )
+        .await;
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+        let permit = match acquired {
+            Ok(Ok(permit)) => permit,
+            Ok(Err(_)) => return Err(io::Error::other("katago pool closed")),
+            Err(_) => {
+                self.counters.timeouts.fetch_add(1, Ordering::Relaxed);
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "timed out waiting for a katago engine"));
+            }
+        };
+        self.record_wait(started.elapsed());
+
+        let idle = self.idle.lock().unwrap().pop();
+        let engine = match idle {
+            Some(engine) => {
+                self.set_in_use(engine.id(), true);
+                engine
+            }
+            None => self.spawn_engine(false).await?,
+        };
+        Ok(PooledEngine {
+            engine: Some(engine),
+            pool: self.clone(),
+            _permit: permit,
+        })
+    }
+
+    /// 启动一个新进程（登记为借出状态）；连续失败后先等退避时间
+    async fn spawn_engine(&self, warm_up: bool) -> io::Result<KataGoEngine> {
+        let retry_at = self.restart.lock().unwrap().retry_at;
+        if let Some(wait) = retry_at.map(|at| at.saturating_duration_since(Instant::now())).filter(|w| !w.is_zero()) {
+            tracing::debug!("katago {}x{} ({}) restart backoff {:?}", self.board_size, self.board_size, self.level.id, wait);
+            tokio::time::sleep(wait).await;
+        }
+        match self.start_engine(warm_up).await {
+            Ok(engine) => {
+                self.counters.spawned.fetch_add(1, Ordering::Relaxed);
+                let status = EngineStatus {
+                    id: engine.id(),
+                    pid: engine.pid(),
+                    name: engine.name().to_string(),
+                    version: engine.version().to_string(),
+                    in_use: true,
+                    uptime_secs: 0,
+                    last_probe_ok: None,
+                    last_probe_secs_ago: None,
+                };
+                let record = EngineRecord { status, started: Instant::now(), last_probe: None };
+                self.engines.lock().unwrap().insert(engine.id(), record);
+                Ok(engine)
+            }
+            Err(err) => {
+                self.record_failure(&format!("spawn failed: {}", err));
+                Err(err)
+            }
+        }
+    }
+
+    async fn start_engine(&self, warm_up: bool) -> io::Result<KataGoEngine> {
+        let mut engine = match &self.config.command {
+            Some(command) => KataGoEngine::spawn(command, self.config.timeouts.clone()).await?,
+            None => KataGoEngine::spawn(&EngineCommand::for_level(self.level)?, self.config.timeouts.clone()).await?,
+        };
+        if warm_up {
+            engine.warm_up(self.board_size).await?;
+        }
+        Ok(engine)
+    }
+
+    fn record_failure(&self, error: &str) {
+        let mut restart = self.restart.lock().unwrap();
+        restart.failures += 1;
+        let backoff = self
+            .config
+            .restart_backoff
+            .saturating_mul(1 << (restart.failures - 1).min(16))
+            .min(self.config.max_restart_backoff);
+        restart.retry_at = Some(Instant::now() + backoff);
+        restart.last_error = Some(error.to_string());
+        tracing::warn!(
+            "katago {}x{} ({}) engine failure #{}: {}; next restart in {:?}",
+            self.board_size, self.board_size, self.level.id, restart.failures, error, backoff
+        );
+    }
+
+    fn record_healthy(&self) {
+        let mut restart = self.restart.lock().unwrap();
+        restart.failures = 0;
+        restart.retry_at = None;
+    }
+
+    fn set_in_use(&self, id: u64, in_use: bool) {
+        if let Some(record) = self.engines.lock().unwrap().get_mut(&id) {
+            record.status.in_use = in_use;
+        }
+    }
+
+    // Dropping the engine kills its process; a broken engine also counts towards the restart backoff
+    fn discard(&self, engine: KataGoEngine, broken: bool) {
+        self.counters.discarded.fetch_add(1, Ordering::Relaxed);
+        self.engines.lock().unwrap().remove(&engine.id());
+        if broken {
+            self.record_failure(&format!("engine {} stopped responding", engine.id()));
+        }
+    }
+
+    fn push_idle(&self, engine: KataGoEngine) {
+        self.set_in_use(engine.id(), false);
+        self.idle.lock().unwrap().push(engine);
+    }
+
+    fn record_wait(&self, wait: Duration) {
+        let us = wait.as_micros() as u64;
+        self.counters.checkouts.fetch_add(1, Ordering::Relaxed);
+        self.counters.total_wait_us.fetch_add(us, Ordering::Relaxed);
+        self.counters.max_wait_us.fetch_max(us, Ordering::Relaxed);
+        tracing::debug!("katago {}x{} ({}) checkout waited {:?}", self.board_size, self.board_size, self.level.id, wait);
+    }
+
+    fn checkin(&self, engine: KataGoEngine) {
+        if engine.is_mid_reply() {
+            // 回复没读完（超时被取消或读出错），流已不同步，不能复用
+            self.discard(engine, true);
+            return;
+        }
+        self.record_healthy();
+        self.push_idle(engine);
+    }
+
+    /// 逐个探测空闲引擎；没有按时回复的杀掉，并（按退避）补一个预热过的新进程
+    pub async fn probe_idle(&self) {
+        let count = self.idle.lock().unwrap().len();
+        for _ in 0..count {
+            // Probe under a permit so the engine counts against capacity just like a checkout
+            let Ok(_permit) = self.permits.clone().try_acquire_owned() else { return };
+            let mut engine = {
+                let mut idle = self.idle.lock().unwrap();
+                if idle.is_empty() {
+                    return;
+                }
+                idle.remove(0)
+            };
+            let result = engine.probe().await;
+            if let Some(record) = self.engines.lock().unwrap().get_mut(&engine.id()) {
+                record.last_probe = Some(Instant::now());
+                record.status.last_probe_ok = Some(result.is_ok());
+            }
+            match result {
+                Ok(()) => self.checkin(engine),
+                Err(err) => {
+                    tracing::warn!("katago engine {} failed health probe: {}", engine.id(), err);
+                    self.discard(engine, true);
+                    match self.spawn_engine(self.config.warmup).await {
+                        Ok(fresh) => self.push_idle(fresh),
+                        Err(err) => tracing::warn!("katago engine restart failed: {}", err),
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn health(&self) -> PoolHealth {
+        let now = Instant::now();
+        let mut engines: Vec<EngineStatus> = self
+            .engines
+            .lock()
+            .unwrap()
+            .values()
+            .map(|record| EngineStatus {
+                uptime_secs: now.duration_since(record.started).as_secs(),
+                last_probe_secs_ago: record.last_probe.map(|at| now.duration_since(at).as_secs()),
+                ..record.status.clone()
+            })
+            .collect();
+        engines.sort_by_key(|e| e.id);
+        let restart = self.restart.lock().unwrap();
+        PoolHealth {
+            board_size: self.board_size,
+            level: self.level.id,
+            healthy: restart.failures == 0,
+            consecutive_failures: restart.failures,
+            restart_in_ms: restart.retry_at.map(|at| at.saturating_duration_since(now).as_millis() as u64).unwrap_or(0),
+            last_error: restart.last_error.clone(),
+            engines,
+        }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let idle = self.idle.lock().unwrap().len();
+        let checkouts = self.counters.checkouts.load(Ordering::Relaxed);
+        let total_wait_us = self.counters.total_wait_us.load(Ordering::Relaxed);
+        PoolStats {
+            board_size: self.board_size,
+            level: self.level.id,
+            capacity: self.config.capacity,
+            idle,
+            in_use: self.config.capacity - self.permits.available_permits(),
+            waiting: self.waiting.load(Ordering::SeqCst),
+            checkouts,
+            rejected: self.counters.rejected.load(Ordering::Relaxed),
+            timeouts: self.counters.timeouts.load(Ordering::Relaxed),
+            discarded: self.counters.discarded.load(Ordering::Relaxed),
+            spawned: self.counters.spawned.load(Ordering::Relaxed),
+            avg_wait_ms: if checkouts == 0 { 0.0 } else { total_wait_us as f64 / checkouts as f64 / 1000.0 },
+            max_wait_ms: self.counters.max_wait_us.load(Ordering::Relaxed) as f64 / 1000.0,
+        }
+    }
+}
+
+/// 借出的引擎；drop 时自动归还（或在状态不一致时丢弃）
+pub struct PooledEngine {
+    engine: Option<KataGoEngine>,
+    pool: Arc<EnginePool>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl PooledEngine {
+    pub fn level(&self) -> &'static AiLevel {
+        self.pool.level
+    }
+
+    pub fn caches_analysis(&self) -> bool {
+        self.pool.config.analysis_cache
+    }
+
+    /// 丢弃当前进程并换一个新的（换随机种子或从错误中恢复）
+    pub async fn respawn(&mut self) -> io::Result<()> {
+        if let Some(engine) = self.engine.take() {
+            let broken = engine.is_mid_reply();
+            self.pool.discard(engine, broken);
+        }
+        self.engine = Some(self.pool.spawn_engine(false).await?);
+        Ok(())
+    }
+}
+
+impl Deref for PooledEngine {
+    type Target = KataGoEngine;
+    fn deref(&self) -> &KataGoEngine {
+        self.engine.as_ref().expect("engine present while checked out")
+    }
+}
+
+impl DerefMut for PooledEngine {
+    fn deref_mut(&mut self) -> &mut KataGoEngine {
+        self.engine.as_mut().expect("engine present while checked out")
+    }
+}
+
+impl Drop for PooledEngine {
+    fn drop(&mut self) {
+        if let Some(engine) = self.engine.take() {
+            self.pool.checkin(engine);
+        }
+    }
+}
+
+// (board size, level id)
+type PoolKey = (u8, &'static str);
+
+static ENGINE_POOLS: Lazy<Mutex<HashMap<PoolKey, Arc<EnginePool>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 获取（必要时创建）某个棋盘尺寸、难度档位的引擎池
+pub async fn pool_for(board_size: u8, level: &'static AiLevel) -> Arc<EnginePool> {
+    let mut pools = ENGINE_POOLS.lock().await;
+    pools
+        .entry((board_size, level.id))
+        .or_insert_with(|| Arc::new(EnginePool::new(board_size, level, PoolConfig::from_env())))
+        .clone()
+}
+
+pub async fn all_stats() -> Vec<PoolStats> {
+    let pools = ENGINE_POOLS.lock().await;
+    let mut stats: Vec<PoolStats> = pools.values().map(|p| p.stats()).collect();
+    stats.sort_by_key(|s| (s.board_size, s.level));
+    stats
+}
+
+pub async fn health_report() -> HealthReport {
+    let pools = ENGINE_POOLS.lock().await;
+    let mut health: Vec<PoolHealth> = pools.values().map(|p| p.health()).collect();
+    health.sort_by_key(|h| (h.board_size, h.level));
+    let status = if health.iter().all(|h| h.healthy) { "ok" } else { "degraded" };
+    HealthReport { status, pools: health }
+}
+
+// KATAGO_PREWARM: comma-separated "size" or "size:level" entries, e.g. "9,19:advanced"
+fn prewarm_targets() -> Vec<(u8, &'static AiLevel)> {
+    let spec = std::env::var("KATAGO_PREWARM").unwrap_or_default();
+    spec.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            let (size, level) = match entry.split_once(':') {
+                Some((size, level)) => (size, Some(level.trim())),
+                None => (entry, None),
+            };
+            match (size.trim().parse::<u8>(), ai_level::resolve(level)) {
+                (Ok(size), Ok(level)) => Some((size, level)),
+                _ => {
+                    tracing::warn!("ignoring KATAGO_PREWARM entry {:?}", entry);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// 后台任务：启动时按 KATAGO_PREWARM 预热引擎，之后每 KATAGO_HEALTH_INTERVAL_SECS 秒探测一次空闲引擎（0 关闭）
+pub fn spawn_health_checks() {
+    let interval = env_or("KATAGO_HEALTH_INTERVAL_SECS", DEFAULT_HEALTH_INTERVAL_SECS);
+    tokio::spawn(async move {
+        for (size, level) in prewarm_targets() {
+            let pool = pool_for(size, level).await;
+            match pool.spawn_engine(pool.config.warmup).await {
+                Ok(engine) => pool.push_idle(engine),
+                Err(err) => tracing::warn!("katago {}x{} ({}) prewarm failed: {}", size, size, level.id, err),
+            }
+        }
+        if interval == 0 {
+            return;
+        }
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        loop {
+            ticker.tick().await;
+            let pools: Vec<Arc<EnginePool>> = ENGINE_POOLS.lock().await.values().cloned().collect();
+            for pool in pools {
+                pool.probe_idle().await;
+            }
+        }
+    });
+}
+
+/// 排队已满或等待超时，调用方应返回 503
+pub fn is_overloaded(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
@@ -0,0 +1,31 @@
+// 服务端各模块；main.rs 只负责启动，集成测试（tests/）也通过本库访问内部接口
+pub mod ai_level;
+pub mod ai_room;
+pub mod analysis_cache;
+pub mod api;
+pub mod bot_runner;
+pub mod chat;
+pub mod db;
+pub mod entity;
+#[cfg(test)]
+mod estimator_regression;
+#[cfg(any(test, feature = "rust-estimator"))]
+pub mod goban_estimator;
+pub mod handicap;
+pub mod jwt;
+pub mod katago;
+pub mod katago_analysis;
+pub mod katago_pool;
+pub mod matchmaking;
+pub mod negotiation;
+pub mod outcome;
+pub mod quantum;
+pub mod quantum_estimate;
+pub mod quantum_search;
+pub mod rating;
+pub mod review;
+pub mod rules;
+pub mod score_estimator;
+pub mod scoring;
+pub mod seating;
+pub mod ws;
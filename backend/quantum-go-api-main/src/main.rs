@@ -2,7 +2,6 @@ use axum::{
     Router,
     routing::{any, get, post},
 };
-use db::Database;
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::{env, net::SocketAddr, path::PathBuf};
@@ -16,14 +15,8 @@ use tower_http::{
 use tracing::info;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-mod api;
-mod db;
-mod entity;
-mod jwt;
-mod katago;
-mod rating;
-mod score_estimator;
-mod ws;
+use quantum_go_api::db::Database;
+use quantum_go_api::{analysis_cache, api, bot_runner, katago_pool, matchmaking, ws};
 
 #[tokio::main]
 async fn main() {
@@ -57,6 +50,10 @@ async fn main() {
         db: Arc::new(database),
     };
 
+    let runner_db = state.db.clone();
+    matchmaking::spawn(state.db.clone());
+    analysis_cache::init(state.db.clone());
+
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
@@ -66,6 +63,7 @@ async fn main() {
     let app = Router::new()
         .fallback_service(ServeDir::new(assets_dir).append_index_html_on_directories(true))
         .route("/", get(|| async { "active" }))
+        .route("/health", get(api::health))
         .route("/createRoom", post(api::create_room))
         .route("/getGameInfo", post(api::get_game_info))
         .route("/userRegister", post(api::register))
@@ -75,9 +73,18 @@ async fn main() {
         .route("/getUserProfile", post(api::get_user_profile))
         .route("/lobby/listRooms", post(api::list_rooms))
         .route("/user/recentRooms", post(api::recent_rooms))
+        .route("/match/queue", post(api::match_queue))
+        .route("/match/cancel", post(api::match_cancel))
         .route("/ai/genmove", post(api::ai_genmove))
         .route("/ai/genmove_dual", post(api::ai_genmove_dual))
         .route("/ai/score_estimate", post(api::score_estimate))
+        .route("/ai/pool_stats", get(api::ai_pool_stats))
+        .route("/ai/cache_stats", get(api::ai_cache_stats))
+        .route("/ai/levels", get(api::ai_levels))
+        .route("/ai/quantum_search", post(api::ai_quantum_search))
+        .route("/ai/analyze", post(api::ai_analyze))
+        .route("/game/review", post(api::game_review))
+        .route("/game/review/status", post(api::game_review_status))
         .route("/ws/{user_id}/{room_id}", any(ws::ws_handler))
         .with_state(state)
         // logging so we can see what's going on
@@ -94,6 +101,16 @@ async fn main() {
     let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await.unwrap();
     tracing::debug!("listening on {}", listener.local_addr().unwrap());
 
+    // KataGo 引擎预热与定期健康探测
+    katago_pool::spawn_health_checks();
+
+    // 大厅机器人以普通 WebSocket 客户端身份连接本服务
+    match bot_runner::RunnerConfig::from_env(&port) {
+        Ok(Some(config)) => bot_runner::spawn(runner_db, config),
+        Ok(None) => {}
+        Err(err) => tracing::warn!("bot runner disabled: {}", err),
+    }
+
     let shutdown = async {
         let ctrl_c = async {
             signal::ctrl_c()
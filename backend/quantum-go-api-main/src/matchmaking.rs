@@ -0,0 +1,362 @@
+// 自动匹配队列（HTTP 长轮询）
+//
+// - POST /match/queue {user_id, model, time_control, rated}：加入或刷新排队，最长挂起 LONG_POLL；
+//   匹配成功返回 {status: "matched", room_id, color, ...}，否则返回 {status: "waiting"}，客户端随即再次请求
+// - POST /match/cancel {user_id}：退出排队（已在建房的配对不能取消）
+// - 匹配任务每 MATCH_INTERVAL 运行一次：棋盘尺寸、计分与否、用时设置都相同的玩家之间，
+//   按 user_rankings 中该尺寸的评分配对（该尺寸尚未下过棋时用其它尺寸的综合评分）；可接受的分差随等待时间放宽（window），取双方中较宽的一个
+// - 配对后建房：等待较久的一方为房主，颜色随机，访客席位预先占好，不进入大厅；双方再以 /ws 连接开始对局
+// 超过 STALE_AFTER 没有轮询的玩家视为离开，从队列移除。
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::db::Database;
+use crate::entity::{RoomInfo, UserRanking};
+use crate::handicap::initial_board;
+use crate::quantum::Stone;
+use crate::rating::seed_for_model;
+use crate::rules::Ruleset;
+use crate::seating::{self, ColorChoice, Players};
+
+const LONG_POLL: Duration = Duration::from_secs(25);
+const STALE_AFTER: Duration = Duration::from_secs(60);
+// Unclaimed matches are kept this long for a late poll
+const MATCH_TTL: Duration = Duration::from_secs(600);
+const MATCH_INTERVAL: Duration = Duration::from_secs(2);
+// Rating window: BASE_WINDOW at first, widening by WIDEN_PER_SEC up to MAX_WINDOW
+const BASE_WINDOW: f64 = 100.0;
+const WIDEN_PER_SEC: f64 = 5.0;
+const MAX_WINDOW: f64 = 600.0;
+const DEFAULT_RATING: f64 = 1500.0;
+
+/// 排队条件
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueRequest {
+    pub user_id: Uuid,
+    pub model: i32,
+    pub time_control: Option<Value>,
+    pub rated: bool,
+}
+
+struct Entry {
+    request: QueueRequest,
+    rating: f64,
+    joined_at: Instant,
+    last_seen: Instant,
+    // A room is being created for this entry
+    matching: bool,
+    notify: Arc<Notify>,
+}
+
+/// 匹配结果（对每位玩家给出自己的颜色与对手）
+#[derive(Debug, Clone, Serialize)]
+pub struct MatchFound {
+    pub room_id: Uuid,
+    pub color: Stone,
+    pub opponent_id: Uuid,
+    pub opponent_rating: f64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum MatchStatus {
+    Waiting { waited_secs: u64, window: f64 },
+    Matched(MatchFound),
+}
+
+#[derive(Default)]
+struct Queue {
+    waiting: Vec<Entry>,
+    matched: HashMap<Uuid, (MatchFound, Instant)>,
+}
+
+static QUEUE: Lazy<Mutex<Queue>> = Lazy::new(|| Mutex::new(Queue::default()));
+
+fn window(waited: Duration) -> f64 {
+    (BASE_WINDOW + WIDEN_PER_SEC * waited.as_secs_f64()).min(MAX_WINDOW)
+}
+
+fn compatible(a: &Entry, b: &Entry, now: Instant) -> bool {
+    let (x, y) = (&a.request, &b.request);
+    if x.user_id == y.user_id || x.model != y.model || x.rated != y.rated || x.time_control != y.time_control {
+        return false;
+    }
+    let allowed = window(now - a.joined_at).max(window(now - b.joined_at));
+    (a.rating - b.rating).abs() <= allowed
+}
+
+// Longest-waiting players first, each with the closest-rated compatible partner
+fn pair(entries: &[Entry], now: Instant) -> Vec<(usize, usize)> {
+    let mut order: Vec<usize> = (0..entries.len()).filter(|&i| !entries[i].matching).collect();
+    order.sort_by_key(|&i| entries[i].joined_at);
+    let mut taken = vec![false; entries.len()];
+    let mut pairs = Vec::new();
+    for &i in &order {
+        if taken[i] {
+            continue;
+        }
+        let partner = order
+            .iter()
+            .copied()
+            .filter(|&j| j != i && !taken[j] && compatible(&entries[i], &entries[j], now))
+            .min_by(|&j, &k| {
+                let diff = |x: usize| (entries[x].rating - entries[i].rating).abs();
+                diff(j).total_cmp(&diff(k))
+            });
+        if let Some(j) = partner {
+            taken[i] = true;
+            taken[j] = true;
+            pairs.push((i, j));
+        }
+    }
+    pairs
+}
+
+// 排队评分：该尺寸已有对局时用其评分，否则与首局建档一样用其它尺寸的综合评分
+fn queue_rating(rankings: &[UserRanking], model: i32) -> f64 {
+    match rankings.iter().find(|r| r.model == model && r.games_played > 0) {
+        Some(ranking) => ranking.rating,
+        None => seed_for_model(rankings, model).map_or(DEFAULT_RATING, |(rating, _, _)| rating),
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.to_string())
+}
+
+/// 加入或刷新排队，并等待匹配（最长 LONG_POLL）
+pub async fn poll(db: &Database, request: QueueRequest) -> io::Result<MatchStatus> {
+    if !matches!(request.model, 7 | 9 | 13 | 19) {
+        return Err(invalid("Invalid model size"));
+    }
+    let user_id = request.user_id;
+    if let Some((found, _)) = QUEUE.lock().unwrap().matched.remove(&user_id) {
+        return Ok(MatchStatus::Matched(found));
+    }
+    db.get_user_by_user_id(user_id)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::NotFound, "user not found"))?;
+    let rankings = db.list_user_rankings(&user_id).await.unwrap_or_default();
+    let rating = queue_rating(&rankings, request.model);
+
+    let notify = {
+        let mut queue = QUEUE.lock().unwrap();
+        let now = Instant::now();
+        match queue.waiting.iter_mut().find(|e| e.request.user_id == user_id) {
+            // Same request (or already being placed): keep the place in the queue
+            Some(entry) if entry.matching || entry.request == request => {
+                entry.last_seen = now;
+                entry.notify.clone()
+            }
+            existing => {
+                let notify = Arc::new(Notify::new());
+                let entry = Entry { request, rating, joined_at: now, last_seen: now, matching: false, notify: notify.clone() };
+                match existing {
+                    Some(old) => *old = entry,
+                    None => queue.waiting.push(entry),
+                }
+                notify
+            }
+        }
+    };
+
+    let _ = tokio::time::timeout(LONG_POLL, notify.notified()).await;
+    let mut queue = QUEUE.lock().unwrap();
+    if let Some((found, _)) = queue.matched.remove(&user_id) {
+        return Ok(MatchStatus::Matched(found));
+    }
+    let waited = queue
+        .waiting
+        .iter()
+        .find(|e| e.request.user_id == user_id)
+        .map(|e| e.joined_at.elapsed())
+        .unwrap_or_default();
+    Ok(MatchStatus::Waiting { waited_secs: waited.as_secs(), window: window(waited) })
+}
+
+/// 退出排队；已配对正在建房时返回 false
+pub fn cancel(user_id: Uuid) -> bool {
+    let mut queue = QUEUE.lock().unwrap();
+    let before = queue.waiting.len();
+    queue.waiting.retain(|e| e.request.user_id != user_id || e.matching);
+    queue.waiting.len() < before
+}
+
+fn new_room(owner: &QueueRequest, visitor: Uuid, players: Players) -> RoomInfo {
+    RoomInfo {
+        id: 0,
+        room_id: Uuid::new_v4(),
+        owner_id: owner.user_id,
+        visitor_id: Some(visitor),
+        black_id: players.black_id,
+        white_id: players.white_id,
+        status: "waiting".to_string(),
+        round: "black".to_string(),
+        winner: None,
+        board: initial_board(owner.model, 0),
+        countdown: 30,
+        moves: 0,
+        black_lost: 0,
+        white_lost: 0,
+        model: owner.model,
+        chessman_records: Value::Array(vec![]),
+        phase: None,
+        komi: 7.5,
+        handicap: 0,
+        time_control: owner.time_control.clone(),
+        is_public: false,
+        is_listed: false,
+        allow_spectate: true,
+        ai_level: None,
+        rated: owner.rated,
+        rules: Ruleset::Quantum.id().to_string(),
+        result: None,
+        events: Value::Array(vec![]),
+        created_at: chrono::Utc::now(),
+        last_activity_at: chrono::Utc::now(),
+    }
+}
+
+// One pairing round: drop stale entries, pick pairs, create their rooms
+async fn match_round(db: &Database) {
+    let pairs: Vec<((QueueRequest, f64), (QueueRequest, f64))> = {
+        let mut queue = QUEUE.lock().unwrap();
+        let now = Instant::now();
+        queue.waiting.retain(|e| e.matching || now - e.last_seen < STALE_AFTER);
+        queue.matched.retain(|_, (_, at)| now - *at < MATCH_TTL);
+        let pairs = pair(&queue.waiting, now);
+        for &(i, j) in &pairs {
+            queue.waiting[i].matching = true;
+            queue.waiting[j].matching = true;
+        }
+        pairs
+            .iter()
+            .map(|&(i, j)| {
+                let (a, b) = (&queue.waiting[i], &queue.waiting[j]);
+                ((a.request.clone(), a.rating), (b.request.clone(), b.rating))
+            })
+            .collect()
+    };
+
+    for ((owner, owner_rating), (visitor, visitor_rating)) in pairs {
+        let players = Players::on_create(ColorChoice::Random, owner.user_id, Some(visitor.user_id), rand::random());
+        let created = db.create_room(&new_room(&owner, visitor.user_id, players)).await;
+
+        let mut queue = QUEUE.lock().unwrap();
+        let ids = [owner.user_id, visitor.user_id];
+        match created {
+            Ok(room) => {
+                info!("matched {} and {} in room {}", owner.user_id, visitor.user_id, room.room_id);
+                let found = |me: Uuid, opponent: Uuid, opponent_rating: f64| MatchFound {
+                    room_id: room.room_id,
+                    color: seating::color_of(&room, me),
+                    opponent_id: opponent,
+                    opponent_rating,
+                };
+                let now = Instant::now();
+                queue.matched.insert(owner.user_id, (found(owner.user_id, visitor.user_id, visitor_rating), now));
+                queue.matched.insert(visitor.user_id, (found(visitor.user_id, owner.user_id, owner_rating), now));
+                for entry in queue.waiting.iter().filter(|e| ids.contains(&e.request.user_id)) {
+                    entry.notify.notify_one();
+                }
+                queue.waiting.retain(|e| !ids.contains(&e.request.user_id));
+            }
+            Err(err) => {
+                // Back into the queue with their original waiting time
+                info!("failed to create matched room: {}", err);
+                for entry in queue.waiting.iter_mut().filter(|e| ids.contains(&e.request.user_id)) {
+                    entry.matching = false;
+                }
+            }
+        }
+    }
+}
+
+/// 在后台启动匹配任务
+pub fn spawn(db: Arc<Database>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(MATCH_INTERVAL);
+        loop {
+            ticker.tick().await;
+            match_round(&db).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn entry(model: i32, rating: f64, waited: u64, now: Instant) -> Entry {
+        Entry {
+            request: QueueRequest { user_id: Uuid::new_v4(), model, time_control: Some(json!({ "type": "none" })), rated: true },
+            rating,
+            joined_at: now - Duration::from_secs(waited),
+            last_seen: now,
+            matching: false,
+            notify: Arc::new(Notify::new()),
+        }
+    }
+
+    #[test]
+    fn test_pairs_closest_rating_within_window() {
+        let now = Instant::now() + Duration::from_secs(1000);
+        let entries = vec![
+            entry(9, 1500.0, 30, now),
+            entry(9, 1900.0, 20, now),
+            entry(9, 1560.0, 10, now),
+            entry(13, 1500.0, 40, now),
+        ];
+        // The longest waiter on 9x9 takes the closest rating; 1900 is out of everyone's window
+        assert_eq!(pair(&entries, now), vec![(0, 2)]);
+
+        // After waiting long enough the window covers the gap
+        let entries = vec![entry(9, 1500.0, 0, now), entry(9, 1900.0, 70, now)];
+        assert_eq!(pair(&entries, now), vec![(1, 0)]);
+        assert_eq!(window(Duration::from_secs(3600)), MAX_WINDOW);
+    }
+
+    #[test]
+    fn test_only_identical_settings_are_paired() {
+        let now = Instant::now() + Duration::from_secs(1000);
+        let mut unrated = entry(9, 1500.0, 5, now);
+        unrated.request.rated = false;
+        let mut blitz = entry(9, 1500.0, 5, now);
+        blitz.request.time_control = Some(json!({ "type": "absolute", "mainTimeMS": 300_000 }));
+        let mut busy = entry(9, 1500.0, 5, now);
+        busy.matching = true;
+        assert!(pair(&[entry(9, 1500.0, 5, now), unrated, blitz, busy], now).is_empty());
+    }
+
+    #[test]
+    fn test_queue_rating_seeds_new_sizes_from_overall() {
+        let ranking = |model: i32, rating: f64, games_played: i32| UserRanking {
+            id: 0,
+            user_id: Uuid::nil(),
+            model,
+            rating,
+            rd: 60.0,
+            vol: 0.06,
+            games_played,
+            wins: games_played,
+            losses: 0,
+            draws: 0,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        };
+        let strong = [ranking(9, 1900.0, 40), ranking(13, 1500.0, 0)];
+        assert_eq!(queue_rating(&strong, 9), 1900.0);
+        // No 13x13 games yet: queued at the 9x9 strength, not as a beginner
+        assert!((queue_rating(&strong, 13) - 1900.0).abs() < 1e-6);
+        assert_eq!(queue_rating(&[], 13), DEFAULT_RATING);
+    }
+}
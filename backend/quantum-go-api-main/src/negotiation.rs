@@ -0,0 +1,364 @@
+// 对局中的协商：和棋、悔棋与中止（PvP 房间）
+//
+// - offerDraw → 对手 acceptDraw / declineDraw；同意后以和棋结束（reason = agreement），正常计入评分
+// - requestTakeback → 对手 acceptTakeback / declineTakeback；同意后服务端撤回请求方的最近一手
+//   （对手已应手时连同对手这一手），截断 chessman_records 并重放出两个现实的局面，以 acceptTakeback 推送给双方
+// - abort：前 ABORT_MOVE_LIMIT 手内一方发送 abort、另一方也发送 abort 即中止（declineAbort 拒绝），
+//   房间置为 aborted，不计胜负与评分
+// 同一时间只有一个待应答的请求；超过 OfferKind::timeout 或任一方落子后作废，向双方推送 offerCancelled。
+// chessman_records 只含落子（前端与复盘按它重放），协商的每一步记入 room_infos.events，
+// move 字段为当时的手数，与落子记录合起来即完整的对局记录。
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::io;
+use std::time::Duration;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::ai_room::{board_entries, color_name};
+use crate::entity::{Room, RoomInfo};
+use crate::outcome::GameOutcome;
+use crate::quantum::Stone;
+use crate::quantum_search::game_from_records;
+use crate::scoring::{seats, send_to, RoomScoring, BOTH};
+use crate::seating::color_of;
+use crate::ws::{end_game, AppState};
+
+/// 可以提出中止的手数上限（棋谱中的落子数）
+pub const ABORT_MOVE_LIMIT: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OfferKind {
+    Draw,
+    Takeback,
+    Abort,
+}
+
+impl OfferKind {
+    // How long the opponent has to answer
+    fn timeout(self) -> Duration {
+        match self {
+            OfferKind::Draw | OfferKind::Abort => Duration::from_secs(60),
+            OfferKind::Takeback => Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Offer,
+    Accept,
+    Decline,
+}
+
+// Message modes; a second abort from the other side accepts the first
+const MODES: [(&str, OfferKind, Action); 9] = [
+    ("offerDraw", OfferKind::Draw, Action::Offer),
+    ("acceptDraw", OfferKind::Draw, Action::Accept),
+    ("declineDraw", OfferKind::Draw, Action::Decline),
+    ("requestTakeback", OfferKind::Takeback, Action::Offer),
+    ("acceptTakeback", OfferKind::Takeback, Action::Accept),
+    ("declineTakeback", OfferKind::Takeback, Action::Decline),
+    ("abort", OfferKind::Abort, Action::Offer),
+    ("abort", OfferKind::Abort, Action::Accept),
+    ("declineAbort", OfferKind::Abort, Action::Decline),
+];
+
+fn parse_mode(mode: &str) -> Option<(OfferKind, Action)> {
+    MODES.iter().find(|(name, _, _)| *name == mode).map(|&(_, kind, action)| (kind, action))
+}
+
+fn mode_of(kind: OfferKind, action: Action) -> &'static str {
+    MODES.iter().find(|&&(_, k, a)| k == kind && a == action).map(|(name, _, _)| *name).unwrap_or_default()
+}
+
+/// 是否为协商消息
+pub fn handles(mode: &str) -> bool {
+    parse_mode(mode).is_some()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Offer {
+    id: Uuid,
+    kind: OfferKind,
+    from: Stone,
+}
+
+/// 房间内待应答的请求
+#[derive(Default)]
+pub struct RoomOffers {
+    pending: Option<Offer>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.to_string())
+}
+
+impl RoomOffers {
+    // Returns the effective action and the offer it concerns
+    fn apply(&mut self, kind: OfferKind, action: Action, color: Stone) -> io::Result<(Action, Offer)> {
+        match (action, self.pending) {
+            (Action::Offer, Some(p)) if kind == OfferKind::Abort && p.kind == OfferKind::Abort && p.from != color => {
+                self.pending = None;
+                Ok((Action::Accept, p))
+            }
+            (Action::Offer, Some(_)) => Err(invalid("another request is waiting for an answer")),
+            (Action::Offer, None) => {
+                let offer = Offer { id: Uuid::new_v4(), kind, from: color };
+                self.pending = Some(offer);
+                Ok((Action::Offer, offer))
+            }
+            (_, Some(p)) if p.kind == kind && p.from != color => {
+                self.pending = None;
+                Ok((action, p))
+            }
+            _ => Err(invalid("no request to answer")),
+        }
+    }
+
+    fn expire(&mut self, id: Uuid) -> Option<Offer> {
+        self.pending.take_if(|p| p.id == id)
+    }
+}
+
+// Colour of the stone a chessman_records entry added
+fn record_color(record: &Value) -> Option<Stone> {
+    record.pointer("/add/0/type").and_then(Value::as_str).and_then(Stone::parse)
+}
+
+fn record_count(room: &RoomInfo) -> usize {
+    room.chessman_records.as_array().map_or(0, Vec::len)
+}
+
+/// 悔棋后的局面（acceptTakeback 推送给双方）
+#[derive(Debug, Serialize)]
+pub struct Rollback {
+    pub undone: usize,
+    pub board: Value,
+    pub board2: Value,
+    pub black_lost: i32,
+    pub white_lost: i32,
+    pub chessman_records: Value,
+    pub round: String,
+}
+
+// Take back `color`'s latest stone and everything played after it
+fn rollback(model: i32, handicap: i32, records: &Value, color: Stone) -> io::Result<Rollback> {
+    let mut records = records.as_array().cloned().unwrap_or_default();
+    let own = records
+        .iter()
+        .rposition(|r| record_color(r) == Some(color))
+        .ok_or_else(|| invalid("no move to take back"))?;
+    let undone = records.len() - own;
+    records.truncate(own);
+
+    // Stones lost on board A, as counted by the frontend
+    let (mut black_lost, mut white_lost) = (0, 0);
+    for removed in records.iter().filter_map(|r| r.get("reduce").and_then(Value::as_array)).flatten() {
+        if removed.get("board").and_then(Value::as_i64).unwrap_or(1) != 1 {
+            continue;
+        }
+        match removed.get("type").and_then(Value::as_str).and_then(Stone::parse) {
+            Some(Stone::Black) => black_lost += 1,
+            Some(Stone::White) => white_lost += 1,
+            None => {}
+        }
+    }
+
+    let records = Value::Array(records);
+    let game = game_from_records(model, handicap, &records)?;
+    Ok(Rollback {
+        undone,
+        board: board_entries(&game, 0),
+        board2: board_entries(&game, 1),
+        black_lost,
+        white_lost,
+        chessman_records: records,
+        round: color_name(color).to_string(),
+    })
+}
+
+fn check(room: &RoomInfo, kind: OfferKind, from: Stone) -> io::Result<()> {
+    if room.status != "playing" {
+        return Err(invalid("game is not in progress"));
+    }
+    match kind {
+        OfferKind::Abort if record_count(room) >= ABORT_MOVE_LIMIT => {
+            Err(invalid(&format!("a game can only be aborted within the first {} moves", ABORT_MOVE_LIMIT)))
+        }
+        OfferKind::Takeback => rollback(room.model, room.handicap, &room.chessman_records, from).map(|_| ()),
+        _ => Ok(()),
+    }
+}
+
+/// room_infos.events 中的一条记录
+#[derive(Debug, Serialize)]
+pub struct LogEntry {
+    pub event: &'static str, // offer / accept / decline / cancel
+    pub offer: OfferKind,
+    pub color: Stone, // the player who acted (the proposer for cancellations)
+    #[serde(rename = "move")]
+    pub move_number: usize,
+    pub at: chrono::DateTime<chrono::Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub undone: Option<usize>,
+}
+
+async fn log(state: &AppState, room: &RoomInfo, entry: LogEntry) {
+    let Ok(event) = serde_json::to_value(&entry) else { return };
+    if let Err(err) = state.db.append_room_event(room.room_id, &event).await {
+        info!("Failed to log {} in room {}: {}", entry.event, room.room_id, err);
+    }
+}
+
+fn entry(event: &'static str, offer: Offer, color: Stone, room: &RoomInfo) -> LogEntry {
+    LogEntry {
+        event,
+        offer: offer.kind,
+        color,
+        move_number: record_count(room),
+        at: chrono::Utc::now(),
+        reason: None,
+        undone: None,
+    }
+}
+
+// Drop an unanswered request and tell both players why
+async fn cancel(state: &AppState, room: &Room, room_info: &RoomInfo, offer: Offer, reason: &'static str) {
+    log(state, room_info, LogEntry { reason: Some(reason), ..entry("cancel", offer, offer.from, room_info) }).await;
+    send_to(&seats(room, room_info), &BOTH, "offerCancelled", json!({ "offer": offer.kind, "reason": reason })).await;
+}
+
+async fn expire(state: AppState, room_id: Uuid, offer: Offer) {
+    tokio::time::sleep(offer.kind.timeout()).await;
+    let mut rooms = state.rooms.lock().await;
+    let Some(room) = rooms.get_mut(&room_id) else { return };
+    if room.offers.expire(offer.id).is_none() {
+        return;
+    }
+    if let Ok(room_info) = state.db.get_room_by_room_id(room_id).await {
+        cancel(&state, room, &room_info, offer, "timeout").await;
+    }
+}
+
+/// 落子后作废未应答的请求
+pub async fn on_move(state: &AppState, room: &mut Room, room_info: &RoomInfo) {
+    if let Some(offer) = room.offers.pending.take() {
+        cancel(state, room, room_info, offer, "move").await;
+    }
+}
+
+async fn handle(state: &AppState, room: &mut Room, room_info: &RoomInfo, color: Stone, kind: OfferKind, action: Action) -> io::Result<()> {
+    if room.scoring.active() {
+        return Err(invalid("game is in the scoring phase"));
+    }
+    let proposer = if action == Action::Offer { color } else { color.opposite() };
+    if action != Action::Decline {
+        check(room_info, kind, proposer)?;
+    }
+    let (action, offer) = room.offers.apply(kind, action, color)?;
+    let seats = seats(room, room_info);
+    match (action, offer.kind) {
+        (Action::Offer, _) => {
+            log(state, room_info, entry("offer", offer, color, room_info)).await;
+            let data = json!({ "from": color, "expires_in_ms": offer.kind.timeout().as_millis() as u64 });
+            send_to(&seats, &[color.opposite()], mode_of(offer.kind, Action::Offer), data).await;
+            tokio::spawn(expire(state.clone(), room_info.room_id, offer));
+        }
+        (Action::Decline, _) => {
+            log(state, room_info, entry("decline", offer, color, room_info)).await;
+            send_to(&seats, &[offer.from], mode_of(offer.kind, Action::Decline), Value::Null).await;
+        }
+        (Action::Accept, OfferKind::Takeback) => {
+            let rollback = rollback(room_info.model, room_info.handicap, &room_info.chessman_records, offer.from)?;
+            state
+                .db
+                .update_room(&RoomInfo {
+                    round: rollback.round.clone(),
+                    board: rollback.board.clone(),
+                    moves: (room_info.moves - rollback.undone as i32).max(0),
+                    black_lost: rollback.black_lost,
+                    white_lost: rollback.white_lost,
+                    chessman_records: rollback.chessman_records.clone(),
+                    ..room_info.clone()
+                })
+                .await
+                .map_err(io::Error::other)?;
+            log(state, room_info, LogEntry { undone: Some(rollback.undone), ..entry("accept", offer, color, room_info) }).await;
+            // Passes are counted again from the restored position
+            room.scoring = RoomScoring::default();
+            send_to(&seats, &BOTH, "acceptTakeback", rollback).await;
+        }
+        (Action::Accept, kind) => {
+            log(state, room_info, entry("accept", offer, color, room_info)).await;
+            let result = match kind {
+                OfferKind::Abort => GameOutcome::aborted(room_info),
+                _ => GameOutcome::draw_agreed(room_info),
+            };
+            end_game(state, room, room_info, &result).await.map_err(io::Error::other)?;
+        }
+    }
+    Ok(())
+}
+
+/// 处理协商消息；不合法的请求只向发送方回复 error
+pub async fn on_message(state: &AppState, room: &mut Room, room_info: &RoomInfo, user_id: Uuid, mode: &str) {
+    let Some((kind, action)) = parse_mode(mode) else { return };
+    let color = color_of(room_info, user_id);
+    if let Err(err) = handle(state, room, room_info, color, kind, action).await {
+        send_to(&seats(room, room_info), &[color], "error", json!({ "message": err.to_string() })).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai_room::LiveGame;
+    use crate::quantum::QuantumGame;
+
+    #[test]
+    fn test_offers_need_an_answer_from_the_opponent() {
+        let mut offers = RoomOffers::default();
+        let (action, offer) = offers.apply(OfferKind::Draw, Action::Offer, Stone::Black).unwrap();
+        assert_eq!((action, offer.kind), (Action::Offer, OfferKind::Draw));
+        // The proposer cannot accept their own offer, and only one request is open at a time
+        assert!(offers.apply(OfferKind::Draw, Action::Accept, Stone::Black).is_err());
+        assert!(offers.apply(OfferKind::Takeback, Action::Offer, Stone::White).is_err());
+        assert!(offers.apply(OfferKind::Takeback, Action::Accept, Stone::White).is_err());
+        assert_eq!(offers.apply(OfferKind::Draw, Action::Decline, Stone::White).unwrap().0, Action::Decline);
+        assert!(offers.pending.is_none());
+
+        // abort from both sides is a mutual abort
+        let (_, first) = offers.apply(OfferKind::Abort, Action::Offer, Stone::White).unwrap();
+        assert!(offers.expire(Uuid::new_v4()).is_none());
+        assert_eq!(offers.apply(OfferKind::Abort, Action::Offer, Stone::Black).unwrap(), (Action::Accept, first));
+        assert_eq!(parse_mode("abort"), Some((OfferKind::Abort, Action::Offer)));
+        assert_eq!(mode_of(OfferKind::Takeback, Action::Decline), "declineTakeback");
+    }
+
+    #[test]
+    fn test_takeback_rolls_back_both_boards() {
+        let mut live = LiveGame { game: QuantumGame::new(9), records: Vec::new(), black_lost: 0, white_lost: 0 };
+        for pos in ["3,3", "7,7", "5,5", "1,1"] {
+            let idx = live.game.index_of(pos).unwrap();
+            live.play(idx).unwrap();
+        }
+        let records = Value::Array(live.records.clone());
+
+        // White's own stone is the last one: only it is undone
+        let white = rollback(9, 0, &records, Stone::White).unwrap();
+        assert_eq!((white.undone, white.round.as_str()), (1, "white"));
+        // Black asks after White answered: both stones go
+        let black = rollback(9, 0, &records, Stone::Black).unwrap();
+        assert_eq!((black.undone, black.round.as_str()), (2, "black"));
+        assert_eq!(black.chessman_records.as_array().unwrap().len(), 2);
+        // The opening quantum pair stays linked across the two realities
+        assert_eq!(black.board[0][1]["brother"], "7,7");
+        assert_eq!(black.board2.as_array().unwrap().len(), 2);
+
+        assert!(rollback(9, 0, &json!([]), Stone::Black).is_err());
+    }
+}
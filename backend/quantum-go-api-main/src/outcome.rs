@@ -0,0 +1,303 @@
+// 对局结果：认输、超时、数子三种结束方式，由服务端校验后写入 room_infos.result
+//
+// - resign：发送方认输
+// - timeout：data.color 为超时的一方（缺省为对手）。承认自己超时等同认输；判对手超时时，
+//   须轮到对手行棋，且自对手开始思考（上一手的 last_activity_at）起已超过其用时上限
+// - score：只由服务端的数子阶段（scoring）或 AI 房间产生，客户端不能提交
+// - agreement / abort：双方协商的和棋与中止（见 negotiation），中止的对局不计评分
+// 旧版客户端的 setWinner 仍被接受：判对方胜视为认输（reason 为 timeout 时视为自己超时），
+// 判自己胜只能作为对手超时的申诉，按 timeout 校验。
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io;
+use uuid::Uuid;
+
+use crate::entity::RoomInfo;
+use crate::quantum::Stone;
+use crate::seating::{color_of, player_of};
+
+// Network latency allowance for timeout claims
+const TIMEOUT_GRACE_MS: i64 = 2_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EndReason {
+    Resign,
+    Timeout,
+    Score,
+    Agreement,
+    Abort,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Seat {
+    Owner,
+    Visitor,
+}
+
+/// 结构化的对局结果（存库，并随 RoomInfo / RecentRoomSummary 返回）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GameOutcome {
+    pub winner: Option<Stone>, // None = draw
+    pub reason: EndReason,
+    pub winner_seat: Option<Seat>,
+    pub winner_id: Option<Uuid>,
+    // Counted games only: both totals (komi included) and the winner's margin
+    pub black_score: Option<f64>,
+    pub white_score: Option<f64>,
+    pub margin: Option<f64>,
+}
+
+// Seat and player holding the given colour
+fn seat_of(room: &RoomInfo, color: Stone) -> (Seat, Option<Uuid>) {
+    let seat = if color_of(room, room.owner_id) == color { Seat::Owner } else { Seat::Visitor };
+    (seat, player_of(room, color))
+}
+
+impl GameOutcome {
+    fn decided(room: &RoomInfo, winner: Option<Stone>, reason: EndReason) -> Self {
+        let seat = winner.map(|color| seat_of(room, color));
+        Self {
+            winner,
+            reason,
+            winner_seat: seat.map(|(seat, _)| seat),
+            winner_id: seat.and_then(|(_, id)| id),
+            black_score: None,
+            white_score: None,
+            margin: None,
+        }
+    }
+
+    pub fn resign(room: &RoomInfo, loser: Stone) -> Self {
+        Self::decided(room, Some(loser.opposite()), EndReason::Resign)
+    }
+
+    pub fn timeout(room: &RoomInfo, flagged: Stone) -> Self {
+        Self::decided(room, Some(flagged.opposite()), EndReason::Timeout)
+    }
+
+    /// 数子结果；双方相同为和棋
+    pub fn score(room: &RoomInfo, black: f64, white: f64) -> Self {
+        let winner = if black > white {
+            Some(Stone::Black)
+        } else if white > black {
+            Some(Stone::White)
+        } else {
+            None
+        };
+        Self {
+            black_score: Some(black),
+            white_score: Some(white),
+            margin: Some((black - white).abs()),
+            ..Self::decided(room, winner, EndReason::Score)
+        }
+    }
+
+    /// 双方同意和棋
+    pub fn draw_agreed(room: &RoomInfo) -> Self {
+        Self::decided(room, None, EndReason::Agreement)
+    }
+
+    /// 双方同意中止：无胜负，不计评分
+    pub fn aborted(room: &RoomInfo) -> Self {
+        Self::decided(room, None, EndReason::Abort)
+    }
+
+    /// 存入 winner 字段的值："black" / "white" / "draw"
+    pub fn winner_name(&self) -> &'static str {
+        match self.winner {
+            Some(Stone::Black) => "black",
+            Some(Stone::White) => "white",
+            None => "draw",
+        }
+    }
+}
+
+/// 客户端提出的结束请求
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Claim {
+    Resign,
+    Timeout(Stone), // the side whose clock ran out
+}
+
+#[derive(Deserialize)]
+struct TimeoutData {
+    color: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LegacySetWinner {
+    winner: String,
+    reason: Option<String>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.to_string())
+}
+
+impl Claim {
+    /// 解析 resign / timeout / 旧版 setWinner 消息；claimant 为发送方的颜色
+    pub fn parse(mode: &str, data: &Value, claimant: Stone) -> io::Result<Self> {
+        match mode {
+            "resign" => Ok(Claim::Resign),
+            "timeout" => {
+                let color = serde_json::from_value::<TimeoutData>(data.clone()).ok().and_then(|d| d.color);
+                match color {
+                    None => Ok(Claim::Timeout(claimant.opposite())),
+                    Some(name) => Stone::parse(&name).map(Claim::Timeout).ok_or_else(|| invalid("invalid timeout colour")),
+                }
+            }
+            "setWinner" => {
+                let data = serde_json::from_value::<LegacySetWinner>(data.clone()).map_err(|_| invalid("invalid setWinner"))?;
+                let winner = Stone::parse(&data.winner).ok_or_else(|| invalid("winner must be black or white"))?;
+                match data.reason.as_deref() {
+                    Some("score") => Err(invalid("score results are set by the server after the scoring phase")),
+                    Some("timeout") => Ok(Claim::Timeout(winner.opposite())),
+                    _ if winner == claimant => Ok(Claim::Timeout(claimant.opposite())),
+                    _ => Ok(Claim::Resign),
+                }
+            }
+            other => Err(invalid(&format!("unknown result message: {}", other))),
+        }
+    }
+
+    /// 校验后给出结果；now 用于判断对手的用时是否耗尽
+    pub fn validate(self, room: &RoomInfo, claimant: Stone, now: chrono::DateTime<chrono::Utc>) -> io::Result<GameOutcome> {
+        let in_progress = room.status == "playing" || (room.status == "scoring" && self == Claim::Resign);
+        if !in_progress {
+            return Err(invalid("game is not in progress"));
+        }
+        match self {
+            Claim::Resign => Ok(GameOutcome::resign(room, claimant)),
+            Claim::Timeout(flagged) if flagged == claimant => Ok(GameOutcome::timeout(room, flagged)),
+            Claim::Timeout(flagged) => {
+                if Stone::parse(&room.round) != Some(flagged) {
+                    return Err(invalid("opponent is not on move"));
+                }
+                // Clocks start once both players have moved
+                let limit = room
+                    .time_control
+                    .as_ref()
+                    .filter(|_| room.moves >= 2)
+                    .and_then(|tc| max_time_left_ms(tc, room.moves))
+                    .ok_or_else(|| invalid("no clock is running"))?;
+                let elapsed = (now - room.last_activity_at).num_milliseconds();
+                if elapsed < limit + TIMEOUT_GRACE_MS {
+                    return Err(invalid("opponent's clock has not run out"));
+                }
+                Ok(GameOutcome::timeout(room, flagged))
+            }
+        }
+    }
+}
+
+// Upper bound of the time a player can have left when their turn starts, from the
+// frontend's time control config (utils/timeControl.ts); None when there is no clock
+fn max_time_left_ms(time_control: &Value, moves: i32) -> Option<i64> {
+    let field = |name: &str| time_control.get(name).and_then(Value::as_i64).unwrap_or(0).max(0);
+    let main = field("mainTimeMS");
+    match time_control.get("type").and_then(Value::as_str)? {
+        "absolute" | "simple" => Some(main),
+        "fischer" => match time_control.get("maxTimeMS").and_then(Value::as_i64) {
+            Some(max) => Some(max.max(main)),
+            None => Some(main + field("incrementMS") * moves.max(0) as i64),
+        },
+        "byoyomi" => Some(main + field("numPeriods") * field("periodTimeMS")),
+        _ => None,
+    }
+    .filter(|&ms| ms > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn room(status: &str, round: &str, time_control: Option<Value>) -> RoomInfo {
+        let (owner, visitor) = (Uuid::new_v4(), Uuid::new_v4());
+        RoomInfo {
+            id: 1,
+            room_id: Uuid::new_v4(),
+            owner_id: owner,
+            visitor_id: Some(visitor),
+            black_id: Some(owner),
+            white_id: Some(visitor),
+            status: status.to_string(),
+            round: round.to_string(),
+            winner: None,
+            board: json!([]),
+            countdown: 30,
+            moves: 10,
+            black_lost: 0,
+            white_lost: 0,
+            model: 9,
+            chessman_records: json!([]),
+            phase: None,
+            komi: 7.5,
+            handicap: 0,
+            time_control,
+            is_public: true,
+            is_listed: true,
+            allow_spectate: true,
+            ai_level: None,
+            rated: true,
+            rules: "quantum".to_string(),
+            result: None,
+            events: json!([]),
+            created_at: chrono::Utc::now(),
+            last_activity_at: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_legacy_set_winner_cannot_claim_a_win() {
+        let parse = |data: Value| Claim::parse("setWinner", &data, Stone::Black);
+        assert_eq!(parse(json!({ "winner": "white" })).unwrap(), Claim::Resign);
+        // Declaring oneself the winner is only a timeout claim against the opponent
+        assert_eq!(parse(json!({ "winner": "black" })).unwrap(), Claim::Timeout(Stone::White));
+        assert!(parse(json!({ "winner": "black", "reason": "score" })).is_err());
+        assert!(parse(json!({ "winner": "nobody" })).is_err());
+        assert_eq!(Claim::parse("timeout", &json!({}), Stone::White).unwrap(), Claim::Timeout(Stone::Black));
+
+        let r = room("playing", "white", None);
+        let outcome = Claim::Timeout(Stone::White).validate(&r, Stone::Black, chrono::Utc::now());
+        assert_eq!(outcome.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let outcome = Claim::Resign.validate(&r, Stone::White, chrono::Utc::now()).unwrap();
+        assert_eq!((outcome.winner, outcome.winner_seat, outcome.winner_id), (Some(Stone::Black), Some(Seat::Owner), Some(r.owner_id)));
+        assert!(Claim::Resign.validate(&room("finished", "white", None), Stone::White, chrono::Utc::now()).is_err());
+    }
+
+    #[test]
+    fn test_timeout_claim_checks_the_clock() {
+        let tc = json!({ "type": "byoyomi", "mainTimeMS": 60_000, "numPeriods": 3, "periodTimeMS": 10_000 });
+        let r = room("playing", "white", Some(tc));
+        let start = r.last_activity_at;
+        let claim = Claim::Timeout(Stone::White);
+        assert!(claim.validate(&r, Stone::Black, start + chrono::Duration::seconds(60)).is_err());
+        let outcome = claim.validate(&r, Stone::Black, start + chrono::Duration::seconds(93)).unwrap();
+        assert_eq!((outcome.winner, outcome.reason), (Some(Stone::Black), EndReason::Timeout));
+        // Not on move: White cannot be flagged while Black is thinking
+        let r = room("playing", "black", r.time_control.clone());
+        assert!(claim.validate(&r, Stone::Black, start + chrono::Duration::hours(1)).is_err());
+        // Admitting one's own timeout needs no clock check
+        assert!(Claim::Timeout(Stone::Black).validate(&r, Stone::Black, start).is_ok());
+
+        assert_eq!(max_time_left_ms(&json!({ "type": "fischer", "mainTimeMS": 1000, "incrementMS": 100, "maxTimeMS": null }), 5), Some(1500));
+        assert_eq!(max_time_left_ms(&json!({ "type": "none", "mainTimeMS": 0 }), 5), None);
+    }
+
+    #[test]
+    fn test_score_outcome_records_margin() {
+        let r = room("scoring", "black", None);
+        let outcome = GameOutcome::score(&r, 40.0, 45.5);
+        assert_eq!((outcome.winner, outcome.margin, outcome.winner_seat), (Some(Stone::White), Some(5.5), Some(Seat::Visitor)));
+        // The owner took White: the seat follows the colour
+        let swapped = RoomInfo { black_id: r.white_id, white_id: r.black_id, ..r.clone() };
+        let outcome = GameOutcome::score(&swapped, 40.0, 45.5);
+        assert_eq!((outcome.winner_seat, outcome.winner_id), (Some(Seat::Owner), Some(r.owner_id)));
+        let draw = GameOutcome::score(&r, 40.0, 40.0);
+        assert_eq!((draw.winner_name(), draw.winner_id), ("draw", None));
+        assert_eq!(serde_json::to_value(&draw).unwrap()["reason"], "score");
+    }
+}
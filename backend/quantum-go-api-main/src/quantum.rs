@@ -0,0 +1,604 @@
+// 量子围棋规则（与前端 store/modules/game.ts 的 putChess 保持一致）
+//
+// - 两个现实（棋盘 A/B）。第一手黑棋在 A 上为黑、在 B 上为白；第二手白棋在 A 上为白、在 B 上为黑，
+//   随后两颗量子子交叉配对：A 上的黑子与 B 上的黑子互为 brother，白子同理。之后双方颜色一致。
+// - 开局之后一般落在两盘的同一坐标；若点击两个量子位置之一（已被提走），黑方落在 A 的黑量子位、
+//   B 的白量子位，白方相反。
+// - 一手棋必须在两盘上都合法（含情境超劫：局面 + 刚行棋的颜色不能重复）。
+// - 每盘分别提子；某盘被提的子，其 brother 在另一盘上也一并移除。
+// - 数子：两盘各自按面积计分后相加，贴目只给白方一次；数目计分的规则集（日本规则）改为地 + 提子。
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashSet};
+use std::hash::{Hash, Hasher};
+
+use crate::rules::{Ruleset, Scoring};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stone {
+    Black,
+    White,
+}
+
+impl Stone {
+    pub fn opposite(self) -> Stone {
+        match self {
+            Stone::Black => Stone::White,
+            Stone::White => Stone::Black,
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Stone> {
+        match s.to_ascii_lowercase().as_str() {
+            "black" | "b" => Some(Stone::Black),
+            "white" | "w" => Some(Stone::White),
+            _ => None,
+        }
+    }
+}
+
+/// 单个现实的棋盘；brother 记录对应子在另一盘上的位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Board {
+    pub size: usize,
+    cells: Vec<Option<Stone>>,
+    brother: Vec<Option<usize>>,
+}
+
+impl Board {
+    pub fn new(size: usize) -> Self {
+        Self { size, cells: vec![None; size * size], brother: vec![None; size * size] }
+    }
+
+    pub fn get(&self, idx: usize) -> Option<Stone> {
+        self.cells[idx]
+    }
+
+    pub fn brother(&self, idx: usize) -> Option<usize> {
+        self.brother[idx]
+    }
+
+    pub fn stones(&self) -> impl Iterator<Item = (usize, Stone)> + '_ {
+        self.cells.iter().enumerate().filter_map(|(i, c)| c.map(|s| (i, s)))
+    }
+
+    fn neighbors(&self, idx: usize) -> impl Iterator<Item = usize> {
+        let n = self.size;
+        let (r, c) = (idx / n, idx % n);
+        let mut out = [usize::MAX; 4];
+        if r > 0 { out[0] = idx - n; }
+        if r + 1 < n { out[1] = idx + n; }
+        if c > 0 { out[2] = idx - 1; }
+        if c + 1 < n { out[3] = idx + 1; }
+        out.into_iter().filter(|&i| i != usize::MAX)
+    }
+
+    // Group containing `idx` and whether it has any liberty
+    fn group(&self, idx: usize) -> (Vec<usize>, bool) {
+        let color = self.cells[idx];
+        let mut seen = vec![false; self.cells.len()];
+        let mut stack = vec![idx];
+        let mut group = Vec::new();
+        let mut has_liberty = false;
+        seen[idx] = true;
+        while let Some(p) = stack.pop() {
+            group.push(p);
+            for nb in self.neighbors(p) {
+                match self.cells[nb] {
+                    None => has_liberty = true,
+                    c if c == color && !seen[nb] => {
+                        seen[nb] = true;
+                        stack.push(nb);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        (group, has_liberty)
+    }
+
+    /// idx 所在的整块棋；空点返回空
+    pub(crate) fn chain(&self, idx: usize) -> Vec<usize> {
+        if self.cells[idx].is_none() {
+            return Vec::new();
+        }
+        self.group(idx).0
+    }
+
+    // Stones captured after `color` played: liberty-less enemy groups, then liberty-less own groups
+    fn captured_after(&self, color: Stone) -> Vec<usize> {
+        let mut board = self.clone();
+        let mut captured = Vec::new();
+        for target in [color.opposite(), color] {
+            let mut seen = vec![false; board.cells.len()];
+            for idx in 0..board.cells.len() {
+                if seen[idx] || board.cells[idx] != Some(target) {
+                    continue;
+                }
+                let (group, has_liberty) = board.group(idx);
+                group.iter().for_each(|&p| seen[p] = true);
+                if !has_liberty {
+                    captured.extend(group);
+                }
+            }
+            for &p in &captured {
+                board.cells[p] = None;
+            }
+        }
+        captured
+    }
+
+    // Board after `color` plays at `idx` with captures removed, or None if occupied/suicide
+    pub(crate) fn after_move(&self, idx: usize, color: Stone) -> Option<Board> {
+        if self.cells[idx].is_some() {
+            return None;
+        }
+        let mut board = self.clone();
+        board.cells[idx] = Some(color);
+        for p in board.captured_after(color) {
+            board.cells[p] = None;
+        }
+        match board.cells[idx] {
+            Some(_) if board.group(idx).1 => Some(board),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn hash_with_turn(&self, color: Stone) -> u64 {
+        let mut h = DefaultHasher::new();
+        self.cells.hash(&mut h);
+        color.hash(&mut h);
+        h.finish()
+    }
+
+    /// 面积计分（子 + 只与一方相邻的空白区域）
+    pub fn area_score(&self) -> (f64, f64) {
+        let (mut black, mut white) = self.territory();
+        for (_, stone) in self.stones() {
+            match stone {
+                Stone::Black => black += 1.0,
+                Stone::White => white += 1.0,
+            }
+        }
+        (black, white)
+    }
+
+    /// 只与一方相邻的空白区域
+    pub fn territory(&self) -> (f64, f64) {
+        let (mut black, mut white) = (0.0, 0.0);
+        let mut seen = vec![false; self.cells.len()];
+        for idx in 0..self.cells.len() {
+            if self.cells[idx].is_some() || seen[idx] {
+                continue;
+            }
+            let mut region = 0.0;
+            let (mut touches_black, mut touches_white) = (false, false);
+            let mut stack = vec![idx];
+            seen[idx] = true;
+            while let Some(p) = stack.pop() {
+                region += 1.0;
+                for nb in self.neighbors(p) {
+                    match self.cells[nb] {
+                        Some(Stone::Black) => touches_black = true,
+                        Some(Stone::White) => touches_white = true,
+                        None if !seen[nb] => {
+                            seen[nb] = true;
+                            stack.push(nb);
+                        }
+                        None => {}
+                    }
+                }
+            }
+            match (touches_black, touches_white) {
+                (true, false) => black += region,
+                (false, true) => white += region,
+                _ => {}
+            }
+        }
+        (black, white)
+    }
+}
+
+/// 一手棋的结果：两盘上的落点与被提的子
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MoveOutcome {
+    pub positions: [usize; 2],
+    pub captured: [Vec<usize>; 2],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IllegalMove {
+    OutOfBoard,
+    Illegal,
+}
+
+impl std::fmt::Display for IllegalMove {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IllegalMove::OutOfBoard => write!(f, "position is outside the board"),
+            IllegalMove::Illegal => write!(f, "move is illegal on at least one board"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct QuantumGame {
+    pub size: usize,
+    pub boards: [Board; 2],
+    pub to_move: Stone,
+    /// 已落子手数（不含 pass）
+    pub move_number: usize,
+    pub consecutive_passes: u32,
+    /// 两盘合计被提的黑子、白子数（数目计分时算作对方的提子）
+    pub captured: (u32, u32),
+    black_quantum: Option<usize>,
+    white_quantum: Option<usize>,
+    history: [HashSet<u64>; 2],
+}
+
+impl QuantumGame {
+    pub fn new(size: usize) -> Self {
+        let boards = [Board::new(size), Board::new(size)];
+        let history = [
+            HashSet::from([boards[0].hash_with_turn(Stone::Black)]),
+            HashSet::from([boards[1].hash_with_turn(Stone::Black)]),
+        ];
+        Self {
+            size,
+            boards,
+            to_move: Stone::Black,
+            move_number: 0,
+            consecutive_passes: 0,
+            captured: (0, 0),
+            black_quantum: None,
+            white_quantum: None,
+            history,
+        }
+    }
+
+    /// 让子局：让子在两盘上都是黑子且 brother 指向自身，白方先行
+    pub fn with_handicap(size: usize, stones: &[usize]) -> Self {
+        let mut game = Self::new(size);
+        if stones.is_empty() {
+            return game;
+        }
+        for board in game.boards.iter_mut() {
+            for &idx in stones.iter().filter(|&&i| i < size * size) {
+                board.cells[idx] = Some(Stone::Black);
+                board.brother[idx] = Some(idx);
+            }
+        }
+        game.to_move = Stone::White;
+        game.history = [
+            HashSet::from([game.boards[0].hash_with_turn(Stone::Black)]),
+            HashSet::from([game.boards[1].hash_with_turn(Stone::Black)]),
+        ];
+        game
+    }
+
+    /// "x,y"（1-based，x 为行）→ 下标
+    pub fn index_of(&self, pos: &str) -> Option<usize> {
+        let (x, y) = pos.split_once(',')?;
+        let (x, y) = (x.trim().parse::<usize>().ok()?, y.trim().parse::<usize>().ok()?);
+        if x == 0 || y == 0 || x > self.size || y > self.size {
+            return None;
+        }
+        Some((x - 1) * self.size + (y - 1))
+    }
+
+    pub fn position_of(&self, idx: usize) -> String {
+        format!("{},{}", idx / self.size + 1, idx % self.size + 1)
+    }
+
+    /// 行棋方在某一盘上的颜色（开局两手 B 盘颜色相反）
+    pub fn color_on(&self, board: usize, player: Stone) -> Stone {
+        if board == 1 && self.move_number < 2 { player.opposite() } else { player }
+    }
+
+    /// 点击 idx 时两盘上的实际落点
+    pub fn map_move(&self, idx: usize, player: Stone) -> [usize; 2] {
+        if self.move_number >= 2 {
+            if let (Some(bq), Some(wq)) = (self.black_quantum, self.white_quantum) {
+                if idx == bq || idx == wq {
+                    return match player {
+                        Stone::Black => [bq, wq],
+                        Stone::White => [wq, bq],
+                    };
+                }
+            }
+        }
+        [idx, idx]
+    }
+
+    fn next_boards(&self, idx: usize) -> Option<[Board; 2]> {
+        let player = self.to_move;
+        let [p0, p1] = self.map_move(idx, player);
+        let c0 = self.color_on(0, player);
+        let c1 = self.color_on(1, player);
+        let b0 = self.boards[0].after_move(p0, c0)?;
+        let b1 = self.boards[1].after_move(p1, c1)?;
+        if self.history[0].contains(&b0.hash_with_turn(c0)) || self.history[1].contains(&b1.hash_with_turn(c1)) {
+            return None;
+        }
+        Some([b0, b1])
+    }
+
+    pub fn is_legal(&self, idx: usize) -> bool {
+        idx < self.size * self.size && self.next_boards(idx).is_some()
+    }
+
+    pub fn legal_moves(&self) -> Vec<usize> {
+        (0..self.size * self.size).filter(|&i| self.is_legal(i)).collect()
+    }
+
+    /// 当前行棋方在 idx 落子
+    pub fn play(&mut self, idx: usize) -> Result<MoveOutcome, IllegalMove> {
+        if idx >= self.size * self.size {
+            return Err(IllegalMove::OutOfBoard);
+        }
+        if self.next_boards(idx).is_none() {
+            return Err(IllegalMove::Illegal);
+        }
+        let player = self.to_move;
+        let positions = self.map_move(idx, player);
+        let colors = [self.color_on(0, player), self.color_on(1, player)];
+
+        for b in 0..2 {
+            self.boards[b].cells[positions[b]] = Some(colors[b]);
+            self.boards[b].brother[positions[b]] = Some(positions[1 - b]);
+        }
+        if self.move_number < 2 {
+            // 让子局白方先行，量子位置按行棋方颜色记录
+            let first = match player {
+                Stone::Black => self.white_quantum,
+                Stone::White => self.black_quantum,
+            };
+            match player {
+                Stone::Black => self.black_quantum = Some(idx),
+                Stone::White => self.white_quantum = Some(idx),
+            }
+            // Cross-link the two quantum stones: same colour pairs across realities
+            if let (1, Some(q)) = (self.move_number, first) {
+                self.boards[0].brother[q] = Some(idx);
+                self.boards[1].brother[idx] = Some(q);
+                self.boards[0].brother[idx] = Some(q);
+                self.boards[1].brother[q] = Some(idx);
+            }
+        }
+
+        // Captures on each board, then entangled removals across realities
+        let direct = [self.boards[0].captured_after(colors[0]), self.boards[1].captured_after(colors[1])];
+        let mut removed: [Vec<usize>; 2] = [direct[0].clone(), direct[1].clone()];
+        for b in 0..2 {
+            for &p in &direct[b] {
+                if let Some(mate) = self.boards[b].brother[p] {
+                    if self.boards[1 - b].cells[mate].is_some() && !removed[1 - b].contains(&mate) {
+                        removed[1 - b].push(mate);
+                    }
+                }
+            }
+        }
+        for (b, board_removed) in removed.iter().enumerate() {
+            for &p in board_removed {
+                match self.boards[b].cells[p] {
+                    Some(Stone::Black) => self.captured.0 += 1,
+                    Some(Stone::White) => self.captured.1 += 1,
+                    None => {}
+                }
+                self.boards[b].cells[p] = None;
+                self.boards[b].brother[p] = None;
+            }
+        }
+
+        self.history[0].insert(self.boards[0].hash_with_turn(colors[0]));
+        self.history[1].insert(self.boards[1].hash_with_turn(colors[1]));
+        self.move_number += 1;
+        self.consecutive_passes = 0;
+        self.to_move = player.opposite();
+        Ok(MoveOutcome { positions, captured: removed })
+    }
+
+    pub fn pass(&mut self) {
+        self.consecutive_passes += 1;
+        self.to_move = self.to_move.opposite();
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.consecutive_passes >= 2
+    }
+
+    /// 两盘面积分之和，贴目计入白方一次
+    pub fn area_score(&self, komi: f64) -> (f64, f64) {
+        let (b0, w0) = self.boards[0].area_score();
+        let (b1, w1) = self.boards[1].area_score();
+        (b0 + b1, w0 + w1 + komi)
+    }
+
+    /// 两盘数目计分：地 + 提子，贴目计入白方一次
+    pub fn territory_score(&self, komi: f64) -> (f64, f64) {
+        let (b0, w0) = self.boards[0].territory();
+        let (b1, w1) = self.boards[1].territory();
+        let (black_lost, white_lost) = self.captured;
+        (b0 + b1 + white_lost as f64, w0 + w1 + black_lost as f64 + komi)
+    }
+
+    /// 按规则集的计分方式数子
+    pub fn score(&self, komi: f64, rules: Ruleset) -> (f64, f64) {
+        match rules.scoring() {
+            Scoring::Area => self.area_score(komi),
+            Scoring::Territory => self.territory_score(komi),
+        }
+    }
+
+    /// 终局数子：先从两盘移除双方确认的死子（数目计分时算作提子），再按规则数子
+    pub fn score_with_dead(&self, dead: &[BTreeSet<usize>; 2], komi: f64, rules: Ruleset) -> (f64, f64) {
+        let mut game = self.clone();
+        for (b, points) in dead.iter().enumerate() {
+            for &p in points {
+                match game.boards[b].cells.get_mut(p).and_then(Option::take) {
+                    Some(Stone::Black) => game.captured.0 += 1,
+                    Some(Stone::White) => game.captured.1 += 1,
+                    None => {}
+                }
+            }
+        }
+        game.score(komi, rules)
+    }
+
+    pub fn position_hash(&self) -> u64 {
+        let mut h = DefaultHasher::new();
+        self.boards[0].cells.hash(&mut h);
+        self.boards[1].cells.hash(&mut h);
+        self.to_move.hash(&mut h);
+        self.move_number.min(2).hash(&mut h);
+        h.finish()
+    }
+
+    /// 按对局记录重放（每手为 A 盘落点；开局后点击量子位置时 A 盘落点即为映射后的位置）
+    pub fn replay(size: usize, moves: &[(Stone, Option<usize>)]) -> Result<Self, (usize, IllegalMove)> {
+        Self::replay_from(Self::new(size), moves)
+    }
+
+    /// 从给定初始局面（如让子局）开始重放
+    pub fn replay_from(mut game: Self, moves: &[(Stone, Option<usize>)]) -> Result<Self, (usize, IllegalMove)> {
+        for (i, &(color, idx)) in moves.iter().enumerate() {
+            if color != game.to_move {
+                // Records may omit passes; keep the side to move in step with the record
+                game.to_move = color;
+            }
+            match idx {
+                Some(idx) => {
+                    game.play(idx).map_err(|e| (i, e))?;
+                }
+                None => game.pass(),
+            }
+        }
+        Ok(game)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(game: &QuantumGame, pos: &str) -> usize {
+        game.index_of(pos).unwrap()
+    }
+
+    #[test]
+    fn test_opening_inverts_colors_and_links_brothers() {
+        let mut game = QuantumGame::new(9);
+        let (p, q) = (at(&game, "3,3"), at(&game, "7,7"));
+        game.play(p).unwrap();
+        assert_eq!(game.boards[0].get(p), Some(Stone::Black));
+        assert_eq!(game.boards[1].get(p), Some(Stone::White));
+        game.play(q).unwrap();
+        assert_eq!(game.boards[0].get(q), Some(Stone::White));
+        assert_eq!(game.boards[1].get(q), Some(Stone::Black));
+        // Same-colour stones are brothers across realities
+        assert_eq!(game.boards[0].brother(p), Some(q));
+        assert_eq!(game.boards[1].brother(q), Some(p));
+
+        let r = at(&game, "5,5");
+        game.play(r).unwrap();
+        assert_eq!(game.boards[0].get(r), Some(Stone::Black));
+        assert_eq!(game.boards[1].get(r), Some(Stone::Black));
+        assert_eq!(game.area_score(7.5).1 - game.area_score(0.0).1, 7.5);
+    }
+
+    #[test]
+    fn test_capture_removes_entangled_brother() {
+        // Black's quantum stone at 1,1 on A pairs with 9,9 on B (Black's colour there)
+        let mut game = QuantumGame::new(9);
+        game.play(at(&game, "1,1")).unwrap(); // B
+        game.play(at(&game, "9,9")).unwrap(); // W
+        game.play(at(&game, "5,5")).unwrap(); // B
+        game.play(at(&game, "1,2")).unwrap(); // W
+        game.play(at(&game, "5,6")).unwrap(); // B
+        let out = game.play(at(&game, "2,1")).unwrap(); // W captures 1,1 on A
+        let (a11, b99) = (at(&game, "1,1"), at(&game, "9,9"));
+        assert_eq!(out.captured[0], vec![a11]);
+        assert!(out.captured[1].contains(&b99));
+        assert_eq!(game.boards[0].get(a11), None);
+        assert_eq!(game.boards[1].get(b99), None);
+        // On B, 1,1 is a White stone that survives
+        assert_eq!(game.boards[1].get(a11), Some(Stone::White));
+    }
+
+    #[test]
+    fn test_territory_scoring_counts_prisoners_instead_of_stones() {
+        let mut game = QuantumGame::new(9);
+        for pos in ["1,1", "9,9", "5,5", "1,2", "5,6"] {
+            game.play(at(&game, pos)).unwrap();
+        }
+        let out = game.play(at(&game, "2,1")).unwrap();
+        let removed = (out.captured[0].len() + out.captured[1].len()) as u32;
+        assert_eq!(game.captured, (removed, 0));
+
+        let stones = |color: Stone| game.boards.iter().map(|b| b.stones().filter(|&(_, s)| s == color).count() as f64).sum::<f64>();
+        let (area_black, area_white) = game.score(6.5, Ruleset::Chinese);
+        let (black, white) = game.score(6.5, Ruleset::Japanese);
+        assert_eq!(black, area_black - stones(Stone::Black));
+        assert_eq!(white, area_white - stones(Stone::White) + removed as f64);
+    }
+
+    #[test]
+    fn test_dead_stones_are_removed_before_counting() {
+        let mut game = QuantumGame::new(9);
+        for pos in ["3,3", "7,7", "5,5", "1,1"] {
+            game.play(at(&game, pos)).unwrap();
+        }
+        // One open region touching both colours: only stones count
+        assert_eq!(game.score(7.5, Ruleset::Quantum), (4.0, 11.5));
+        let w = at(&game, "1,1");
+        assert_eq!(game.boards[0].chain(w), vec![w]);
+        assert!(game.boards[0].chain(at(&game, "2,2")).is_empty());
+        let dead = [BTreeSet::from([w]), BTreeSet::from([w])];
+        assert_eq!(game.score_with_dead(&dead, 7.5, Ruleset::Quantum), (4.0, 9.5));
+        // Territory scoring: the two dead stones become Black's prisoners
+        assert_eq!(game.score_with_dead(&dead, 7.5, Ruleset::Japanese), (2.0, 7.5));
+    }
+
+    #[test]
+    fn test_move_must_be_legal_on_both_boards() {
+        let mut game = QuantumGame::new(9);
+        game.play(at(&game, "1,2")).unwrap(); // B: A black, B white
+        game.play(at(&game, "9,9")).unwrap(); // W
+        game.play(at(&game, "2,1")).unwrap(); // B
+        // White at 1,1 is suicide on A (surrounded by black) even though B has a white neighbour
+        assert!(!game.is_legal(at(&game, "1,1")));
+        assert!(game.is_legal(at(&game, "5,5")));
+    }
+
+    #[test]
+    fn test_handicap_game_starts_with_white_quantum_pair() {
+        let probe = QuantumGame::new(9);
+        let stones = [at(&probe, "3,7"), at(&probe, "7,3")];
+        let mut game = QuantumGame::with_handicap(9, &stones);
+        assert_eq!(game.to_move, Stone::White);
+        assert_eq!(game.boards[1].get(stones[0]), Some(Stone::Black));
+        let (w, b) = (at(&game, "3,3"), at(&game, "7,7"));
+        game.play(w).unwrap();
+        game.play(b).unwrap();
+        assert_eq!(game.boards[1].get(w), Some(Stone::Black));
+        // Clicking White's captured quantum point maps to White's anchor on A
+        assert_eq!(game.map_move(w, Stone::White), [w, b]);
+        assert_eq!(game.map_move(w, Stone::Black), [b, w]);
+    }
+
+    #[test]
+    fn test_replay_matches_live_play() {
+        let mut live = QuantumGame::new(9);
+        let seq = ["3,3", "7,7", "5,5", "3,7"];
+        let mut record = Vec::new();
+        for (i, pos) in seq.iter().enumerate() {
+            let color = if i % 2 == 0 { Stone::Black } else { Stone::White };
+            record.push((color, Some(at(&live, pos))));
+            live.play(at(&live, pos)).unwrap();
+        }
+        let replayed = QuantumGame::replay(9, &record).unwrap();
+        assert_eq!(replayed.boards, live.boards);
+        assert_eq!(replayed.position_hash(), live.position_hash());
+    }
+}
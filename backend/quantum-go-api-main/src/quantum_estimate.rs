@@ -0,0 +1,274 @@
+// 量子围棋形势判断：两盘按纠缠关系联合估算
+//
+// 逐盘调用 score-estimator 得到归属图与死子后再联合处理：
+// - 纠缠子同生同死：一块棋被提时另一盘上的兄弟子随之移除，所以一盘判死的子，其兄弟子也判死；
+//   只有当判死一方的归属图并不支持（该点不归对方）时才撤销死子标记，两颗子一起按活棋处理
+// - 每盘面积 = 归属为黑/白的点数，合计结果 = 两盘之和，贴目只计一次（与 QuantumGame::area_score 一致）
+// - 置信度 = 两盘中归属已定的点所占比例 × 纠缠子判断无冲突的比例
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::io;
+
+use crate::katago::{ScoreEstimateBoardRequest, ScoreEstimateBoardResponse, ScoreEstimateRequest, ScoreEstimateResponse};
+use crate::score_estimator::{self, BoardEstimate, EstimateParams};
+
+pub const DEFAULT_KOMI: f32 = 7.5;
+
+/// 两盘合计的形势
+#[derive(Debug, Serialize)]
+pub struct CombinedEstimate {
+    pub black_score: f32,
+    pub white_score: f32,
+    pub komi: f32,
+    pub score_lead: f32, // 黑方领先（已扣贴目）
+    pub winner: String,
+    pub winrate: f32, // 黑方胜率
+    pub confidence: f32,
+    pub disputed_pairs: usize, // 一盘判死、另一盘明显是活棋的纠缠子对
+}
+
+// One reality after estimation; indices follow the estimator layout ((y-1) * size + (x-1) for "x,y")
+struct Reality {
+    size: usize,
+    stones: HashMap<usize, f32>, // 1.0 = black, -1.0 = white
+    ownership: Vec<f32>,
+    dead: HashSet<usize>,
+}
+
+impl Reality {
+    fn new(size: usize, black: &[String], white: &[String], estimate: BoardEstimate) -> io::Result<Self> {
+        let (ownership, _, dead_pairs) = estimate;
+        let mut stones = HashMap::new();
+        for pos in black {
+            stones.insert(point(pos, size)?, 1.0);
+        }
+        for pos in white {
+            stones.insert(point(pos, size)?, -1.0);
+        }
+        let dead = dead_pairs
+            .into_iter()
+            .filter(|&(x, y)| x >= 0 && y >= 0 && (x as usize) < size && (y as usize) < size)
+            .map(|(x, y)| y as usize * size + x as usize)
+            .collect();
+        Ok(Self { size, stones, ownership, dead })
+    }
+
+    // How strongly the opponent of the stone at `idx` owns the point (positive = opponent)
+    fn against(&self, idx: usize) -> f32 {
+        -self.ownership[idx] * self.stones[&idx]
+    }
+
+    fn set_dead(&mut self, idx: usize, dead: bool) {
+        let color = self.stones[&idx];
+        self.ownership[idx] = if dead { -color } else { color };
+        if dead {
+            self.dead.insert(idx);
+        } else {
+            self.dead.remove(&idx);
+        }
+    }
+
+    fn area(&self) -> (f32, f32) {
+        let black = self.ownership.iter().filter(|&&v| v > 0.0).sum::<f32>();
+        let white = -self.ownership.iter().filter(|&&v| v < 0.0).sum::<f32>();
+        (black, white)
+    }
+
+    fn settled(&self) -> usize {
+        self.ownership.iter().filter(|&&v| v.abs() >= 0.5).count()
+    }
+
+    fn dead_flat(&self) -> Vec<i32> {
+        let mut dead: Vec<usize> = self.dead.iter().copied().collect();
+        dead.sort_unstable();
+        dead.into_iter()
+            .flat_map(|idx| [(idx % self.size) as i32, (idx / self.size) as i32])
+            .collect()
+    }
+}
+
+fn point(pos: &str, size: usize) -> io::Result<usize> {
+    let bad = || io::Error::new(io::ErrorKind::InvalidInput, format!("invalid position: {}", pos));
+    let (x, y) = pos.split_once(',').ok_or_else(bad)?;
+    let x: usize = x.trim().parse().map_err(|_| bad())?;
+    let y: usize = y.trim().parse().map_err(|_| bad())?;
+    if x == 0 || y == 0 || x > size || y > size {
+        return Err(bad());
+    }
+    Ok((y - 1) * size + (x - 1))
+}
+
+// Makes dead marks agree across brother pairs; returns the number of disputed pairs
+fn reconcile(realities: &mut [Reality; 2], links: &[(usize, usize)]) -> usize {
+    let mut disputed = 0;
+    for &(a, b) in links {
+        // Stale links (one of the stones is already gone) carry no constraint
+        if !realities[0].stones.contains_key(&a) || !realities[1].stones.contains_key(&b) {
+            continue;
+        }
+        let (dead_a, dead_b) = (realities[0].dead.contains(&a), realities[1].dead.contains(&b));
+        if dead_a == dead_b {
+            continue;
+        }
+        let (flagged, other) = if dead_a { (realities[0].against(a), realities[1].against(b)) } else { (realities[1].against(b), realities[0].against(a)) };
+        let dead = flagged > 0.0;
+        if dead && other < 0.0 {
+            disputed += 1;
+        }
+        realities[0].set_dead(a, dead);
+        realities[1].set_dead(b, dead);
+    }
+    disputed
+}
+
+// Black's winning chance from the lead; undecided points widen the spread
+fn winrate(lead: f32, undecided: usize) -> f32 {
+    let scale = 1.0 + undecided as f32 / 2.0;
+    1.0 / (1.0 + (-lead / scale).exp())
+}
+
+fn combine(realities: &[Reality; 2], komi: f32, disputed: usize, links: usize) -> CombinedEstimate {
+    let (b0, w0) = realities[0].area();
+    let (b1, w1) = realities[1].area();
+    let (black_score, white_score) = (b0 + b1, w0 + w1 + komi);
+    let score_lead = black_score - white_score;
+    let points: usize = realities.iter().map(|r| r.ownership.len()).sum();
+    let settled: usize = realities.iter().map(Reality::settled).sum();
+    let consistency = if links == 0 { 1.0 } else { 1.0 - disputed as f32 / links as f32 };
+    CombinedEstimate {
+        black_score,
+        white_score,
+        komi,
+        score_lead,
+        winner: if score_lead > 0.0 { "black" } else { "white" }.to_string(),
+        winrate: winrate(score_lead, points - settled),
+        confidence: if points == 0 { 0.0 } else { settled as f32 / points as f32 * consistency },
+        disputed_pairs: disputed,
+    }
+}
+
+async fn reality_of(board: &ScoreEstimateBoardRequest, params: EstimateParams) -> io::Result<Reality> {
+    let estimate = score_estimator::estimate_board_blocking(
+        board.board_size,
+        &board.black_stones,
+        &board.white_stones,
+        board.next_to_move.as_deref(),
+        params,
+    )
+    .await?;
+    Reality::new(board.board_size as usize, &board.black_stones, &board.white_stones, estimate)
+}
+
+/// quantum 模式：恰好两盘（A、B），brothers 为 [A 盘位置, B 盘位置] 的纠缠子对
+pub async fn estimate(req: &ScoreEstimateRequest, params: EstimateParams) -> io::Result<ScoreEstimateResponse> {
+    let [a, b] = req.boards.as_slice() else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "quantum mode needs exactly two boards"));
+    };
+    if a.board_size != b.board_size {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "boards must have the same size"));
+    }
+    let size = a.board_size as usize;
+    let links = req
+        .brothers
+        .iter()
+        .flatten()
+        .map(|(pa, pb)| Ok((point(pa, size)?, point(pb, size)?)))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let (ra, rb) = tokio::try_join!(reality_of(a, params), reality_of(b, params))?;
+    let mut realities = [ra, rb];
+    let disputed = reconcile(&mut realities, &links);
+    let combined = combine(&realities, req.komi.unwrap_or(DEFAULT_KOMI), disputed, links.len());
+    let boards = realities
+        .iter()
+        .enumerate()
+        .map(|(idx, reality)| {
+            let (black, white) = reality.area();
+            ScoreEstimateBoardResponse {
+                board_index: idx,
+                board_size: a.board_size,
+                ownership: reality.ownership.clone(),
+                winrate: combined.winrate,
+                score_lead: black - white,
+                confidence: reality.settled() as f32 / reality.ownership.len().max(1) as f32,
+                dead_stones: reality.dead_flat(),
+            }
+        })
+        .collect();
+    Ok(ScoreEstimateResponse { boards, combined: Some(combined) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reality(size: usize, black: &[&str], white: &[&str], ownership: Vec<f32>, dead: &[&str]) -> Reality {
+        let strings = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let dead = dead
+            .iter()
+            .map(|p| {
+                let idx = point(p, size).unwrap();
+                ((idx % size) as i32, (idx / size) as i32)
+            })
+            .collect();
+        Reality::new(size, &strings(black), &strings(white), (ownership, Vec::new(), dead)).unwrap()
+    }
+
+    #[test]
+    fn test_dead_stone_takes_its_brother_with_it() {
+        let size = 3;
+        // A: black stone at 2,2 judged dead inside white's area
+        let a = reality(size, &["2,2"], &["1,2", "2,1"], vec![-1.0; 9], &["2,2"]);
+        // B: its brother at 3,3 looks alive on its own board
+        let b = reality(size, &["3,3"], &[], vec![1.0; 9], &[]);
+        let mut realities = [a, b];
+        let links = [(point("2,2", size).unwrap(), point("3,3", size).unwrap())];
+        assert_eq!(reconcile(&mut realities, &links), 1);
+        assert_eq!(realities[1].dead_flat(), vec![2, 2]);
+        assert_eq!(realities[1].ownership[8], -1.0);
+
+        // Komi counted once over both boards
+        let combined = combine(&realities, 7.5, 1, 1);
+        assert_eq!(combined.black_score, 8.0);
+        assert_eq!(combined.white_score, 9.0 + 1.0 + 7.5);
+        assert_eq!(combined.winner, "white");
+        assert!(combined.winrate < 0.5);
+        assert_eq!(combined.confidence, 0.0);
+    }
+
+    #[test]
+    fn test_unsupported_dead_mark_is_dropped_on_both_boards() {
+        let size = 3;
+        // Dead mark on A although the point is not owned by white
+        let mut own_a = vec![0.0; 9];
+        own_a[point("2,2", size).unwrap()] = 1.0;
+        let a = reality(size, &["2,2"], &["1,1"], own_a, &["2,2"]);
+        let b = reality(size, &["1,3"], &[], vec![1.0; 9], &[]);
+        let mut realities = [a, b];
+        let links = [(point("2,2", size).unwrap(), point("1,3", size).unwrap())];
+        assert_eq!(reconcile(&mut realities, &links), 0);
+        assert!(realities[0].dead.is_empty() && realities[1].dead.is_empty());
+        let combined = combine(&realities, 0.5, 0, 1);
+        assert_eq!(combined.score_lead, 1.0 + 9.0 - 0.5);
+        assert!((combined.confidence - 10.0 / 18.0).abs() < 1e-6);
+    }
+
+    #[tokio::test]
+    async fn test_quantum_mode_validates_boards() {
+        let board = |size| ScoreEstimateBoardRequest { board_size: size, black_stones: vec!["3,3".to_string()], white_stones: Vec::new(), next_to_move: None };
+        let req = |boards, brothers| ScoreEstimateRequest { boards, mode: Some("quantum".to_string()), komi: None, brothers, trials: None, tolerance: None };
+        let params = |trials| EstimateParams::resolve(Some(trials), None).unwrap();
+        let err = estimate(&req(vec![board(9)], None), params(10)).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = estimate(&req(vec![board(9), board(13)], None), params(10)).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = estimate(&req(vec![board(9), board(9)], Some(vec![("3,3".to_string(), "10,1".to_string())])), params(10)).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let resp = estimate(&req(vec![board(9), board(9)], Some(vec![("3,3".to_string(), "3,3".to_string())])), params(50)).await.unwrap();
+        assert_eq!(resp.boards.len(), 2);
+        let combined = resp.combined.unwrap();
+        assert_eq!(combined.komi, DEFAULT_KOMI);
+        assert_eq!(combined.score_lead, resp.boards[0].score_lead + resp.boards[1].score_lead - DEFAULT_KOMI);
+    }
+}
@@ -0,0 +1,493 @@
+// 量子围棋 AI 搜索
+//
+// 按量子规则（quantum.rs）在两个现实上同时展开着法，叶子用 KataGo 分别评估两盘（同一批查询），
+// 两盘黑方领先目数相加、贴目计一次，作为叶子价值。搜索为迭代加深的 negamax + alpha-beta，
+// 候选着按两盘策略先验之和排序，只展开前 width 个；受节点数与时间预算限制，
+// 预算用尽时采用最后一个完整深度的结果。同一次搜索内评估按局面哈希缓存。
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::handicap::handicap_points;
+use crate::katago::gtp_to_xy;
+use crate::katago_analysis::{self, board_query};
+use crate::quantum::{QuantumGame, Stone};
+
+const DEFAULT_MAX_NODES: usize = 64;
+const DEFAULT_TIME_MS: u64 = 5_000;
+const DEFAULT_MAX_DEPTH: u32 = 3;
+const DEFAULT_WIDTH: usize = 6;
+const DEFAULT_LEAF_VISITS: u32 = 32;
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key).ok().and_then(|v| v.trim().parse().ok()).unwrap_or(default)
+}
+
+/// 叶子评估：两盘各自的黑方领先目数（贴目按 0 计），以及按落点汇总的候选先验
+#[derive(Debug, Clone, Default)]
+pub struct LeafEval {
+    pub board_leads: [f64; 2],
+    pub priors: Vec<(usize, f64)>,
+}
+
+pub trait LeafEvaluator: Sync {
+    fn evaluate(&self, game: &QuantumGame) -> impl Future<Output = io::Result<LeafEval>> + Send;
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchLimits {
+    pub max_nodes: usize,
+    pub time_budget: Duration,
+    pub max_depth: u32,
+    pub width: usize,
+}
+
+impl SearchLimits {
+    // QUANTUM_SEARCH_MAX_NODES / QUANTUM_SEARCH_TIME_MS / QUANTUM_SEARCH_DEPTH / QUANTUM_SEARCH_WIDTH
+    pub fn from_env() -> Self {
+        Self {
+            max_nodes: env_or("QUANTUM_SEARCH_MAX_NODES", DEFAULT_MAX_NODES).max(1),
+            time_budget: Duration::from_millis(env_or("QUANTUM_SEARCH_TIME_MS", DEFAULT_TIME_MS)),
+            max_depth: env_or("QUANTUM_SEARCH_DEPTH", DEFAULT_MAX_DEPTH).max(1),
+            width: env_or("QUANTUM_SEARCH_WIDTH", DEFAULT_WIDTH).max(1),
+        }
+    }
+
+    /// 请求可以收紧预算，但不能超过服务器配置
+    pub fn capped(mut self, max_nodes: Option<usize>, time_ms: Option<u64>) -> Self {
+        if let Some(n) = max_nodes {
+            self.max_nodes = n.clamp(1, self.max_nodes);
+        }
+        if let Some(ms) = time_ms {
+            self.time_budget = self.time_budget.min(Duration::from_millis(ms));
+        }
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchOutcome {
+    /// None 表示 pass
+    pub best: Option<usize>,
+    /// 行棋方视角的合计领先目数（含贴目）
+    pub value: f64,
+    /// 主变化末端两盘各自的领先目数（行棋方视角，贴目平分到两盘）
+    pub board_leads: [f64; 2],
+    pub nodes: usize,
+    pub depth: u32,
+}
+
+// Search result below a node; values are from the side to move at that node
+struct Line {
+    value: f64,
+    // Black-perspective leads at the end of the principal variation
+    board_leads: [f64; 2],
+    best: Option<Option<usize>>,
+}
+
+type LineFuture<'a> = Pin<Box<dyn Future<Output = io::Result<Option<Line>>> + Send + 'a>>;
+
+struct Searcher<'a, E: LeafEvaluator> {
+    evaluator: &'a E,
+    komi: f64,
+    limits: &'a SearchLimits,
+    deadline: Instant,
+    nodes: AtomicUsize,
+    cache: Mutex<HashMap<u64, Arc<LeafEval>>>,
+}
+
+impl<'a, E: LeafEvaluator> Searcher<'a, E> {
+    fn exhausted(&self) -> bool {
+        self.nodes.load(Ordering::Relaxed) >= self.limits.max_nodes || Instant::now() >= self.deadline
+    }
+
+    async fn evaluate(&self, game: &QuantumGame) -> io::Result<Arc<LeafEval>> {
+        let key = game.position_hash();
+        if let Some(hit) = self.cache.lock().unwrap().get(&key) {
+            return Ok(hit.clone());
+        }
+        let eval = Arc::new(self.evaluator.evaluate(game).await?);
+        self.nodes.fetch_add(1, Ordering::Relaxed);
+        self.cache.lock().unwrap().insert(key, eval.clone());
+        Ok(eval)
+    }
+
+    fn black_value(&self, board_leads: &[f64; 2]) -> f64 {
+        board_leads[0] + board_leads[1] - self.komi
+    }
+
+    fn for_side(value: f64, side: Stone) -> f64 {
+        if side == Stone::Black { value } else { -value }
+    }
+
+    // Candidate moves ordered by prior, quantum-legal only; pass is offered once the opening is over
+    fn candidates(&self, game: &QuantumGame, eval: &LeafEval, first: Option<Option<usize>>) -> Vec<Option<usize>> {
+        let mut priors = eval.priors.clone();
+        priors.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let mut out: Vec<Option<usize>> = Vec::new();
+        if let Some(m) = first {
+            if m.is_none_or(|idx| game.is_legal(idx)) {
+                out.push(m);
+            }
+        }
+        for (idx, _) in priors {
+            if out.len() >= self.limits.width {
+                break;
+            }
+            if !out.contains(&Some(idx)) && game.is_legal(idx) {
+                out.push(Some(idx));
+            }
+        }
+        if out.iter().filter(|m| m.is_some()).count() < self.limits.width {
+            for idx in game.legal_moves() {
+                if out.len() >= self.limits.width {
+                    break;
+                }
+                if !out.contains(&Some(idx)) {
+                    out.push(Some(idx));
+                }
+            }
+        }
+        if game.move_number >= 2 && !out.contains(&None) {
+            out.push(None);
+        }
+        out
+    }
+
+    fn child(game: &QuantumGame, mv: Option<usize>) -> QuantumGame {
+        let mut child = game.clone();
+        match mv {
+            Some(idx) => {
+                child.play(idx).expect("candidate checked for legality");
+            }
+            None => child.pass(),
+        }
+        child
+    }
+
+    fn negamax(&'a self, game: QuantumGame, depth: u32, mut alpha: f64, beta: f64, enforce_budget: bool, first: Option<Option<usize>>) -> LineFuture<'a> {
+        Box::pin(async move {
+            let side = game.to_move;
+            if game.is_over() {
+                let (black, white) = game.area_score(self.komi);
+                let (b0, w0) = game.boards[0].area_score();
+                let (b1, w1) = game.boards[1].area_score();
+                return Ok(Some(Line { value: Self::for_side(black - white, side), board_leads: [b0 - w0, b1 - w1], best: None }));
+            }
+            if enforce_budget && self.exhausted() {
+                return Ok(None);
+            }
+            let eval = self.evaluate(&game).await?;
+            if depth == 0 {
+                let value = Self::for_side(self.black_value(&eval.board_leads), side);
+                return Ok(Some(Line { value, board_leads: eval.board_leads, best: None }));
+            }
+
+            let moves = self.candidates(&game, &eval, first);
+            let children: Vec<QuantumGame> = moves.iter().map(|&m| Self::child(&game, m)).collect();
+            if depth == 1 {
+                // Frontier children are leaves: evaluate them concurrently so the engine can batch
+                join_all(children.iter().filter(|c| !c.is_over()).map(|c| self.evaluate(c)))
+                    .await
+                    .into_iter()
+                    .collect::<io::Result<Vec<_>>>()?;
+            }
+
+            let mut best: Option<Line> = None;
+            for (mv, child) in moves.into_iter().zip(children) {
+                let Some(line) = self.negamax(child, depth - 1, -beta, -alpha, enforce_budget, None).await? else {
+                    return Ok(None);
+                };
+                let value = -line.value;
+                if best.as_ref().is_none_or(|b| value > b.value) {
+                    best = Some(Line { value, board_leads: line.board_leads, best: Some(mv) });
+                }
+                alpha = alpha.max(value);
+                if alpha >= beta {
+                    break;
+                }
+            }
+            Ok(best)
+        })
+    }
+}
+
+/// 在当前局面为行棋方搜索一手
+pub async fn search<E: LeafEvaluator>(game: &QuantumGame, komi: f64, limits: &SearchLimits, evaluator: &E) -> io::Result<SearchOutcome> {
+    let searcher = Searcher {
+        evaluator,
+        komi,
+        limits,
+        deadline: Instant::now() + limits.time_budget,
+        nodes: AtomicUsize::new(0),
+        cache: Mutex::new(HashMap::new()),
+    };
+
+    let mut result: Option<(Line, u32)> = None;
+    for depth in 1..=limits.max_depth {
+        // The first iteration always completes so there is a move to return
+        let enforce_budget = depth > 1;
+        if enforce_budget && searcher.exhausted() {
+            break;
+        }
+        let first = result.as_ref().and_then(|(line, _)| line.best);
+        match searcher.negamax(game.clone(), depth, f64::NEG_INFINITY, f64::INFINITY, enforce_budget, first).await? {
+            Some(line) => result = Some((line, depth)),
+            None => break,
+        }
+    }
+
+    let (line, depth) = result.ok_or_else(|| io::Error::other("quantum search produced no move"))?;
+    let side = game.to_move;
+    let half_komi = komi / 2.0;
+    Ok(SearchOutcome {
+        best: line.best.flatten(),
+        value: line.value,
+        board_leads: [
+            Searcher::<E>::for_side(line.board_leads[0] - half_komi, side),
+            Searcher::<E>::for_side(line.board_leads[1] - half_komi, side),
+        ],
+        nodes: searcher.nodes.load(Ordering::Relaxed),
+        depth,
+    })
+}
+
+/// 用 KataGo 分析引擎评估叶子：两盘以摆子方式作为同一批的两个查询（贴目 0）
+pub struct KataGoEvaluator {
+    pub rules: String,
+    pub max_visits: u32,
+}
+
+impl KataGoEvaluator {
+    pub fn from_env(rules: String) -> Self {
+        Self { rules, max_visits: env_or("QUANTUM_SEARCH_LEAF_VISITS", DEFAULT_LEAF_VISITS) }
+    }
+}
+
+impl LeafEvaluator for KataGoEvaluator {
+    async fn evaluate(&self, game: &QuantumGame) -> io::Result<LeafEval> {
+        let engine = katago_analysis::shared_engine().await?;
+        let queries = [
+            board_query(engine.next_query_id("qs-a"), game, 0, &self.rules, 0.0, Some(self.max_visits), false)?,
+            board_query(engine.next_query_id("qs-b"), game, 1, &self.rules, 0.0, Some(self.max_visits), false)?,
+        ];
+        let responses = katago_analysis::run_batch(&queries).await?;
+
+        let mut leads = [0.0; 2];
+        let mut priors: HashMap<usize, f64> = HashMap::new();
+        for (board, resp) in responses.iter().enumerate() {
+            // Responses are from the side to move on that board; convert to Black
+            let to_move = game.color_on(board, game.to_move);
+            leads[board] = if to_move == Stone::Black { resp.root_info.score_lead } else { -resp.root_info.score_lead };
+            for mi in &resp.move_infos {
+                let Ok(xy) = gtp_to_xy(&mi.move_coord, game.size as u8) else { continue };
+                if let Some(idx) = game.index_of(&xy) {
+                    *priors.entry(idx).or_insert(0.0) += mi.prior;
+                }
+            }
+        }
+        Ok(LeafEval { board_leads: leads, priors: priors.into_iter().collect() })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QuantumMoveItem {
+    pub color: String,
+    /// 棋盘 A 上的落点 "x,y"；"pass" 或缺省表示虚手
+    pub position: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QuantumSearchRequest {
+    pub board_size: u8,
+    pub moves: Vec<QuantumMoveItem>,
+    pub komi: Option<f64>,
+    pub rules: Option<String>,
+    pub max_nodes: Option<usize>,
+    pub time_ms: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BoardValue {
+    /// 该盘上的实际落点 "x,y"（pass 时为空）
+    pub position: Option<String>,
+    pub score_lead: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QuantumSearchResponse {
+    /// 点击坐标 "x,y" 或 "pass"
+    pub move_coord: String,
+    pub board_a: BoardValue,
+    pub board_b: BoardValue,
+    pub combined_score_lead: f64,
+    pub nodes: usize,
+    pub depth: u32,
+}
+
+/// 按记录重建量子对局
+pub fn game_from_moves(board_size: u8, moves: &[QuantumMoveItem]) -> io::Result<QuantumGame> {
+    let size = board_size as usize;
+    let probe = QuantumGame::new(size);
+    let record = moves
+        .iter()
+        .map(|m| {
+            let color = Stone::parse(&m.color)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid color: {}", m.color)))?;
+            let idx = match m.position.as_deref() {
+                None => None,
+                Some(p) if p.eq_ignore_ascii_case("pass") => None,
+                Some(p) => Some(probe.index_of(p).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid position: {}", p)))?),
+            };
+            Ok((color, idx))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    QuantumGame::replay(size, &record)
+        .map_err(|(i, e)| io::Error::new(io::ErrorKind::InvalidInput, format!("move {}: {}", i + 1, e)))
+}
+
+/// 从 chessman_records 取出每手的颜色与 A 盘落点（记录中不含 pass）
+pub fn parse_records(records: &serde_json::Value, size: usize) -> io::Result<Vec<(Stone, usize)>> {
+    let probe = QuantumGame::new(size);
+    let invalid = |i: usize, what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("record {}: {}", i + 1, what));
+    records
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .enumerate()
+        .map(|(i, record)| {
+            let added = record.get("add").and_then(|a| a.get(0)).ok_or_else(|| invalid(i, "missing stone"))?;
+            let color = added.get("type").and_then(|t| t.as_str()).and_then(Stone::parse).ok_or_else(|| invalid(i, "bad color"))?;
+            let idx = added
+                .get("position")
+                .and_then(|p| p.as_str())
+                .and_then(|p| probe.index_of(p))
+                .ok_or_else(|| invalid(i, "bad position"))?;
+            Ok((color, idx))
+        })
+        .collect()
+}
+
+/// 让子局的初始局面（让子 < 2 时为空盘）
+pub fn handicap_game(model: i32, handicap: i32) -> QuantumGame {
+    let size = model as usize;
+    let probe = QuantumGame::new(size);
+    let stones: Vec<usize> = handicap_points(model, handicap)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|p| probe.index_of(p))
+        .collect();
+    QuantumGame::with_handicap(size, &stones)
+}
+
+/// 按房间的 chessman_records 重建当前局面
+pub fn game_from_records(model: i32, handicap: i32, records: &serde_json::Value) -> io::Result<QuantumGame> {
+    let moves: Vec<(Stone, Option<usize>)> = parse_records(records, model as usize)?
+        .into_iter()
+        .map(|(color, idx)| (color, Some(idx)))
+        .collect();
+    QuantumGame::replay_from(handicap_game(model, handicap), &moves)
+        .map_err(|(i, e)| io::Error::new(io::ErrorKind::InvalidData, format!("move {}: {}", i + 1, e)))
+}
+
+pub async fn quantum_search(req: QuantumSearchRequest) -> io::Result<QuantumSearchResponse> {
+    if !matches!(req.board_size, 7 | 9 | 13 | 19) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported board size"));
+    }
+    let game = game_from_moves(req.board_size, &req.moves)?;
+    let komi = req.komi.unwrap_or(7.5);
+    let limits = SearchLimits::from_env().capped(req.max_nodes, req.time_ms);
+    let evaluator = KataGoEvaluator::from_env(katago_analysis::rules_name(req.rules.as_deref())?.to_string());
+    let outcome = search(&game, komi, &limits, &evaluator).await?;
+
+    let positions = outcome.best.map(|idx| game.map_move(idx, game.to_move));
+    Ok(QuantumSearchResponse {
+        move_coord: outcome.best.map(|idx| game.position_of(idx)).unwrap_or_else(|| "pass".to_string()),
+        board_a: BoardValue { position: positions.map(|p| game.position_of(p[0])), score_lead: outcome.board_leads[0] },
+        board_b: BoardValue { position: positions.map(|p| game.position_of(p[1])), score_lead: outcome.board_leads[1] },
+        combined_score_lead: outcome.value,
+        nodes: outcome.nodes,
+        depth: outcome.depth,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Area count of each board as the leaf value; every legal move gets the same prior
+    struct AreaEvaluator;
+
+    impl LeafEvaluator for AreaEvaluator {
+        async fn evaluate(&self, game: &QuantumGame) -> io::Result<LeafEval> {
+            let lead = |b: usize| {
+                let (black, white) = game.boards[b].area_score();
+                black - white
+            };
+            Ok(LeafEval { board_leads: [lead(0), lead(1)], priors: Vec::new() })
+        }
+    }
+
+    fn limits(max_nodes: usize, max_depth: u32) -> SearchLimits {
+        SearchLimits { max_nodes, time_budget: Duration::from_secs(10), max_depth, width: 81 }
+    }
+
+    fn play_all(game: &mut QuantumGame, moves: &[&str]) {
+        for m in moves {
+            let idx = game.index_of(m).unwrap();
+            game.play(idx).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_captures_entangled_stone() {
+        // White can capture Black's quantum stone at 1,1 on A, which also removes its brother on B
+        let mut game = QuantumGame::new(7);
+        play_all(&mut game, &["1,1", "7,7", "4,4", "1,2", "4,5"]);
+        let outcome = search(&game, 0.0, &limits(500, 1), &AreaEvaluator).await.unwrap();
+        assert_eq!(outcome.best, game.index_of("2,1"));
+        assert!(outcome.nodes > 1);
+    }
+
+    #[tokio::test]
+    async fn test_search_respects_node_budget() {
+        let mut game = QuantumGame::new(7);
+        play_all(&mut game, &["2,2", "6,6"]);
+        let outcome = search(&game, 7.5, &limits(10, 4), &AreaEvaluator).await.unwrap();
+        // Depth 1 always completes; deeper iterations stop once the budget is spent
+        assert_eq!(outcome.depth, 1);
+        assert!(outcome.best.is_some());
+        let combined = outcome.board_leads[0] + outcome.board_leads[1];
+        assert!((combined - outcome.value).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_game_from_moves_rejects_illegal_records() {
+        let moves = vec![
+            QuantumMoveItem { color: "black".into(), position: Some("3,3".into()) },
+            QuantumMoveItem { color: "white".into(), position: Some("3,3".into()) },
+        ];
+        let err = game_from_moves(9, &moves).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(game_from_moves(9, &moves[..1]).is_ok());
+    }
+
+    #[test]
+    fn test_parse_records_takes_board_a_point() {
+        let records = serde_json::json!([
+            {"add": [{"position": "3,3", "type": "black", "brother": "3,3"}], "reduce": []},
+            {"add": [{"position": "7,7", "type": "white", "brother": "7,7"}], "reduce": []}
+        ]);
+        let moves = parse_records(&records, 9).unwrap();
+        assert_eq!(moves, vec![(Stone::Black, 20), (Stone::White, 60)]);
+        let bad = serde_json::json!([{"add": [{"position": "10,1", "type": "black", "brother": ""}]}]);
+        assert_eq!(parse_records(&bad, 9).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,13 +1,24 @@
 use crate::db::Database;
-use crate::entity::{UserRanking, GameResult as MatchResult};
+use crate::entity::{UserRanking, OverallRating, PlayerRatingChange, RatingUpdate, GameResult as MatchResult};
 use uuid::Uuid;
 
 // glicko2 0.3.1 文档：GameResult::win/loss/draw(opponent_rating)
 // new_rating(prior, results, sys_constant) -> Glicko2Rating
-use glicko2::{Glicko2Rating, GameResult as GlickoGameResult, new_rating};
+// 注意：Glicko2Rating 是内部尺度（μ≈0, φ≈2），库表里存的是 Glicko 尺度（1500/350），需经 GlickoRating 转换
+use glicko2::{Glicko2Rating, GlickoRating, GameResult as GlickoGameResult, new_rating};
 
 const TAU: f64 = 0.5; // 系统常数 τ，0.3 ~ 1.2 之间自行选择
 
+const DEFAULT_RD: f64 = 350.0;
+
+// 用综合评分初始化新尺寸时，RD 在综合 RD 基础上放大，且不低于下限：
+// 不同尺寸的实力相关但不等同，需要留出较快收敛的空间
+const SEED_RD_BONUS: f64 = 100.0;
+const SEED_RD_FLOOR: f64 = 200.0;
+
+// 对等对局的贴目（两个现实合计得分，只贴一次）
+const EVEN_KOMI: f64 = 7.5;
+
 pub struct RatingSystem;
 
 impl RatingSystem {
@@ -19,30 +30,40 @@ impl RatingSystem {
         game_result: &MatchResult,
         black_player_id: Uuid,
         white_player_id: Uuid,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+        // AI 房间的机器人账号：评分固定，只更新对手
+        fixed_player: Option<Uuid>,
+    ) -> Result<RatingUpdate, Box<dyn std::error::Error>> {
         let model = game_result.model;
 
         // 1) 读取双方当前评级，如果不存在则创建默认记录
         let black_ranking = self.get_or_create_user_ranking(db, &black_player_id, model).await?;
         let white_ranking = self.get_or_create_user_ranking(db, &white_player_id, model).await?;
+        let black_old_rating = black_ranking.rating;
+        let white_old_rating = white_ranking.rating;
 
         // 2) 转成 glicko2 的 Rating 结构（字段名 value/deviation/volatility）
-        let black_rating = to_glicko2(&black_ranking);
-        let white_rating = to_glicko2(&white_ranking);
+        let black_rating = to_glicko2(&black_ranking, 0.0);
+        let white_rating = to_glicko2(&white_ranking, 0.0);
+
+        // 让子与贴目偏离折算为黑方的等效评分加成；更新一方时把加成计入对手评分，
+        // 这样期望得分随让子调整，指导棋也能公平计分
+        let offset = handicap_rating_offset(model, game_result.handicap, game_result.komi);
+        let black_as_opponent = to_glicko2(&black_ranking, offset);
+        let white_as_opponent = to_glicko2(&white_ranking, -offset);
 
         // 3) 构造对局结果（从各自视角）
         let (black_res, white_res) = match game_result.winner.as_deref() {
             Some("black") => (
-                [GlickoGameResult::win(white_rating)],
-                [GlickoGameResult::loss(black_rating)],
+                [GlickoGameResult::win(white_as_opponent)],
+                [GlickoGameResult::loss(black_as_opponent)],
             ),
             Some("white") => (
-                [GlickoGameResult::loss(white_rating)],
-                [GlickoGameResult::win(black_rating)],
+                [GlickoGameResult::loss(white_as_opponent)],
+                [GlickoGameResult::win(black_as_opponent)],
             ),
             _ => (
-                [GlickoGameResult::draw(white_rating)],
-                [GlickoGameResult::draw(black_rating)],
+                [GlickoGameResult::draw(white_as_opponent)],
+                [GlickoGameResult::draw(black_as_opponent)],
             ),
         };
 
@@ -51,10 +72,9 @@ impl RatingSystem {
         let new_white = new_rating(white_rating, &white_res, TAU);
 
         // 5) 写回数据库字段（你的表用 rating/rd/vol 命名）
+        let (nb_unchanged, nw_unchanged) = (black_ranking.clone(), white_ranking.clone());
         let mut nb = black_ranking;
-        nb.rating = new_black.value;
-        nb.rd     = new_black.deviation;
-        nb.vol    = new_black.volatility;
+        (nb.rating, nb.rd, nb.vol) = from_glicko2(new_black);
         nb.games_played += 1;
         match game_result.winner.as_deref() {
             Some("black") => nb.wins += 1,
@@ -63,9 +83,7 @@ impl RatingSystem {
         }
 
         let mut nw = white_ranking;
-        nw.rating = new_white.value;
-        nw.rd     = new_white.deviation;
-        nw.vol    = new_white.volatility;
+        (nw.rating, nw.rd, nw.vol) = from_glicko2(new_white);
         nw.games_played += 1;
         match game_result.winner.as_deref() {
             Some("white") => nw.wins += 1,
@@ -73,41 +91,50 @@ impl RatingSystem {
             _ => nw.draws += 1,
         }
 
-        db.update_user_ranking(&nb).await?;
-        db.update_user_ranking(&nw).await?;
-        Ok(())
+        let nb = if fixed_player == Some(black_player_id) { nb_unchanged } else { db.update_user_ranking(&nb).await? };
+        let nw = if fixed_player == Some(white_player_id) { nw_unchanged } else { db.update_user_ranking(&nw).await? };
+
+        // 6) 双方都写入后再计算名次，保证名次反映本局结果
+        Ok(RatingUpdate {
+            black: PlayerRatingChange {
+                user_id: black_player_id,
+                old_rating: black_old_rating,
+                new_rating: nb.rating,
+                delta: nb.rating - black_old_rating,
+                new_rank: db.get_user_rank(model, nb.rating).await?,
+            },
+            white: PlayerRatingChange {
+                user_id: white_player_id,
+                old_rating: white_old_rating,
+                new_rating: nw.rating,
+                delta: nw.rating - white_old_rating,
+                new_rank: db.get_user_rank(model, nw.rating).await?,
+            },
+        })
     }
 
-    // 新增：获取或创建用户评级记录
+    // 获取或创建用户评级记录；该尺寸尚未下过棋时，用其它尺寸的综合评分做初始值
     async fn get_or_create_user_ranking(
         &self,
         db: &Database,
         user_id: &Uuid,
         model: i32,
     ) -> Result<UserRanking, Box<dyn std::error::Error>> {
-        match db.get_user_ranking(user_id, model).await {
-            Ok(ranking) => Ok(ranking),
-            Err(_) => {
-                // 如果不存在，创建默认评级记录
-                let default_ranking = UserRanking {
-                    id: 0,
-                    user_id: *user_id,
-                    model,
-                    rating: 1500.0,
-                    rd: 350.0,
-                    vol: 0.06,
-                    games_played: 0,
-                    wins: 0,
-                    losses: 0,
-                    draws: 0,
-                    created_at: chrono::Utc::now(),
-                    updated_at: chrono::Utc::now(),
-                };
-                
-                db.create_user_ranking(&default_ranking.user_id, default_ranking.model).await?;
-                Ok(default_ranking)
-            }
+        let mut ranking = match db.get_user_ranking(user_id, model).await {
+            Ok(ranking) => ranking,
+            Err(_) => db.create_user_ranking(user_id, model).await?,
+        };
+        if ranking.games_played > 0 {
+            return Ok(ranking);
         }
+
+        if let Some((rating, rd, vol)) = seed_for_model(&db.list_user_rankings(user_id).await?, model) {
+            ranking.rating = rating;
+            ranking.rd = rd;
+            ranking.vol = vol;
+            ranking = db.update_user_ranking(&ranking).await?;
+        }
+        Ok(ranking)
     }
 }
 
@@ -115,10 +142,200 @@ impl Default for RatingSystem {
     fn default() -> Self { Self::new() }
 }
 
-fn to_glicko2(r: &UserRanking) -> Glicko2Rating {
+// offset：计算期望时附加在评分上的等效加成（Glicko 尺度）
+fn to_glicko2(r: &UserRanking, offset: f64) -> Glicko2Rating {
+    let scaled = Glicko2Rating::from(GlickoRating {
+        value:     r.rating + offset,
+        deviation: r.rd,
+    });
     Glicko2Rating {
-        value:      r.rating, // μ
-        deviation:  r.rd,     // φ
-        volatility: r.vol,    // σ
+        value:      scaled.value,     // μ
+        deviation:  scaled.deviation, // φ
+        volatility: r.vol,            // σ
+    }
+}
+
+fn from_glicko2(g: Glicko2Rating) -> (f64, f64, f64) {
+    let scaled = GlickoRating::from(g);
+    (scaled.value, scaled.deviation, g.volatility)
+}
+
+// 一手棋（一子）在各尺寸上约合多少评分点；小棋盘上每一手的分量更重
+fn rating_per_stone(model: i32) -> f64 {
+    match model {
+        7 => 300.0,
+        9 => 250.0,
+        13 => 150.0,
+        _ => 100.0,
+    }
+}
+
+/// 黑方相对对等对局（黑先、贴 7.5）的优势，折算为评分点。
+/// 让 H 子（H ≥ 2）相当于黑方多下 H-1 手；一手约值两倍对等贴目；
+/// 贴目低于对等贴目的部分按目数直接计入。
+pub fn handicap_rating_offset(model: i32, handicap: i32, komi: f64) -> f64 {
+    let move_value = 2.0 * EVEN_KOMI;
+    let extra_moves = (handicap - 1).max(0) as f64;
+    let advantage_points = extra_moves * move_value + (EVEN_KOMI - komi);
+    advantage_points / move_value * rating_per_stone(model)
+}
+
+/// 由各尺寸评分聚合出综合评分：按 1/RD² 加权平均，未下过棋的尺寸不参与。
+/// 综合 RD 由合并后的精度与各尺寸评分围绕均值的加权离散度共同决定：
+/// 各尺寸水平一致时比单一尺寸更确定，相互矛盾时则更不确定（不超过初始 RD）。
+pub fn overall_rating(rankings: &[UserRanking]) -> Option<OverallRating> {
+    let played: Vec<&UserRanking> = rankings.iter().filter(|r| r.games_played > 0).collect();
+    if played.is_empty() {
+        return None;
+    }
+
+    let mut weight_sum = 0.0;
+    let mut rating_sum = 0.0;
+    let mut vol_sum = 0.0;
+    for r in &played {
+        let w = 1.0 / (r.rd * r.rd);
+        weight_sum += w;
+        rating_sum += w * r.rating;
+        vol_sum += w * r.vol;
+    }
+    let rating = rating_sum / weight_sum;
+    let spread = played.iter().map(|r| (r.rating - rating).powi(2) / (r.rd * r.rd)).sum::<f64>() / weight_sum;
+
+    Some(OverallRating {
+        rating,
+        rd: (1.0 / weight_sum + spread).sqrt().min(DEFAULT_RD),
+        vol: vol_sum / weight_sum,
+        games_played: played.iter().map(|r| r.games_played).sum(),
+        wins: played.iter().map(|r| r.wins).sum(),
+        losses: played.iter().map(|r| r.losses).sum(),
+        draws: played.iter().map(|r| r.draws).sum(),
+    })
+}
+
+/// 新尺寸的初始 (rating, rd, vol)
+pub fn seed_from_overall(overall: &OverallRating) -> (f64, f64, f64) {
+    let rd = (overall.rd + SEED_RD_BONUS).clamp(SEED_RD_FLOOR, DEFAULT_RD);
+    (overall.rating, rd, overall.vol)
+}
+
+/// 某尺寸尚未下过棋时的初始 (rating, rd, vol)：取其它尺寸的综合评分；其它尺寸也没有对局时为 None
+pub fn seed_for_model(rankings: &[UserRanking], model: i32) -> Option<(f64, f64, f64)> {
+    let others: Vec<UserRanking> = rankings.iter().filter(|r| r.model != model).cloned().collect();
+    overall_rating(&others).map(|overall| seed_from_overall(&overall))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranking(model: i32, rating: f64, rd: f64, games_played: i32) -> UserRanking {
+        UserRanking {
+            id: 0,
+            user_id: Uuid::nil(),
+            model,
+            rating,
+            rd,
+            vol: 0.06,
+            games_played,
+            wins: games_played,
+            losses: 0,
+            draws: 0,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_overall_ignores_unplayed_sizes() {
+        assert!(overall_rating(&[ranking(9, 1500.0, 350.0, 0)]).is_none());
+
+        let overall = overall_rating(&[
+            ranking(9, 1800.0, 80.0, 30),
+            ranking(13, 1500.0, 350.0, 0),
+        ])
+        .unwrap();
+        assert!((overall.rating - 1800.0).abs() < 1e-9);
+        assert!((overall.rd - 80.0).abs() < 1e-9);
+        assert_eq!(overall.games_played, 30);
+    }
+
+    #[test]
+    fn test_overall_weights_by_precision() {
+        let overall = overall_rating(&[
+            ranking(9, 1800.0, 60.0, 50),
+            ranking(19, 1400.0, 240.0, 3),
+        ])
+        .unwrap();
+        // The confident 9x9 rating dominates; the disagreeing 19x19 rating widens the RD
+        assert!(overall.rating > 1750.0 && overall.rating < 1800.0);
+        assert!(overall.rd > 100.0 && overall.rd < 120.0);
+        assert_eq!(overall.games_played, 53);
+
+        // Consistent sizes combine into a tighter RD than either alone
+        let overall = overall_rating(&[
+            ranking(9, 1600.0, 80.0, 40),
+            ranking(13, 1600.0, 80.0, 40),
+        ])
+        .unwrap();
+        assert!((overall.rd - 80.0 / 2f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_handicap_offset() {
+        assert_eq!(handicap_rating_offset(19, 0, EVEN_KOMI), 0.0);
+        // Half a stone for a no-komi game
+        let no_komi = handicap_rating_offset(19, 1, 0.5);
+        assert!((no_komi - 100.0 * 7.0 / 15.0).abs() < 1e-9);
+        // Each extra handicap stone adds a full stone
+        let two = handicap_rating_offset(19, 2, 0.5);
+        let three = handicap_rating_offset(19, 3, 0.5);
+        assert!((three - two - 100.0).abs() < 1e-9);
+        // Smaller boards value a stone more
+        assert!(handicap_rating_offset(9, 2, 0.5) > two);
+        // Reverse komi favours White
+        assert!(handicap_rating_offset(9, 0, 15.0) < 0.0);
+    }
+
+    #[test]
+    fn test_glicko_scale_round_trip() {
+        let r = ranking(9, 1650.0, 120.0, 10);
+        let (rating, rd, vol) = from_glicko2(to_glicko2(&r, 0.0));
+        assert!((rating - 1650.0).abs() < 1e-9);
+        assert!((rd - 120.0).abs() < 1e-9);
+        assert_eq!(vol, r.vol);
+    }
+
+    #[test]
+    fn test_update_on_glicko_scale() {
+        // An established 1800 player against an established 1500 player
+        let (strong, weak) = (ranking(19, 1800.0, 80.0, 40), ranking(19, 1500.0, 80.0, 40));
+        let raw = |r: &UserRanking| Glicko2Rating { value: r.rating, deviation: r.rd, volatility: r.vol };
+        let unscaled = |result: fn(Glicko2Rating) -> GlickoGameResult| new_rating(raw(&strong), &[result(raw(&weak))], TAU).value;
+        let scaled = |result: fn(Glicko2Rating) -> GlickoGameResult| {
+            from_glicko2(new_rating(to_glicko2(&strong, 0.0), &[result(to_glicko2(&weak, 0.0))], TAU)).0
+        };
+
+        // Before: the stored values were read as internal-scale μ/φ, so a 300-point gap
+        // looked like a certain win: winning earned nothing and one upset cost ~144 points
+        assert!(unscaled(GlickoGameResult::win) - 1800.0 < 0.5);
+        assert!(1800.0 - unscaled(GlickoGameResult::loss) > 140.0);
+
+        // After: the expected score is ~0.85, giving the usual Glicko-sized updates
+        let win = scaled(GlickoGameResult::win) - 1800.0;
+        let loss = 1800.0 - scaled(GlickoGameResult::loss);
+        assert!(win > 5.0 && win < 6.0);
+        assert!(loss > 29.0 && loss < 31.0);
+    }
+
+    #[test]
+    fn test_seed_uses_higher_rd() {
+        let overall = overall_rating(&[ranking(9, 1800.0, 60.0, 50)]).unwrap();
+        let (rating, rd, _) = seed_from_overall(&overall);
+        assert_eq!(rating, 1800.0);
+        assert_eq!(rd, SEED_RD_FLOOR);
+
+        let overall = overall_rating(&[ranking(9, 1800.0, 300.0, 1)]).unwrap();
+        let (_, rd, _) = seed_from_overall(&overall);
+        assert_eq!(rd, DEFAULT_RD);
     }
 }
@@ -0,0 +1,410 @@
+// 赛后复盘
+//
+// 把已结束对局的着法记录逐手重放，每个局面在两个现实上分别交给 KataGo 分析，
+// 记录每手前后的胜率与目差变化，标出缓手/问题手/恶手以及错过的最佳着。
+// 复盘在后台任务中执行，结果存入 game_reviews 表，客户端通过 /game/review 轮询状态并取回结果。
+//
+// 同时运行的复盘数由 REVIEW_MAX_CONCURRENT 限制（默认 2），等待中的任务数超过
+// REVIEW_QUEUE_LIMIT（默认 16）时拒绝新的复盘请求。
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use uuid::Uuid;
+
+use crate::db::Database;
+use crate::entity::{GameReview, RoomInfo};
+use crate::katago::gtp_to_xy;
+use crate::katago_analysis::{self, board_query, split_komi, AnalysisResponse};
+use crate::quantum::{QuantumGame, Stone};
+use crate::quantum_search::{handicap_game, parse_records};
+use crate::rules::Ruleset;
+
+const DEFAULT_MAX_CONCURRENT: usize = 2;
+const DEFAULT_QUEUE_LIMIT: usize = 16;
+const DEFAULT_REVIEW_VISITS: u32 = 100;
+// Positions sent to the analysis engine per batch (two queries each)
+const BATCH_POSITIONS: usize = 8;
+// Candidates on board A considered when looking for the best move
+const BEST_MOVE_CANDIDATES: usize = 5;
+
+/// 按两盘合计目差损失划分的阈值
+pub const INACCURACY_POINTS: f64 = 1.5;
+pub const MISTAKE_POINTS: f64 = 3.0;
+pub const BLUNDER_POINTS: f64 = 6.0;
+
+fn env_usize(key: &str, default: usize) -> usize {
+    std::env::var(key).ok().and_then(|v| v.parse().ok()).filter(|&v| v > 0).unwrap_or(default)
+}
+
+static REVIEW_SLOTS: Lazy<Arc<Semaphore>> =
+    Lazy::new(|| Arc::new(Semaphore::new(env_usize("REVIEW_MAX_CONCURRENT", DEFAULT_MAX_CONCURRENT))));
+
+// In-flight jobs (queued or running) with their progress
+static JOBS: Lazy<std::sync::Mutex<HashMap<Uuid, Progress>>> = Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Progress {
+    pub done: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Judgement {
+    Good,
+    Inaccuracy,
+    Mistake,
+    Blunder,
+}
+
+/// 按目差损失（行棋方视角，正数为亏损）给出评价
+pub fn judge(loss: f64) -> Judgement {
+    if loss >= BLUNDER_POINTS {
+        Judgement::Blunder
+    } else if loss >= MISTAKE_POINTS {
+        Judgement::Mistake
+    } else if loss >= INACCURACY_POINTS {
+        Judgement::Inaccuracy
+    } else {
+        Judgement::Good
+    }
+}
+
+/// 一个局面的评估（黑方视角，两盘合计，已计贴目）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PositionEval {
+    pub black_lead: f64,
+    /// 两盘各自的黑方胜率
+    pub black_winrate: [f64; 2],
+    /// 行棋方的最佳着（A 盘点击位置 "x,y"）及其下完后的黑方合计目差
+    pub best_move: Option<String>,
+    pub best_lead: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MoveReview {
+    pub move_number: usize,
+    pub color: Stone,
+    pub position: String,
+    pub lead_before: f64,
+    pub lead_after: f64,
+    pub winrate_before: [f64; 2],
+    pub winrate_after: [f64; 2],
+    /// 行棋方视角的目差损失（两盘合计）
+    pub loss: f64,
+    /// 行棋方视角的平均胜率变化
+    pub winrate_swing: f64,
+    pub judgement: Judgement,
+    pub best_move: Option<String>,
+    pub missed_best: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayerSummary {
+    pub color: Stone,
+    pub moves: usize,
+    pub inaccuracies: usize,
+    pub mistakes: usize,
+    pub blunders: usize,
+    pub average_loss: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReviewDocument {
+    pub room_id: Uuid,
+    pub model: i32,
+    pub komi: f64,
+    pub visits: u32,
+    pub moves: Vec<MoveReview>,
+    pub black: PlayerSummary,
+    pub white: PlayerSummary,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReviewStatus {
+    pub room_id: Uuid,
+    /// queued | running | done | failed
+    pub status: String,
+    pub progress: Option<Progress>,
+    pub error: Option<String>,
+    pub review: Option<serde_json::Value>,
+}
+
+impl ReviewStatus {
+    fn from_row(row: GameReview) -> Self {
+        let progress = JOBS.lock().unwrap().get(&row.room_id).copied();
+        Self { room_id: row.room_id, status: row.status, progress, error: row.error, review: row.review }
+    }
+}
+
+/// 由各局面评估生成逐手评价；evals 比 moves 多一个（终局局面）
+pub fn build_moves(game_moves: &[(Stone, String)], evals: &[PositionEval]) -> Vec<MoveReview> {
+    game_moves
+        .iter()
+        .zip(evals.windows(2))
+        .enumerate()
+        .map(|(i, ((color, position), pair))| {
+            let (before, after) = (&pair[0], &pair[1]);
+            let sign = if *color == Stone::Black { 1.0 } else { -1.0 };
+            let loss = sign * (before.black_lead - after.black_lead);
+            let mean = |w: &[f64; 2]| (w[0] + w[1]) / 2.0;
+            let judgement = judge(loss);
+            let missed_best = judgement >= Judgement::Mistake && before.best_move.as_ref().is_some_and(|b| b != position);
+            MoveReview {
+                move_number: i + 1,
+                color: *color,
+                position: position.clone(),
+                lead_before: before.black_lead,
+                lead_after: after.black_lead,
+                winrate_before: before.black_winrate,
+                winrate_after: after.black_winrate,
+                loss,
+                winrate_swing: sign * (mean(&after.black_winrate) - mean(&before.black_winrate)),
+                judgement,
+                best_move: before.best_move.clone(),
+                missed_best,
+            }
+        })
+        .collect()
+}
+
+pub fn summarize(moves: &[MoveReview], color: Stone) -> PlayerSummary {
+    let own: Vec<&MoveReview> = moves.iter().filter(|m| m.color == color).collect();
+    let count = |j: Judgement| own.iter().filter(|m| m.judgement == j).count();
+    let average_loss = if own.is_empty() { 0.0 } else { own.iter().map(|m| m.loss.max(0.0)).sum::<f64>() / own.len() as f64 };
+    PlayerSummary {
+        color,
+        moves: own.len(),
+        inaccuracies: count(Judgement::Inaccuracy),
+        mistakes: count(Judgement::Mistake),
+        blunders: count(Judgement::Blunder),
+        average_loss,
+    }
+}
+
+// Black-perspective lead and winrate of one board's analysis (responses come in side-to-move perspective)
+fn black_view(game: &QuantumGame, board: usize, lead: f64, winrate: f64, komi_rest: f64) -> (f64, f64) {
+    match game.color_on(board, game.to_move) {
+        Stone::Black => (lead - komi_rest, winrate),
+        Stone::White => (-lead - komi_rest, 1.0 - winrate),
+    }
+}
+
+fn evaluate(game: &QuantumGame, responses: [&AnalysisResponse; 2], komi_rest: f64) -> PositionEval {
+    let size = game.size as u8;
+    let roots: Vec<(f64, f64)> = (0..2)
+        .map(|b| black_view(game, b, responses[b].root_info.score_lead, responses[b].root_info.winrate, komi_rest))
+        .collect();
+
+    // Best move: top candidates on A, valued with the matching reply on B (root value when B did not search it)
+    let sign = if game.to_move == Stone::Black { 1.0 } else { -1.0 };
+    let mut infos_a: Vec<_> = responses[0].move_infos.iter().collect();
+    infos_a.sort_by_key(|mi| mi.order);
+    let best = infos_a
+        .into_iter()
+        .filter_map(|mi| {
+            let idx = game.index_of(&gtp_to_xy(&mi.move_coord, size).ok()?)?;
+            if !game.is_legal(idx) {
+                return None;
+            }
+            let [_, on_b] = game.map_move(idx, game.to_move);
+            let lead_a = black_view(game, 0, mi.score_lead, mi.winrate, komi_rest).0;
+            let lead_b = responses[1]
+                .move_infos
+                .iter()
+                .find(|o| gtp_to_xy(&o.move_coord, size).ok().and_then(|xy| game.index_of(&xy)) == Some(on_b))
+                .map(|o| black_view(game, 1, o.score_lead, o.winrate, komi_rest).0)
+                .unwrap_or(roots[1].0);
+            Some((game.position_of(idx), lead_a + lead_b))
+        })
+        .take(BEST_MOVE_CANDIDATES)
+        .max_by(|a, b| (sign * a.1).total_cmp(&(sign * b.1)));
+
+    PositionEval {
+        black_lead: roots[0].0 + roots[1].0,
+        black_winrate: [roots[0].1, roots[1].1],
+        best_move: best.as_ref().map(|(p, _)| p.clone()),
+        best_lead: best.map(|(_, v)| v),
+    }
+}
+
+fn set_progress(room_id: Uuid, done: usize, total: usize) {
+    JOBS.lock().unwrap().insert(room_id, Progress { done, total });
+}
+
+/// 分析整盘对局并生成复盘文档
+async fn review_room(room: &RoomInfo) -> io::Result<ReviewDocument> {
+    let size = room.model as usize;
+    let moves = parse_records(&room.chessman_records, size)?;
+    let mut game = handicap_game(room.model, room.handicap);
+
+    // Positions before each move plus the final one
+    let mut positions = Vec::with_capacity(moves.len() + 1);
+    let mut played = Vec::with_capacity(moves.len());
+    for (i, &(color, idx)) in moves.iter().enumerate() {
+        // Records omit passes; keep the side to move in step with the record
+        game.to_move = color;
+        positions.push(game.clone());
+        game.play(idx)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("move {}: {}", i + 1, e)))?;
+        played.push((color, game.position_of(idx)));
+    }
+    positions.push(game);
+
+    let rules = Ruleset::from_id(&room.rules).katago_name();
+    let visits = std::env::var("REVIEW_VISITS").ok().and_then(|v| v.parse().ok()).filter(|&v| v > 0).unwrap_or(DEFAULT_REVIEW_VISITS);
+    let (board_komi, komi_rest) = split_komi(room.komi);
+    let engine = katago_analysis::shared_engine().await?;
+
+    let mut evals = Vec::with_capacity(positions.len());
+    for chunk in positions.chunks(BATCH_POSITIONS) {
+        let queries = chunk
+            .iter()
+            .flat_map(|g| [0, 1].map(|b| board_query(engine.next_query_id("review"), g, b, rules, board_komi, Some(visits), false)))
+            .collect::<io::Result<Vec<_>>>()?;
+        let responses = katago_analysis::run_batch(&queries).await?;
+        for (g, pair) in chunk.iter().zip(responses.chunks(2)) {
+            evals.push(evaluate(g, [&pair[0], &pair[1]], komi_rest));
+        }
+        set_progress(room.room_id, evals.len(), positions.len());
+    }
+
+    let reviewed = build_moves(&played, &evals);
+    Ok(ReviewDocument {
+        room_id: room.room_id,
+        model: room.model,
+        komi: room.komi,
+        visits,
+        black: summarize(&reviewed, Stone::Black),
+        white: summarize(&reviewed, Stone::White),
+        moves: reviewed,
+    })
+}
+
+async fn run_job(db: Arc<Database>, room: RoomInfo) {
+    let room_id = room.room_id;
+    let result = async {
+        let _permit = REVIEW_SLOTS.clone().acquire_owned().await.map_err(io::Error::other)?;
+        db.upsert_game_review_status(room_id, "running", None).await.map_err(io::Error::other)?;
+        let doc = review_room(&room).await?;
+        let value = serde_json::to_value(&doc).map_err(io::Error::other)?;
+        db.save_game_review(room_id, &value).await.map_err(io::Error::other)
+    }
+    .await;
+    if let Err(err) = result {
+        tracing::warn!("review of room {} failed: {}", room_id, err);
+        let _ = db.upsert_game_review_status(room_id, "failed", Some(&err.to_string())).await;
+    }
+    JOBS.lock().unwrap().remove(&room_id);
+}
+
+/// 请求复盘：已有结果或任务进行中时直接返回状态，否则排队新任务
+pub async fn request_review(db: Arc<Database>, room_id: Uuid) -> io::Result<ReviewStatus> {
+    let room = db
+        .get_room_by_room_id(room_id)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::NotFound, "room not found"))?;
+    if room.status != "finished" {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "game is not finished"));
+    }
+    // Rows left queued/running by a previous server process are restarted
+    let existing = db.get_game_review(room_id).await.map_err(io::Error::other)?;
+    let done = existing.as_ref().is_some_and(|row| row.status == "done");
+    {
+        // Check and claim under one guard so concurrent requests start at most one job
+        let mut jobs = JOBS.lock().unwrap();
+        if !done && !jobs.contains_key(&room_id) {
+            if jobs.len() >= env_usize("REVIEW_QUEUE_LIMIT", DEFAULT_QUEUE_LIMIT) {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "review queue is full"));
+            }
+            jobs.insert(room_id, Progress { done: 0, total: 0 });
+        } else if let Some(row) = existing {
+            drop(jobs);
+            return Ok(ReviewStatus::from_row(row));
+        } else {
+            // Claimed by a concurrent request that has not written its row yet
+            let progress = jobs.get(&room_id).copied();
+            return Ok(ReviewStatus { room_id, status: "queued".to_string(), progress, error: None, review: None });
+        }
+    }
+    if let Err(err) = db.upsert_game_review_status(room_id, "queued", None).await {
+        JOBS.lock().unwrap().remove(&room_id);
+        return Err(io::Error::other(err));
+    }
+    tokio::spawn(run_job(db.clone(), room));
+    review_status(&db, room_id).await
+}
+
+/// 查询复盘状态（不会新建任务）
+pub async fn review_status(db: &Database, room_id: Uuid) -> io::Result<ReviewStatus> {
+    db.get_game_review(room_id)
+        .await
+        .map_err(io::Error::other)?
+        .map(ReviewStatus::from_row)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no review for this room"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::katago_analysis::{MoveInfo, RootInfo};
+
+    fn eval(black_lead: f64, best: Option<&str>) -> PositionEval {
+        PositionEval { black_lead, black_winrate: [0.5, 0.5], best_move: best.map(str::to_string), best_lead: None }
+    }
+
+    #[test]
+    fn test_losses_are_from_the_movers_perspective() {
+        let played = vec![(Stone::Black, "3,3".to_string()), (Stone::White, "5,5".to_string())];
+        // Black keeps the lead, then White's move hands Black another 8 points
+        let evals = vec![eval(2.0, Some("3,3")), eval(1.5, Some("3,7")), eval(9.5, None)];
+        let moves = build_moves(&played, &evals);
+        assert_eq!(moves[0].judgement, Judgement::Good);
+        assert!(!moves[0].missed_best);
+        assert_eq!(moves[1].loss, 8.0);
+        assert_eq!(moves[1].judgement, Judgement::Blunder);
+        assert!(moves[1].missed_best);
+
+        let white = summarize(&moves, Stone::White);
+        assert_eq!((white.moves, white.blunders), (1, 1));
+        assert_eq!(summarize(&moves, Stone::Black).average_loss, 0.5);
+    }
+
+    fn response(root: (f64, f64), infos: &[(&str, u32, f64, f64)]) -> AnalysisResponse {
+        let info = |&(mv, order, winrate, score_lead): &(&str, u32, f64, f64)| MoveInfo {
+            move_coord: mv.to_string(),
+            visits: 100,
+            winrate,
+            score_lead,
+            prior: 0.0,
+            order,
+            pv: vec![],
+        };
+        AnalysisResponse {
+            id: String::new(),
+            turn_number: 0,
+            is_during_search: false,
+            move_infos: infos.iter().map(info).collect(),
+            root_info: RootInfo { winrate: root.0, score_lead: root.1, visits: 100, current_player: String::new() },
+            ownership: None,
+        }
+    }
+
+    #[test]
+    fn test_evaluate_converts_to_black_view() {
+        // Black to move on board A, but on board B the opening mover plays White
+        let game = QuantumGame::new(9);
+        // Responses are in the side-to-move perspective: (winrate, score lead)
+        let a = response((0.7, 4.0), &[("C3", 0, 0.6, 1.0), ("D4", 1, 0.8, 5.0)]);
+        let b = response((0.6, 2.0), &[("C3", 0, 0.5, 0.0), ("D4", 1, 0.9, 8.0)]);
+        let eval = evaluate(&game, [&a, &b], 0.5);
+        assert_eq!(eval.black_winrate, [0.7, 0.4]);
+        assert!((eval.black_lead - ((4.0 - 0.5) + (-2.0 - 0.5))).abs() < 1e-9);
+        // D4 is better on A but hands board B to White; over both boards C3 is Black's best
+        assert_eq!(eval.best_move.as_deref(), Some("7,3"));
+        assert!((eval.best_lead.unwrap() - ((1.0 - 0.5) + (0.0 - 0.5))).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,97 @@
+// 规则集：KataGo 请求（kata-set-rules / analysis 的 rules 字段）与服务端数子共用
+//
+// 量子围棋房间默认使用本站规则（quantum）：两盘面积计分、贴目只计一次、禁止自杀、
+// 每盘按局面+行棋方判全局同形（情境超级劫）。其它规则集用于普通 AI 对弈与分析请求。
+use std::io;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ruleset {
+    Chinese,
+    Japanese,
+    TrompTaylor,
+    Aga,
+    Quantum,
+}
+
+/// 计分方式：面积（子 + 地）或数目（地 + 提子）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scoring {
+    Area,
+    Territory,
+}
+
+impl Ruleset {
+    /// 存库与 API 使用的名字
+    pub fn id(self) -> &'static str {
+        match self {
+            Ruleset::Chinese => "chinese",
+            Ruleset::Japanese => "japanese",
+            Ruleset::TrompTaylor => "tromp-taylor",
+            Ruleset::Aga => "aga",
+            Ruleset::Quantum => "quantum",
+        }
+    }
+
+    /// 传给 KataGo 的规则串；本站规则写成 KataGo 的组合形式
+    pub fn katago_name(self) -> &'static str {
+        match self {
+            Ruleset::Quantum => "koSITUATIONALscoreAREAtaxNONEsui0",
+            other => other.id(),
+        }
+    }
+
+    pub fn scoring(self) -> Scoring {
+        match self {
+            Ruleset::Japanese => Scoring::Territory,
+            _ => Scoring::Area,
+        }
+    }
+
+    /// 大小写、下划线/空格写法都接受；未知规则返回 InvalidInput
+    pub fn parse(name: &str) -> io::Result<Ruleset> {
+        let normalized: String = name
+            .trim()
+            .to_ascii_lowercase()
+            .chars()
+            .filter(|c| !matches!(c, '-' | '_' | ' '))
+            .collect();
+        match normalized.as_str() {
+            "chinese" | "cn" => Ok(Ruleset::Chinese),
+            "japanese" | "jp" => Ok(Ruleset::Japanese),
+            "tromptaylor" | "tt" => Ok(Ruleset::TrompTaylor),
+            "aga" => Ok(Ruleset::Aga),
+            "quantum" | "house" => Ok(Ruleset::Quantum),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown rules: {}", name))),
+        }
+    }
+
+    /// 请求中的可选规则字段；缺省或空串时用 default
+    pub fn resolve(name: Option<&str>, default: Ruleset) -> io::Result<Ruleset> {
+        match name.map(str::trim).filter(|n| !n.is_empty()) {
+            Some(name) => Ruleset::parse(name),
+            None => Ok(default),
+        }
+    }
+
+    /// 库中的规则名；旧数据或无法识别时按本站规则处理
+    pub fn from_id(id: &str) -> Ruleset {
+        Ruleset::parse(id).unwrap_or(Ruleset::Quantum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_accepts_common_spellings() {
+        assert_eq!(Ruleset::parse("Tromp-Taylor").unwrap(), Ruleset::TrompTaylor);
+        assert_eq!(Ruleset::parse("tromp_taylor").unwrap(), Ruleset::TrompTaylor);
+        assert_eq!(Ruleset::parse(" AGA ").unwrap(), Ruleset::Aga);
+        assert_eq!(Ruleset::resolve(Some(""), Ruleset::Chinese).unwrap(), Ruleset::Chinese);
+        assert_eq!(Ruleset::parse("korean").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        for rules in [Ruleset::Chinese, Ruleset::Japanese, Ruleset::TrompTaylor, Ruleset::Aga, Ruleset::Quantum] {
+            assert_eq!(Ruleset::parse(rules.id()).unwrap(), rules);
+        }
+    }
+}
@@ -1,42 +1,56 @@
+use once_cell::sync::Lazy;
+#[cfg(not(feature = "rust-estimator"))]
 use std::ffi::c_void;
+use std::io;
+use std::time::Duration;
+use tokio::sync::Semaphore;
 
 // FFI绑定到简化的C++估算器
+#[cfg(not(feature = "rust-estimator"))]
 extern "C" {
     fn create_estimator(width: i32, height: i32) -> *mut c_void;
     fn destroy_estimator(estimator: *mut c_void);
     fn set_stone(estimator: *mut c_void, x: i32, y: i32, color: i32);
-    fn estimate_score(
+    fn estimate_board(
         estimator: *mut c_void,
         player_to_move: i32,
         trials: i32,
         tolerance: f32,
         ownership: *mut f32,
-        width: *mut i32,
-        height: *mut i32,
-    );
-    fn compute_territory(
-        estimator: *mut c_void,
         territory: *mut f32,
-        width: *mut i32,
-        height: *mut i32,
-    );
-    fn get_dead_stones(
-        estimator: *mut c_void,
-        player_to_move: i32,
-        trials: i32,
-        tolerance: f32,
         dead_stones: *mut i32,
         count: *mut i32,
         max_count: i32,
     );
 }
 
+// Goban.h 的 MAX_WIDTH / MAX_HEIGHT
+const MAX_BOARD_SIZE: u8 = 25;
+
+const DEFAULT_TRIALS: i32 = 1000;
+const DEFAULT_TOLERANCE: f32 = 0.4;
+const DEFAULT_MAX_TRIALS: i32 = 5000;
+const DEFAULT_QUEUE_TIMEOUT_MS: u64 = 10_000;
+
+// 纯 Rust 实现，接口与 C++ 版相同
+#[cfg(feature = "rust-estimator")]
+pub use crate::goban_estimator::Goban as ScoreEstimator;
+
+#[cfg(not(feature = "rust-estimator"))]
 pub struct ScoreEstimator {
     estimator: *mut c_void,
     width: i32,
     height: i32,
 }
 
+// SAFETY: the Goban behind `estimator` is created in `new`, freed once in `drop` and never shared:
+// the C side keeps no other pointer to it. Built with USE_THREADS (see build.rs), its remaining
+// global state — the default grid size and the RNG — is thread-local or per board, so a handle may
+// move to the blocking pool. It is deliberately not Sync: every call mutates the board.
+#[cfg(not(feature = "rust-estimator"))]
+unsafe impl Send for ScoreEstimator {}
+
+#[cfg(not(feature = "rust-estimator"))]
 impl ScoreEstimator {
     pub fn new(width: i32, height: i32) -> Self {
         unsafe {
@@ -49,139 +63,208 @@ impl ScoreEstimator {
         }
     }
 
-    pub fn set_stone(&self, x: i32, y: i32, color: i32) {
+    /// 坐标从 0 开始；越界的点直接忽略，不传给 C++
+    pub fn set_stone(&mut self, x: i32, y: i32, color: i32) {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return;
+        }
         unsafe {
             set_stone(self.estimator, x, y, color);
         }
     }
 
-    pub fn estimate_score(&self, player_to_move: i32, trials: i32, tolerance: f32) -> Vec<f32> {
+    /// 一次模拟同时得到归属、地域与死子
+    pub fn estimate(&mut self, player_to_move: i32, trials: i32, tolerance: f32) -> BoardEstimate {
         let size = (self.width * self.height) as usize;
         let mut ownership = vec![0.0f32; size];
-        let mut width = 0i32;
-        let mut height = 0i32;
+        let mut territory = vec![0.0f32; size];
+        // Every stone can be dead at most once
+        let mut dead_stones = vec![0i32; size * 2];
+        let mut count = 0i32;
 
         unsafe {
-            estimate_score(
+            estimate_board(
                 self.estimator,
                 player_to_move,
                 trials,
                 tolerance,
                 ownership.as_mut_ptr(),
-                &mut width,
-                &mut height,
+                territory.as_mut_ptr(),
+                dead_stones.as_mut_ptr(),
+                &mut count,
+                size as i32,
             );
         }
 
-        ownership
+        let dead = dead_stones
+            .chunks_exact(2)
+            .take(count as usize)
+            .map(|pair| (pair[0], pair[1]))
+            .collect();
+        (ownership, territory, dead)
     }
+}
 
-    pub fn compute_territory(&self) -> Vec<f32> {
-        let size = (self.width * self.height) as usize;
-        let mut territory = vec![0.0f32; size];
-        let mut width = 0i32;
-        let mut height = 0i32;
-
+#[cfg(not(feature = "rust-estimator"))]
+impl Drop for ScoreEstimator {
+    fn drop(&mut self) {
         unsafe {
-            compute_territory(
-                self.estimator,
-                territory.as_mut_ptr(),
-                &mut width,
-                &mut height,
-            );
+            destroy_estimator(self.estimator);
         }
-
-        territory
     }
+}
 
-    pub fn get_dead_stones(
-        &self,
-        player_to_move: i32,
-        trials: i32,
-        tolerance: f32,
-    ) -> Vec<(i32, i32)> {
-        let max_count = 1000; // 最大死子数量
-        let mut dead_stones = vec![0i32; max_count * 2];
-        let mut count = 0i32;
+// (ownership, territory, dead stones)
+pub type BoardEstimate = (Vec<f32>, Vec<f32>, Vec<(i32, i32)>);
 
-        unsafe {
-            get_dead_stones(
-                self.estimator,
-                player_to_move,
-                trials,
-                tolerance,
-                dead_stones.as_mut_ptr(),
-                &mut count,
-                max_count as i32,
-            );
-        }
+/// 模拟参数；请求未指定时使用默认值
+#[derive(Debug, Clone, Copy)]
+pub struct EstimateParams {
+    pub trials: i32,
+    pub tolerance: f32,
+}
 
-        let mut result = Vec::new();
-        for i in 0..count as usize {
-            let x = dead_stones[i * 2];
-            let y = dead_stones[i * 2 + 1];
-            result.push((x, y));
-        }
+struct Limits {
+    permits: Semaphore,
+    max_trials: i32,
+    queue_timeout: Duration,
+}
 
-        result
+// SCORE_ESTIMATE_CONCURRENCY（默认 CPU 核数的一半）/ SCORE_ESTIMATE_MAX_TRIALS / SCORE_ESTIMATE_QUEUE_TIMEOUT_MS
+static LIMITS: Lazy<Limits> = Lazy::new(|| {
+    let env = |key: &str| std::env::var(key).ok().map(|v| v.trim().to_string());
+    let concurrency = env("SCORE_ESTIMATE_CONCURRENCY")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or_else(|| std::thread::available_parallelism().map(|n| n.get() / 2).unwrap_or(1))
+        .max(1);
+    Limits {
+        permits: Semaphore::new(concurrency),
+        max_trials: env("SCORE_ESTIMATE_MAX_TRIALS").and_then(|v| v.parse().ok()).unwrap_or(DEFAULT_MAX_TRIALS).max(1),
+        queue_timeout: Duration::from_millis(
+            env("SCORE_ESTIMATE_QUEUE_TIMEOUT_MS").and_then(|v| v.parse().ok()).unwrap_or(DEFAULT_QUEUE_TIMEOUT_MS),
+        ),
     }
-}
+});
 
-impl Drop for ScoreEstimator {
-    fn drop(&mut self) {
-        unsafe {
-            destroy_estimator(self.estimator);
+impl EstimateParams {
+    /// trials 截断到 [1, SCORE_ESTIMATE_MAX_TRIALS]；tolerance 须在 (0, 1) 内
+    pub fn resolve(trials: Option<i32>, tolerance: Option<f32>) -> io::Result<Self> {
+        let tolerance = tolerance.unwrap_or(DEFAULT_TOLERANCE);
+        if !(tolerance > 0.0 && tolerance < 1.0) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "tolerance must be between 0 and 1"));
         }
+        let trials = trials.unwrap_or(DEFAULT_TRIALS).clamp(1, LIMITS.max_trials);
+        Ok(Self { trials, tolerance })
     }
 }
 
 // 将我们的数据格式转换为score-estimator格式
-pub fn estimate_board_score(
-    board_size: u8,
-    black_stones: &[String],
-    white_stones: &[String],
-    next_to_move: Option<&str>,
-    trials: i32,
-    tolerance: f32,
-) -> Result<(Vec<f32>, Vec<f32>, Vec<(i32, i32)>), String> {
-    let estimator = ScoreEstimator::new(board_size as i32, board_size as i32);
-
-    // 设置黑子
-    for stone in black_stones {
-        let (x, y) = parse_position(stone)?;
-        estimator.set_stone(x - 1, y - 1, 1); // 1表示黑色
+fn build_estimator(board_size: u8, black_stones: &[String], white_stones: &[String]) -> Result<ScoreEstimator, String> {
+    if board_size == 0 || board_size > MAX_BOARD_SIZE {
+        return Err(format!("Unsupported board size: {}", board_size));
     }
+    let mut estimator = ScoreEstimator::new(board_size as i32, board_size as i32);
 
-    // 设置白子
-    for stone in white_stones {
-        let (x, y) = parse_position(stone)?;
-        estimator.set_stone(x - 1, y - 1, 2); // 2表示白色
+    // 设置黑子（1）与白子（2）
+    for (stones, color) in [(black_stones, 1), (white_stones, 2)] {
+        for stone in stones {
+            let (x, y) = parse_position(stone, board_size)?;
+            estimator.set_stone(x - 1, y - 1, color);
+        }
     }
+    Ok(estimator)
+}
 
-    // 确定下一步玩家
-    let player_to_move = match next_to_move.unwrap_or("black") {
+fn player_to_move(next_to_move: Option<&str>) -> i32 {
+    match next_to_move.unwrap_or("black") {
         "black" => 1,
         "white" => 2,
         _ => 1,
-    };
+    }
+}
+
+/// 同步估算，在当前线程运行；服务端请求走 estimate_board_blocking
+pub fn estimate_board_score(
+    board_size: u8,
+    black_stones: &[String],
+    white_stones: &[String],
+    next_to_move: Option<&str>,
+    trials: i32,
+    tolerance: f32,
+) -> Result<BoardEstimate, String> {
+    let mut estimator = build_estimator(board_size, black_stones, white_stones)?;
+    Ok(estimator.estimate(player_to_move(next_to_move), trials, tolerance))
+}
 
-    // 估算分数
-    let ownership = estimator.estimate_score(player_to_move, trials, tolerance);
-    let territory = estimator.compute_territory();
-    let dead_stones = estimator.get_dead_stones(player_to_move, trials, tolerance);
+/// 在阻塞线程池上估算一盘；同时运行的估算数受 SCORE_ESTIMATE_CONCURRENCY 限制，排队超时返回 TimedOut
+pub async fn estimate_board_blocking(
+    board_size: u8,
+    black_stones: &[String],
+    white_stones: &[String],
+    next_to_move: Option<&str>,
+    params: EstimateParams,
+) -> io::Result<BoardEstimate> {
+    // Bad input is rejected before waiting for a slot
+    let mut estimator = build_estimator(board_size, black_stones, white_stones)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let player = player_to_move(next_to_move);
 
-    Ok((ownership, territory, dead_stones))
+    let _permit = tokio::time::timeout(LIMITS.queue_timeout, LIMITS.permits.acquire())
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "timed out waiting for the score estimator"))?
+        .map_err(io::Error::other)?;
+    tokio::task::spawn_blocking(move || estimator.estimate(player, params.trials, params.tolerance))
+        .await
+        .map_err(io::Error::other)
 }
 
-// 估算死子
-fn parse_position(pos: &str) -> Result<(i32, i32), String> {
+// 解析 "x,y"（从 1 开始），越界返回错误
+fn parse_position(pos: &str, board_size: u8) -> Result<(i32, i32), String> {
     let parts: Vec<&str> = pos.split(',').collect();
     if parts.len() != 2 {
         return Err("Invalid position format".to_string());
     }
 
-    let x: i32 = parts[0].parse().map_err(|_| "Invalid x coordinate")?;
-    let y: i32 = parts[1].parse().map_err(|_| "Invalid y coordinate")?;
+    let x: i32 = parts[0].trim().parse().map_err(|_| "Invalid x coordinate")?;
+    let y: i32 = parts[1].trim().parse().map_err(|_| "Invalid y coordinate")?;
+    if x < 1 || y < 1 || x > board_size as i32 || y > board_size as i32 {
+        return Err(format!("Position out of bounds: {}", pos));
+    }
 
     Ok((x, y))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stones(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_params_are_capped_and_positions_checked() {
+        let params = EstimateParams::resolve(Some(i32::MAX), None).unwrap();
+        assert_eq!(params.trials, LIMITS.max_trials);
+        assert_eq!(EstimateParams::resolve(Some(-5), Some(0.3)).unwrap().trials, 1);
+        assert!(EstimateParams::resolve(None, Some(1.5)).is_err());
+        assert!(EstimateParams::resolve(None, Some(f32::NAN)).is_err());
+
+        assert!(estimate_board_score(9, &stones(&["10,1"]), &[], None, 10, 0.4).is_err());
+        assert!(estimate_board_score(9, &stones(&["0,3"]), &[], None, 10, 0.4).is_err());
+        assert!(estimate_board_score(30, &[], &[], None, 10, 0.4).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_blocking_estimate_matches_board_shape() {
+        let params = EstimateParams::resolve(Some(100), None).unwrap();
+        let black = stones(&["3,3", "3,4", "4,3"]);
+        let white = stones(&["7,7"]);
+        let (ownership, territory, dead) = estimate_board_blocking(9, &black, &white, Some("white"), params).await.unwrap();
+        assert_eq!(ownership.len(), 81);
+        assert_eq!(territory.len(), 81);
+        assert!(dead.iter().all(|&(x, y)| (0..9).contains(&x) && (0..9).contains(&y)));
+        let err = estimate_board_blocking(9, &stones(&["1,12"]), &[], None, params).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,467 @@
+// 终局数子阶段：由服务端主导的死子确认与数子
+//
+// - 服务端按席位记录 PvP 房间的虚手；双方连续各虚一手后，房间状态置为 scoring，
+//   以 quantum 模式的形势判断在两盘上提出死子，向双方推送 stoneRemovalStart
+// - 死子按"块"切换：一块棋连同它在另一盘上的兄弟子（及兄弟子所在的块）同生同死。
+//   stoneRemovalToggle 切换一个点所在的块；stoneRemovalUpdate 提交整组标记，同样补全为完整的块
+// - 任何改动都撤销双方的同意并把新的死子推送给双方；双方对同一组死子 stoneRemovalAccept 后，
+//   服务端移除死子、按房间规则与贴目数子，写入胜负与目差
+// - stoneRemovalExit 退出数子阶段，恢复对局（重新计算虚手）
+// AI 房间仍由 ai_room 在双方虚手后直接数子。
+use axum::extract::ws::Message;
+use futures::sink::SinkExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::io;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::ai_room::{color_name, LiveGame};
+use crate::entity::{Room, RoomInfo, WsSender};
+use crate::katago::{ScoreEstimateBoardRequest, ScoreEstimateRequest};
+use crate::outcome::GameOutcome;
+use crate::quantum::{QuantumGame, Stone};
+use crate::quantum_estimate;
+use crate::rules::Ruleset;
+use crate::score_estimator::EstimateParams;
+use crate::seating::color_of;
+use crate::ws::{end_game, AppState, Data};
+
+/// 房间内存中的终局状态
+#[derive(Default)]
+pub struct RoomScoring {
+    // Colour of the player whose pass was the last action
+    last_pass: Option<Stone>,
+    // Both players passed and the dead-stone proposal is being computed
+    proposing: bool,
+    session: Option<Session>,
+}
+
+struct Session {
+    game: QuantumGame,
+    komi: f64,
+    rules: Ruleset,
+    dead: [BTreeSet<usize>; 2],
+    accepted: [bool; 2], // [black, white]
+}
+
+/// 推送给双方的死子状态（stoneRemovalStart / stoneRemovalUpdate）
+#[derive(Debug, Serialize)]
+pub struct RemovalState {
+    pub board1: Vec<String>,
+    pub board2: Vec<String>,
+    pub accepted: Vec<String>, // colours that accepted the current marks
+    pub black_score: f64,
+    pub white_score: f64,
+    pub komi: f64,
+}
+
+#[derive(Deserialize)]
+struct Toggle {
+    board: usize, // 1 = A, 2 = B
+    position: String,
+}
+
+#[derive(Deserialize)]
+struct Marks {
+    board1: Option<Vec<String>>,
+    board2: Option<Vec<String>>,
+}
+
+fn color_index(color: Stone) -> usize {
+    match color {
+        Stone::Black => 0,
+        Stone::White => 1,
+    }
+}
+
+impl RoomScoring {
+    /// 数子阶段（含死子计算中）不接受落子
+    pub fn active(&self) -> bool {
+        self.proposing || self.session.is_some()
+    }
+
+    /// 记录一手棋；双方连续虚手时返回 true，调用方随后启动 start
+    pub fn record_move(&mut self, color: Stone, passed: bool) -> bool {
+        if !passed {
+            self.last_pass = None;
+            return false;
+        }
+        if self.last_pass == Some(color.opposite()) {
+            self.last_pass = None;
+            self.proposing = true;
+            return true;
+        }
+        self.last_pass = Some(color);
+        false
+    }
+}
+
+// Every stone that lives or dies together with the stone at (board, idx): its block, the brothers of
+// those stones on the other board, their blocks, and so on
+fn dead_chain(game: &QuantumGame, board: usize, idx: usize) -> [BTreeSet<usize>; 2] {
+    let mut chain = [BTreeSet::new(), BTreeSet::new()];
+    let mut stack = vec![(board, idx)];
+    while let Some((b, p)) = stack.pop() {
+        for q in game.boards[b].chain(p) {
+            if !chain[b].insert(q) {
+                continue;
+            }
+            if let Some(mate) = game.boards[b].brother(q) {
+                if game.boards[1 - b].get(mate).is_some() && !chain[1 - b].contains(&mate) {
+                    stack.push((1 - b, mate));
+                }
+            }
+        }
+    }
+    chain
+}
+
+// Marks closed over dead_chain; points without a stone are dropped
+fn close_marks(game: &QuantumGame, marks: [Vec<usize>; 2]) -> [BTreeSet<usize>; 2] {
+    let mut dead = [BTreeSet::new(), BTreeSet::new()];
+    for (b, points) in marks.iter().enumerate() {
+        for &p in points {
+            if p >= game.size * game.size || dead[b].contains(&p) {
+                continue;
+            }
+            let [a, c] = dead_chain(game, b, p);
+            dead[0].extend(a);
+            dead[1].extend(c);
+        }
+    }
+    dead
+}
+
+impl Session {
+    fn new(game: QuantumGame, komi: f64, rules: Ruleset, proposal: [Vec<usize>; 2]) -> Self {
+        let dead = close_marks(&game, proposal);
+        Self { game, komi, rules, dead, accepted: [false; 2] }
+    }
+
+    fn positions(&self, board: usize) -> Vec<String> {
+        self.dead[board].iter().map(|&p| self.game.position_of(p)).collect()
+    }
+
+    fn score(&self) -> (f64, f64) {
+        self.game.score_with_dead(&self.dead, self.komi, self.rules)
+    }
+
+    fn state(&self) -> RemovalState {
+        let (black_score, white_score) = self.score();
+        RemovalState {
+            board1: self.positions(0),
+            board2: self.positions(1),
+            accepted: [Stone::Black, Stone::White]
+                .into_iter()
+                .filter(|&c| self.accepted[color_index(c)])
+                .map(|c| color_name(c).to_string())
+                .collect(),
+            black_score,
+            white_score,
+            komi: self.komi,
+        }
+    }
+
+    fn parse_points(&self, positions: &[String]) -> io::Result<Vec<usize>> {
+        positions
+            .iter()
+            .map(|pos| {
+                self.game
+                    .index_of(pos)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid position: {}", pos)))
+            })
+            .collect()
+    }
+
+    // Toggles the block at a point; returns false if there is no stone
+    fn toggle(&mut self, board: usize, idx: usize) -> bool {
+        if self.game.boards[board].get(idx).is_none() {
+            return false;
+        }
+        let revive = self.dead[board].contains(&idx);
+        let chain = dead_chain(&self.game, board, idx);
+        for (b, points) in chain.into_iter().enumerate() {
+            for p in points {
+                if revive {
+                    self.dead[b].remove(&p);
+                } else {
+                    self.dead[b].insert(p);
+                }
+            }
+        }
+        self.accepted = [false; 2];
+        true
+    }
+
+    fn replace(&mut self, marks: [Vec<usize>; 2]) {
+        self.dead = close_marks(&self.game, marks);
+        self.accepted = [false; 2];
+    }
+
+    // An accept carrying marks only counts if they are the current ones
+    fn accept(&mut self, color: Stone, marks: Option<[Vec<usize>; 2]>) -> bool {
+        if let Some(marks) = marks {
+            let current = marks.map(|points| points.into_iter().collect::<BTreeSet<_>>());
+            if current != self.dead {
+                return false;
+            }
+        }
+        self.accepted[color_index(color)] = true;
+        true
+    }
+
+    fn result(&self, room_info: &RoomInfo) -> GameOutcome {
+        let (black, white) = self.score();
+        GameOutcome::score(room_info, black, white)
+    }
+}
+
+// Connections by colour ([black, white]); user1 is the owner's connection
+pub(crate) fn seats(room: &Room, room_info: &RoomInfo) -> [Option<WsSender>; 2] {
+    match color_of(room_info, room_info.owner_id) {
+        Stone::Black => [room.user1.clone(), room.user2.clone()],
+        Stone::White => [room.user2.clone(), room.user1.clone()],
+    }
+}
+
+pub(crate) async fn send_to<T: Serialize>(seats: &[Option<WsSender>; 2], colors: &[Stone], mode: &str, data: T) {
+    let Ok(text) = serde_json::to_string(&Data { mode: mode.to_string(), data }) else { return };
+    for &color in colors {
+        if let Some(sender) = &seats[color_index(color)] {
+            let _ = sender.lock().await.send(Message::Text(text.clone().into())).await;
+        }
+    }
+}
+
+pub(crate) const BOTH: [Stone; 2] = [Stone::Black, Stone::White];
+
+async fn set_status(state: &AppState, room_info: &RoomInfo, status: &str) -> io::Result<RoomInfo> {
+    state
+        .db
+        .update_room(&RoomInfo { status: status.to_string(), ..room_info.clone() })
+        .await
+        .map_err(io::Error::other)
+}
+
+// Dead stones proposed by the quantum score estimate, as indices on boards A and B
+async fn propose_dead(game: &QuantumGame, komi: f64) -> io::Result<[Vec<usize>; 2]> {
+    let board = |b: usize| {
+        let stones = |color: Stone| {
+            game.boards[b].stones().filter(|&(_, s)| s == color).map(|(idx, _)| game.position_of(idx)).collect()
+        };
+        ScoreEstimateBoardRequest {
+            board_size: game.size as u8,
+            black_stones: stones(Stone::Black),
+            white_stones: stones(Stone::White),
+            next_to_move: Some(color_name(game.to_move).to_string()),
+        }
+    };
+    let brothers = game.boards[0]
+        .stones()
+        .filter_map(|(idx, _)| game.boards[0].brother(idx).map(|mate| (game.position_of(idx), game.position_of(mate))))
+        .collect();
+    let req = ScoreEstimateRequest {
+        boards: vec![board(0), board(1)],
+        mode: Some("quantum".to_string()),
+        komi: Some(komi as f32),
+        brothers: Some(brothers),
+        trials: None,
+        tolerance: None,
+    };
+    let resp = quantum_estimate::estimate(&req, EstimateParams::resolve(None, None)?).await?;
+    let mut dead = [Vec::new(), Vec::new()];
+    for (b, board) in resp.boards.iter().enumerate().take(2) {
+        // Estimator coordinates are 0-based (x, y) of the "x,y" position
+        dead[b] = board
+            .dead_stones
+            .chunks_exact(2)
+            .filter_map(|xy| game.index_of(&format!("{},{}", xy[0] + 1, xy[1] + 1)))
+            .collect();
+    }
+    Ok(dead)
+}
+
+async fn open_session(state: &AppState, room_id: Uuid) -> io::Result<()> {
+    let room_info = state.db.get_room_by_room_id(room_id).await.map_err(io::Error::other)?;
+    let live = LiveGame::from_room(&room_info)?;
+    let proposal = match propose_dead(&live.game, room_info.komi).await {
+        Ok(dead) => dead,
+        Err(err) => {
+            // Players can still mark everything by hand
+            info!("Dead stone proposal failed in room {}: {}", room_id, err);
+            [Vec::new(), Vec::new()]
+        }
+    };
+    let session = Session::new(live.game, room_info.komi, Ruleset::from_id(&room_info.rules), proposal);
+
+    let mut rooms = state.rooms.lock().await;
+    let Some(room) = rooms.get_mut(&room_id) else { return Ok(()) };
+    if !room.scoring.proposing {
+        // Play resumed while the estimate was running
+        return Ok(());
+    }
+    set_status(state, &room_info, "scoring").await?;
+    let removal = session.state();
+    room.scoring.proposing = false;
+    room.scoring.session = Some(session);
+    send_to(&seats(room, &room_info), &BOTH, "stoneRemovalStart", removal).await;
+    Ok(())
+}
+
+/// 双方连续虚手后进入数子阶段：估算死子、房间状态置为 scoring 并通知双方
+pub async fn start(state: AppState, room_id: Uuid) {
+    if let Err(err) = open_session(&state, room_id).await {
+        info!("Failed to start scoring in room {}: {}", room_id, err);
+        let mut rooms = state.rooms.lock().await;
+        if let Some(room) = rooms.get_mut(&room_id) {
+            room.scoring = RoomScoring::default();
+        }
+    }
+}
+
+/// 玩家（重新）连接时：推送当前死子；服务重启后仍处于 scoring 的房间重新估算
+pub async fn resume(state: &AppState, room: &mut Room, room_info: &RoomInfo, user_id: Uuid) {
+    if let Some(session) = &room.scoring.session {
+        send_to(&seats(room, room_info), &[color_of(room_info, user_id)], "stoneRemovalStart", session.state()).await;
+    } else if room_info.status == "scoring" && !room.scoring.proposing {
+        room.scoring.proposing = true;
+        tokio::spawn(start(state.clone(), room_info.room_id));
+    }
+}
+
+/// 处理数子阶段的消息（stoneRemovalToggle / Update / Accept / Exit）
+pub async fn on_message(state: &AppState, room: &mut Room, room_info: &RoomInfo, user_id: Uuid, msg: &Data<Value>) {
+    let color = color_of(room_info, user_id);
+    let seats = seats(room, room_info);
+    if msg.mode == "stoneRemovalExit" {
+        if !room.scoring.active() {
+            return;
+        }
+        room.scoring = RoomScoring::default();
+        if let Err(err) = set_status(state, room_info, "playing").await {
+            info!("Failed to resume room {}: {}", room_info.room_id, err);
+        }
+        send_to(&seats, &[color.opposite()], "stoneRemovalExit", Value::Null).await;
+        return;
+    }
+
+    let Some(session) = room.scoring.session.as_mut() else {
+        send_to(&seats, &[color], "error", serde_json::json!({ "message": "Not in the scoring phase" })).await;
+        return;
+    };
+    let marks = serde_json::from_value::<Marks>(msg.data.clone())
+        .ok()
+        .filter(|m| m.board1.is_some() || m.board2.is_some())
+        .map(|m| -> io::Result<[Vec<usize>; 2]> {
+            Ok([session.parse_points(&m.board1.unwrap_or_default())?, session.parse_points(&m.board2.unwrap_or_default())?])
+        })
+        .transpose();
+    let marks = match marks {
+        Ok(marks) => marks,
+        Err(err) => {
+            send_to(&seats, &[color], "error", serde_json::json!({ "message": err.to_string() })).await;
+            return;
+        }
+    };
+
+    let changed = match msg.mode.as_str() {
+        "stoneRemovalToggle" => match serde_json::from_value::<Toggle>(msg.data.clone()) {
+            Ok(toggle) if (1..=2).contains(&toggle.board) => {
+                match session.game.index_of(&toggle.position) {
+                    Some(idx) => session.toggle(toggle.board - 1, idx),
+                    None => false,
+                }
+            }
+            _ => false,
+        },
+        "stoneRemovalUpdate" => {
+            session.replace(marks.unwrap_or_default());
+            true
+        }
+        "stoneRemovalAccept" => {
+            if !session.accept(color, marks) {
+                // Accepted stale marks: show the current ones again
+                let removal = session.state();
+                send_to(&seats, &[color], "stoneRemovalUpdate", removal).await;
+                return;
+            }
+            if session.accepted == [true, true] {
+                let result = session.result(room_info);
+                if let Err(err) = end_game(state, room, room_info, &result).await {
+                    info!("Failed to record score result in room {}: {}", room_info.room_id, err);
+                }
+                return;
+            }
+            send_to(&seats, &[color.opposite()], "stoneRemovalAccept", Value::Null).await;
+            return;
+        }
+        _ => return,
+    };
+    if changed {
+        let removal = session.state();
+        send_to(&seats, &BOTH, "stoneRemovalUpdate", removal).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game(moves: &[&str]) -> QuantumGame {
+        let mut game = QuantumGame::new(9);
+        for pos in moves {
+            game.play(game.index_of(pos).unwrap()).unwrap();
+        }
+        game
+    }
+
+    #[test]
+    fn test_two_consecutive_passes_start_scoring() {
+        let mut scoring = RoomScoring::default();
+        assert!(!scoring.record_move(Stone::Black, true));
+        // A stone in between resets the count
+        assert!(!scoring.record_move(Stone::White, false));
+        assert!(!scoring.record_move(Stone::Black, true));
+        // The same player passing twice is not two consecutive passes
+        assert!(!scoring.record_move(Stone::Black, true));
+        assert!(scoring.record_move(Stone::White, true));
+        assert!(scoring.active());
+    }
+
+    #[test]
+    fn test_toggle_marks_blocks_with_brothers() {
+        // 1,1 and 1,2 form a white block; White's quantum stone 7,7 pairs with 3,3 on B
+        let game = game(&["3,3", "7,7", "5,5", "1,1", "5,6", "1,2"]);
+        let idx = |pos: &str| game.index_of(pos).unwrap();
+        let mut session = Session::new(game.clone(), 7.5, Ruleset::Quantum, [vec![], vec![]]);
+        assert!(session.toggle(0, idx("1,1")));
+        assert_eq!(session.positions(0), vec!["1,1", "1,2"]);
+        assert_eq!(session.positions(1), vec!["1,1", "1,2"]);
+        assert!(!session.toggle(0, idx("2,2")));
+
+        assert!(session.toggle(0, idx("7,7")));
+        assert!(session.dead[0].contains(&idx("7,7")) && session.dead[1].contains(&idx("3,3")));
+        // Toggling again revives the whole chain
+        assert!(session.toggle(1, idx("3,3")));
+        assert!(!session.dead[0].contains(&idx("7,7")));
+    }
+
+    #[test]
+    fn test_accept_requires_current_marks() {
+        let game = game(&["3,3", "7,7", "5,5", "1,1"]);
+        let w = game.index_of("1,1").unwrap();
+        let mut session = Session::new(game, 7.5, Ruleset::Quantum, [vec![w], vec![]]);
+        // The proposal is closed over brothers
+        assert_eq!(session.positions(1), vec!["1,1"]);
+        assert!(!session.accept(Stone::Black, Some([vec![], vec![]])));
+        assert!(session.accept(Stone::Black, Some([vec![w], vec![w]])));
+        assert!(session.accept(Stone::White, None));
+        assert_eq!(session.state().accepted, vec!["black", "white"]);
+        assert_eq!(session.score(), (4.0, 9.5));
+
+        session.replace([vec![], vec![]]);
+        assert!(session.state().accepted.is_empty());
+    }
+}
@@ -0,0 +1,121 @@
+// 执子颜色：建房时房主选择黑 / 白 / 随机 / 猜先，结果写入 room_infos.black_id / white_id
+//
+// - black / white：房主执该颜色，访客入座时执另一方
+// - random：建房时即随机决定房主的颜色
+// - nigiri：猜先，建房时双方颜色都未定，访客入座时才随机决定（等待期间房主不知道自己的颜色）
+// 评分、对局结果与历史记录都按这两列记入实际执黑 / 执白的玩家；席位（owner / visitor）只决定连接槽位。
+// 迁移前的旧房间按房主执黑、访客执白回填。
+use serde::{Deserialize, Serialize};
+use std::io;
+use uuid::Uuid;
+
+use crate::entity::RoomInfo;
+use crate::quantum::Stone;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    Black,
+    White,
+    Random,
+    Nigiri,
+}
+
+impl ColorChoice {
+    /// 缺省为执黑（与旧版行为一致）
+    pub fn resolve(value: Option<&str>) -> io::Result<Self> {
+        match value.map(|v| v.trim().to_ascii_lowercase()).as_deref() {
+            None | Some("") | Some("black") => Ok(ColorChoice::Black),
+            Some("white") => Ok(ColorChoice::White),
+            Some("random") => Ok(ColorChoice::Random),
+            Some("nigiri") => Ok(ColorChoice::Nigiri),
+            Some(other) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid colour {}: expected black, white, random or nigiri", other),
+            )),
+        }
+    }
+}
+
+/// 双方颜色（startGame 随附，客户端据此确定自己执哪一方）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Players {
+    pub black_id: Option<Uuid>,
+    pub white_id: Option<Uuid>,
+}
+
+impl Players {
+    pub fn of(room: &RoomInfo) -> Self {
+        Self { black_id: room.black_id, white_id: room.white_id }
+    }
+
+    /// 建房时的颜色；visitor 为已入座的访客（AI 房间的机器人），coin 在需要随机时决定房主是否执黑
+    pub fn on_create(choice: ColorChoice, owner: Uuid, visitor: Option<Uuid>, coin: bool) -> Self {
+        let owner_black = match choice {
+            ColorChoice::Black => true,
+            ColorChoice::White => false,
+            ColorChoice::Random => coin,
+            ColorChoice::Nigiri if visitor.is_none() => return Self { black_id: None, white_id: None },
+            ColorChoice::Nigiri => coin,
+        };
+        if owner_black {
+            Self { black_id: Some(owner), white_id: visitor }
+        } else {
+            Self { black_id: visitor, white_id: Some(owner) }
+        }
+    }
+
+    /// 访客入座：坐到空出的颜色；猜先房间此时才由 coin 决定房主是否执黑。已入座时不变
+    pub fn on_join(self, owner: Uuid, visitor: Uuid, coin: bool) -> Self {
+        match (self.black_id, self.white_id) {
+            (Some(black), Some(white)) if black == visitor || white == visitor => self,
+            (Some(black), _) => Self { black_id: Some(black), white_id: Some(visitor) },
+            (None, Some(white)) => Self { black_id: Some(visitor), white_id: Some(white) },
+            (None, None) if coin => Self { black_id: Some(owner), white_id: Some(visitor) },
+            (None, None) => Self { black_id: Some(visitor), white_id: Some(owner) },
+        }
+    }
+}
+
+/// 用户执子的颜色；尚未入座时为空出的一方
+pub fn color_of(room: &RoomInfo, user_id: Uuid) -> Stone {
+    if room.black_id == Some(user_id) {
+        Stone::Black
+    } else if room.white_id == Some(user_id) || room.black_id.is_some() {
+        Stone::White
+    } else {
+        Stone::Black
+    }
+}
+
+/// 执该颜色的玩家
+pub fn player_of(room: &RoomInfo, color: Stone) -> Option<Uuid> {
+    match color {
+        Stone::Black => room.black_id,
+        Stone::White => room.white_id,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_colour_choice_assigns_seats() {
+        let (owner, visitor) = (Uuid::new_v4(), Uuid::new_v4());
+        let white = Players::on_create(ColorChoice::White, owner, None, true);
+        assert_eq!(white, Players { black_id: None, white_id: Some(owner) });
+        // The visitor takes the free colour, and reconnecting keeps it
+        let joined = white.on_join(owner, visitor, true);
+        assert_eq!(joined, Players { black_id: Some(visitor), white_id: Some(owner) });
+        assert_eq!(joined.on_join(owner, visitor, false), joined);
+
+        assert_eq!(Players::on_create(ColorChoice::Random, owner, None, false).white_id, Some(owner));
+        // Nigiri leaves both colours open until the opponent sits down
+        let nigiri = Players::on_create(ColorChoice::Nigiri, owner, None, true);
+        assert_eq!(nigiri, Players { black_id: None, white_id: None });
+        assert_eq!(nigiri.on_join(owner, visitor, false), Players { black_id: Some(visitor), white_id: Some(owner) });
+
+        assert_eq!(ColorChoice::resolve(None).unwrap(), ColorChoice::Black);
+        assert!(ColorChoice::resolve(Some("purple")).is_err());
+    }
+}
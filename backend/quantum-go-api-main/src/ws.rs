@@ -1,8 +1,14 @@
+use crate::ai_room;
+use crate::chat;
 use crate::db::Database;
 use crate::entity::Room;
 use crate::entity::WsSender;
-use crate::entity::{Chessman, RoomInfo, GameResult};
+use crate::entity::{Chessman, RoomInfo, GameResult, RatingUpdate};
+use crate::outcome::{Claim, EndReason, GameOutcome};
+use crate::negotiation;
 use crate::rating::RatingSystem;
+use crate::scoring;
+use crate::seating::{self, Players};
 
 use axum::{
     extract::{
@@ -42,10 +48,11 @@ pub async fn ws_handler(
     ws.on_upgrade(move |socket| handle_socket(socket, addr, state, room_id, user_id))
 }
 
-async fn send_start_game_message(sender: &WsSender) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let msg = Data::<serde_json::Value> {
+// startGame carries the players by colour so each client knows which side it plays
+async fn send_start_game_message(sender: &WsSender, players: Players) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let msg = Data::<Players> {
         mode: "startGame".to_string(),
-        data: serde_json::Value::Null,
+        data: players,
     };
     sender
         .lock()
@@ -56,6 +63,7 @@ async fn send_start_game_message(sender: &WsSender) -> Result<(), Box<dyn Error
 }
 
 // Proactively push updated owner/visitor info to a client
+#[allow(dead_code)]
 async fn send_update_room_info(
     sender: &WsSender,
     owner_id: Uuid,
@@ -82,24 +90,49 @@ async fn handle_user_connection(
     user_id: Uuid,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     let is_owner = user_id == room_info.owner_id;
-    let is_visitor = room_info.visitor_id.map_or(true, |vid| vid == user_id);
+    // AI 房间的访客席位由服务端机器人占据，不接受其它连接
+    let is_visitor = room_info.ai_level.is_none() && room_info.visitor_id.is_none_or(|vid| vid == user_id);
 
     if is_owner {
         room.user1 = Some(ws_sender.clone());
+        if room_info.ai_level.is_some() {
+            if room_info.status == "playing" {
+                send_start_game_message(ws_sender, Players::of(room_info)).await?;
+                // 让子局或重连时可能轮到机器人
+                tokio::spawn(ai_room::play_bot_turn(state.clone(), room_info.room_id, false));
+            }
+        } else {
+            // 匹配房间中访客可能先到：房主连上后再开始
+            if room_info.status == "playing" && room_info.moves == 0 {
+                if let Some(user2) = &room.user2 {
+                    send_start_game_message(ws_sender, Players::of(room_info)).await?;
+                    send_start_game_message(user2, Players::of(room_info)).await?;
+                }
+            }
+            // 数子阶段中重连：推送当前死子
+            scoring::resume(state, room, room_info, user_id).await;
+        }
     } else if is_visitor {
         room.user2 = Some(ws_sender.clone());
-        if let Err(err) = update_room_visitor(state, room_info, user_id).await {
-            // 放宽处理：数据库更新失败不阻断连接，继续开始游戏
-            info!("Failed to update room visitor: {}", err);
-        }
-
-        
+        // 入座时确定访客的颜色（猜先房间此时才决定双方颜色）
+        let joined = match update_room_visitor(state, room_info, user_id).await {
+            Ok(joined) => joined,
+            Err(err) => {
+                // 放宽处理：数据库更新失败不阻断连接，继续开始游戏
+                info!("Failed to update room visitor: {}", err);
+                room_info.clone()
+            }
+        };
 
         // Send start game message to both players
         if let (Some(user1), Some(user2)) = (&room.user1, &room.user2) {
-            send_start_game_message(user1).await?;
-            send_start_game_message(user2).await?;
+            send_start_game_message(user1, Players::of(&joined)).await?;
+            send_start_game_message(user2, Players::of(&joined)).await?;
         }
+        scoring::resume(state, room, &joined, user_id).await;
+    } else if room_info.allow_spectate {
+        // 观众：只参与观众频道的聊天
+        room.spectators.push((user_id, ws_sender.clone()));
     } else {
         return Err("Room is full".into());
     }
@@ -131,6 +164,10 @@ async fn handle_socket(
     let room = rooms.entry(room_id).or_insert(Room {
         user1: None,
         user2: None,
+        scoring: Default::default(),
+        spectators: Vec::new(),
+        chat: Default::default(),
+        offers: Default::default(),
     });
 
     // Handle user connection
@@ -142,14 +179,15 @@ async fn handle_socket(
     }
 
     info!("`{user_id}` at {who} connected to room `{room_id}`.");
+    chat::send_history(&state, &room_info, user_id, &ws_sender).await;
     drop(rooms);
 
     let room_info = room_info.clone();
     tokio::spawn(async move {
-        process_messages(&mut ws_receiver, &state, room_id, user_id).await;
+        process_messages(&mut ws_receiver, &state, room_id, user_id, &ws_sender).await;
 
         // Cleanup on disconnect
-        cleanup_connection(&state, room_id, user_id, &room_info).await;
+        cleanup_connection(&state, room_id, user_id, &room_info, &ws_sender).await;
     });
 }
 
@@ -174,9 +212,12 @@ async fn update_room_visitor(
 ) -> Result<RoomInfo, sqlx::Error> {
     info!("Updating room visitor (simple): room_id={}, visitor_id={}, id={}", 
           room_info.room_id, user_id, room_info.id);
+    // Reconnecting during the scoring phase keeps the room in it
+    let status = if room_info.status == "scoring" { "scoring" } else { "playing" };
+    let players = Players::of(room_info).on_join(room_info.owner_id, user_id, rand::random());
     let result = state
         .db
-        .update_room_visitor_simple(room_info.id, Some(user_id), "playing")
+        .update_room_visitor_simple(room_info.id, Some(user_id), players, status)
         .await;
 
     match &result {
@@ -197,6 +238,7 @@ async fn process_messages(
     state: &AppState,
     room_id: Uuid,
     user_id: Uuid,
+    own: &WsSender,
 ) {
     while let Some(Ok(Message::Text(text))) = ws_receiver.next().await {
         info!("message: {text}");
@@ -212,30 +254,95 @@ async fn process_messages(
             let room_info = match state.db.get_room_by_room_id(room_id).await {
                 Ok(info) => info,
                 Err(_) => {
-                    send_error_message(&room.user1.as_ref().unwrap(), "Room not found").await;
-                    send_error_message(&room.user2.as_ref().unwrap(), "Room not found").await;
+                    send_error_message(own, "Room not found").await;
                     return;
                 }
             };
 
-            // 对于 setWinner 消息，需要向两个玩家都发送
-            if msg.mode == "setWinner" {
-                // 先更新数据库（只执行一次）
-                if let Ok(data) = serde_json::from_value::<SetWinner>(msg.data.clone()) {
-                    if let Err(err) = update_winner(state, &room_info, &data).await {
-                        info!("Failed to update room winner: {}", err);
-                        return;
+            // 聊天（含旧版 sendMessage）：玩家与观众各自的频道
+            if chat::handles(&msg.mode) {
+                chat::on_message(state, room, &room_info, user_id, own, &msg.mode, &msg.data).await;
+                continue;
+            }
+            // 观众不能影响对局
+            if chat::channel_of(&room_info, user_id) == chat::Channel::Spectators {
+                continue;
+            }
+
+            // 认输 / 超时（含旧版 setWinner）：服务端校验后写库，并向两个玩家都发送结果
+            if matches!(msg.mode.as_str(), "resign" | "timeout" | "setWinner") {
+                let claimant = seating::color_of(&room_info, user_id);
+                let result = Claim::parse(&msg.mode, &msg.data, claimant)
+                    .and_then(|claim| claim.validate(&room_info, claimant, chrono::Utc::now()));
+                match result {
+                    Ok(result) => {
+                        if let Err(err) = end_game(state, room, &room_info, &result).await {
+                            info!("Failed to update room winner: {}", err);
+                        }
                     }
+                    Err(err) => send_error_message(own, &err.to_string()).await,
                 }
-                
-                // 向两个玩家都发送游戏结束消息
-                if let Some(user1) = &mut room.user1 {
-                    handle_message(&msg, user1, state, &room_info, &text).await;
-                }
-                if let Some(user2) = &mut room.user2 {
-                    handle_message(&msg, user2, state, &room_info, &text).await;
+            } else if room_info.ai_level.is_some() {
+                // AI 房间：玩家的落子/虚手触发机器人应手，双方虚手后的数子与悔棋请求由服务端处理
+                match msg.mode.as_str() {
+                    "updateChess" => {
+                        if let Ok(data) = serde_json::from_value::<UpdataChess>(msg.data.clone()) {
+                            let passed = data.put_chess.position == "0,0";
+                            if !passed {
+                                if let Err(err) = update_game_state(state, &room_info, &data).await {
+                                    info!("Failed to update room state: {}", err);
+                                    continue;
+                                }
+                            } else if let Err(err) = record_pass(state, &room_info).await {
+                                info!("Failed to record pass: {}", err);
+                            }
+                            tokio::spawn(ai_room::play_bot_turn(state.clone(), room_id, passed));
+                        }
+                    }
+                    "stoneRemovalStart" => {
+                        tokio::spawn(ai_room::finish_by_score(state.clone(), room_id));
+                    }
+                    "backChessApply" => {
+                        if let Some(user1) = &room.user1 {
+                            let reply = serde_json::json!({ "type": "backChessResult", "data": { "operation": false } });
+                            let _ = user1.lock().await.send(Message::Text(reply.to_string().into())).await;
+                        }
+                    }
+                    _ => {}
                 }
             } else {
+                let own = if user_id == room_info.owner_id { &room.user1 } else { &room.user2 };
+                match msg.mode.as_str() {
+                    "updateChess" if room.scoring.active() => {
+                        if let Some(own) = own {
+                            send_error_message(own, "Game is in the scoring phase").await;
+                        }
+                        continue;
+                    }
+                    "updateChess" => {
+                        // 服务端记录虚手：双方连续虚手后进入数子阶段（先转发这一手，再推送 stoneRemovalStart）
+                        if let Ok(data) = serde_json::from_value::<UpdataChess>(msg.data.clone()) {
+                            let passed = data.put_chess.position == "0,0";
+                            if room.scoring.record_move(seating::color_of(&room_info, user_id), passed) {
+                                tokio::spawn(scoring::start(state.clone(), room_id));
+                            }
+                            // A move withdraws any unanswered draw / takeback / abort request
+                            negotiation::on_move(state, room, &room_info).await;
+                        }
+                    }
+                    mode if negotiation::handles(mode) => {
+                        negotiation::on_message(state, room, &room_info, user_id, mode).await;
+                        continue;
+                    }
+                    // The server decides when the scoring phase starts
+                    "stoneRemovalStart" => continue,
+                    "stoneRemovalToggle" | "stoneRemovalUpdate" | "stoneRemovalAccept" | "stoneRemovalExit" => {
+                        scoring::on_message(state, room, &room_info, user_id, &msg).await;
+                        continue;
+                    }
+                    _ => {}
+                }
+
                 // 其他消息只发送给目标玩家
                 let target = if user_id == room_info.owner_id {
                     &mut room.user2
@@ -260,7 +367,6 @@ async fn handle_message(
 ) {
     match msg.mode.as_str() {
         "updateChess" => handle_update_chess(msg, target_tx, state, room_info).await,
-        "setWinner" => handle_set_winner(msg, target_tx, state, room_info, text).await,
         _ => {
             let _ = target_tx
                 .lock()
@@ -290,6 +396,8 @@ async fn handle_update_chess(
                 info!("Failed to update room state: {}", err);
                 return;
             }
+        } else if let Err(err) = record_pass(state, room_info).await {
+            info!("Failed to record pass: {}", err);
         }
 
         let _ = target_tx
@@ -300,7 +408,16 @@ async fn handle_update_chess(
     }
 }
 
-async fn update_game_state(
+fn next_round(room_info: &RoomInfo) -> &'static str {
+    if room_info.round == "black" { "white" } else { "black" }
+}
+
+/// 记录一次虚手：轮到对方行棋，对方的思考时间从此刻算起
+pub(crate) async fn record_pass(state: &AppState, room_info: &RoomInfo) -> Result<(), sqlx::Error> {
+    state.db.record_pass(room_info.id, next_round(room_info)).await
+}
+
+pub(crate) async fn update_game_state(
     state: &AppState,
     room_info: &RoomInfo,
     data: &UpdataChess,
@@ -312,54 +429,61 @@ async fn update_game_state(
             room_id: room_info.room_id,
             owner_id: room_info.owner_id,
             visitor_id: room_info.visitor_id,
+            black_id: room_info.black_id,
+            white_id: room_info.white_id,
             status: room_info.status.clone(),
-            round: if room_info.round == "black" {
-                "white".to_string()
-            } else {
-                "black".to_string()
-            },
+            round: next_round(room_info).to_string(),
             winner: room_info.winner.clone(),
             board: data.board.clone(),
             countdown: 30,
             moves: room_info.moves + 1,
             black_lost: data.black_lost,
             white_lost: data.white_lost,
-            model: room_info.model.clone(),
+            model: room_info.model,
             chessman_records: data.chessman_records.clone(),
             phase: room_info.phase.clone(),
             komi: room_info.komi,
+            handicap: room_info.handicap,
             time_control: room_info.time_control.clone(),
             is_public: room_info.is_public,
             is_listed: room_info.is_listed,
             allow_spectate: room_info.allow_spectate,
+            ai_level: room_info.ai_level.clone(),
+            rated: room_info.rated,
+            rules: room_info.rules.clone(),
+            result: room_info.result.clone(),
+            events: room_info.events.clone(),
             created_at: room_info.created_at,
             last_activity_at: room_info.last_activity_at,
         })
         .await
 }
 
-async fn handle_set_winner(
-    msg: &Data<Value>,
-    target_tx: &mut WsSender,
-    _state: &AppState,
-    _room_info: &RoomInfo,
-    text: &str,
-) {
-    if let Ok(_data) = serde_json::from_value::<SetWinner>(msg.data.clone()) {
-        // 只向当前玩家发送消息，数据库更新在 process_messages 中只执行一次
-        let _ = target_tx
-            .lock()
-            .await
-            .send(Message::Text(text.to_string().into()))
-            .await;
+/// 写入结果并向房间内两个玩家发送 setWinner；数子阶段随之结束
+pub(crate) async fn end_game(
+    state: &AppState,
+    room: &mut Room,
+    room_info: &RoomInfo,
+    result: &GameOutcome,
+) -> Result<(), sqlx::Error> {
+    room.scoring = Default::default();
+    room.offers = Default::default();
+    update_winner(state, room_info, result).await?;
+    let msg = Data { mode: "setWinner".to_string(), data: SetWinner::from(result) };
+    let text = to_string(&msg).unwrap_or_default();
+    for sender in [&room.user1, &room.user2].into_iter().flatten() {
+        let _ = sender.lock().await.send(Message::Text(text.clone().into())).await;
     }
+    Ok(())
 }
 
-async fn update_winner(
+pub(crate) async fn update_winner(
     state: &AppState,
     room_info: &RoomInfo,
-    data: &SetWinner,
+    result: &GameOutcome,
 ) -> Result<RoomInfo, sqlx::Error> {
+    // 中止的对局不计胜负，也不更新评分
+    let aborted = result.reason == EndReason::Abort;
     let updated_room = state
         .db
         .update_room(&RoomInfo {
@@ -367,22 +491,30 @@ async fn update_winner(
             room_id: room_info.room_id,
             owner_id: room_info.owner_id,
             visitor_id: room_info.visitor_id,
-            status: "finished".to_string(),
+            black_id: room_info.black_id,
+            white_id: room_info.white_id,
+            status: if aborted { "aborted" } else { "finished" }.to_string(),
             round: room_info.round.clone(),
-            winner: Some(data.winner.clone()),
+            winner: Some(result.winner_name().to_string()).filter(|_| !aborted),
             board: room_info.board.clone(),
             countdown: room_info.countdown,
             moves: room_info.moves,
             black_lost: room_info.black_lost,
             white_lost: room_info.white_lost,
-            model: room_info.model.clone(),
+            model: room_info.model,
             chessman_records: room_info.chessman_records.clone(),
             phase: room_info.phase.clone(),
             komi: room_info.komi,
+            handicap: room_info.handicap,
             time_control: room_info.time_control.clone(),
             is_public: room_info.is_public,
             is_listed: room_info.is_listed,
             allow_spectate: room_info.allow_spectate,
+            ai_level: room_info.ai_level.clone(),
+            rated: room_info.rated,
+            rules: room_info.rules.clone(),
+            result: Some(sqlx::types::Json(result.clone())),
+            events: room_info.events.clone(),
             created_at: room_info.created_at,
             last_activity_at: room_info.last_activity_at,
         })
@@ -391,36 +523,92 @@ async fn update_winner(
     // 游戏结束后更新评分
     let rating_system = RatingSystem::new();
     let game_result = GameResult {
-        winner: Some(data.winner.clone()),
+        winner: result.winner.map(|_| result.winner_name().to_string()),
         black_score: room_info.black_lost,
         white_score: room_info.white_lost,
         model: room_info.model,
+        handicap: room_info.handicap,
+        komi: room_info.komi,
     };
 
-    // 在后台更新评分，不阻塞响应
-    let db_clone = state.db.clone();
-    let owner_id = room_info.owner_id;
-    
-    // 如果有访客，更新双方评分
-    if let Some(visitor_id) = room_info.visitor_id {
-        tokio::spawn(async move {
-            if let Err(err) = rating_system.update_ratings(
-                &db_clone,
-                &game_result,
-                owner_id,
-                visitor_id,
-            ).await {
-                info!("Failed to update ratings: {}", err);
+    let mut game_over = GameOver {
+        winner: result.winner_name().to_string(),
+        reason: result.reason,
+        score: match (result.black_score, result.white_score) {
+            (Some(black), Some(white)) => Some(FinalScore { black, white }),
+            _ => None,
+        },
+        ratings: None,
+    };
+
+    // 在后台更新评分，不阻塞响应；评分写入后再推送 gameOver，客户端无需重新拉取资料
+    let state_clone = state.clone();
+    let room_id = room_info.room_id;
+    // 不计分的房间不更新评分；AI 房间或大厅机器人对局中机器人（访客）评分固定
+    let players = match (room_info.black_id, room_info.white_id) {
+        (Some(black_id), Some(white_id)) if room_info.rated && !aborted => Some((black_id, white_id)),
+        _ => None,
+    };
+    let visitor_id = room_info.visitor_id;
+    let ai_room = room_info.ai_level.is_some();
+    tokio::spawn(async move {
+        // 双方都已入座时，按实际执黑 / 执白更新评分
+        if let Some((black_id, white_id)) = players {
+            let visitor_is_bot = match visitor_id {
+                Some(id) => ai_room || state_clone.db.is_bot_user(id).await.unwrap_or(false),
+                None => false,
+            };
+            let fixed_player = visitor_id.filter(|_| visitor_is_bot);
+            match rating_system
+                .update_ratings(&state_clone.db, &game_result, black_id, white_id, fixed_player)
+                .await
+            {
+                Ok(update) => game_over.ratings = Some(update),
+                Err(err) => info!("Failed to update ratings: {}", err),
             }
-        });
-    }
+        }
+        broadcast_game_over(&state_clone, room_id, &game_over).await;
+    });
 
     Ok(updated_room)
 }
 
-async fn cleanup_connection(state: &AppState, room_id: Uuid, user_id: Uuid, room_info: &RoomInfo) {
+async fn broadcast_game_over(state: &AppState, room_id: Uuid, game_over: &GameOver) {
+    let msg = Data::<&GameOver> {
+        mode: "gameOver".to_string(),
+        data: game_over,
+    };
+    let text = match serde_json::to_string(&msg) {
+        Ok(text) => text,
+        Err(_) => return,
+    };
+    // Spectators see the result too; collect the senders so no socket write happens under the rooms lock
+    let senders: Vec<WsSender> = match state.rooms.lock().await.get(&room_id) {
+        Some(room) => [&room.user1, &room.user2]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .chain(room.spectators.iter().map(|(_, sender)| sender.clone()))
+            .collect(),
+        None => return,
+    };
+    for sender in senders {
+        let _ = sender
+            .lock()
+            .await
+            .send(Message::Text(text.clone().into()))
+            .await;
+    }
+}
+
+async fn cleanup_connection(state: &AppState, room_id: Uuid, user_id: Uuid, room_info: &RoomInfo, own: &WsSender) {
     let mut rooms = state.rooms.lock().await;
     if let Some(room) = rooms.get_mut(&room_id) {
+        let spectators = room.spectators.len();
+        room.spectators.retain(|(_, sender)| !Arc::ptr_eq(sender, own));
+        if room.spectators.len() < spectators {
+            return;
+        }
         if user_id == room_info.owner_id {
             room.user1 = None;
         } else {
@@ -430,20 +618,20 @@ async fn cleanup_connection(state: &AppState, room_id: Uuid, user_id: Uuid, room
 }
 
 #[derive(Serialize, Deserialize)]
-struct Data<T> {
+pub struct Data<T> {
     #[serde(rename(serialize = "type", deserialize = "type"))]
-    mode: String,
-    data: T,
+    pub mode: String,
+    pub data: T,
 }
 
 #[derive(Serialize, Deserialize)]
-struct UpdataChess {
+pub(crate) struct UpdataChess {
     #[serde(rename(serialize = "putChess", deserialize = "putChess"))]
-    put_chess: Chessman,
-    board: Value,
-    black_lost: i32,
-    white_lost: i32,
-    chessman_records: serde_json::Value,
+    pub(crate) put_chess: Chessman,
+    pub(crate) board: Value,
+    pub(crate) black_lost: i32,
+    pub(crate) white_lost: i32,
+    pub(crate) chessman_records: serde_json::Value,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -452,9 +640,41 @@ struct UpdataChessResponse {
     put_chess: Chessman,
 }
 
-#[derive(Serialize, Deserialize)]
-struct SetWinner {
+// Result pushed to the players as soon as it is recorded ("draw" when a count is tied)
+#[derive(Serialize)]
+pub(crate) struct SetWinner {
+    pub(crate) winner: String,
+    pub(crate) reason: EndReason,
+    pub(crate) black_score: Option<f64>,
+    pub(crate) white_score: Option<f64>,
+    pub(crate) margin: Option<f64>,
+}
+
+impl From<&GameOutcome> for SetWinner {
+    fn from(result: &GameOutcome) -> Self {
+        Self {
+            winner: result.winner_name().to_string(),
+            reason: result.reason,
+            black_score: result.black_score,
+            white_score: result.white_score,
+            margin: result.margin,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct FinalScore {
+    black: f64,
+    white: f64,
+}
+
+// Sent to both players once the result and ratings have been committed
+#[derive(Serialize)]
+struct GameOver {
     winner: String,
+    reason: EndReason,
+    score: Option<FinalScore>,
+    ratings: Option<RatingUpdate>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -0,0 +1,195 @@
+// GTP 启动器与引擎池的离线测试：用 src/bin/mock_gtp.rs 代替 KataGo，
+// cargo 会先构建 mock_gtp，并通过 CARGO_BIN_EXE_mock_gtp 提供路径
+use quantum_go_api::ai_level;
+use quantum_go_api::katago::{
+    genmove_dual_in_pool, genmove_in_pool, gtp_to_xy, AiDualGenmoveRequest, AiGenmoveRequest, EngineCommand, EngineTimeouts, MoveItem,
+};
+use quantum_go_api::katago_pool::{EnginePool, PoolConfig};
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn temp_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("mock_gtp_{}_{}_{}", std::process::id(), name, rand::random::<u32>()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn mock_config(args: &[&str]) -> PoolConfig {
+    let command = EngineCommand {
+        program: env!("CARGO_BIN_EXE_mock_gtp").to_string(),
+        args: args.iter().map(|a| a.to_string()).collect(),
+    };
+    PoolConfig {
+        capacity: 1,
+        queue_limit: 4,
+        checkout_timeout: Duration::from_secs(5),
+        request_timeout: Duration::from_secs(10),
+        timeouts: EngineTimeouts {
+            command: Duration::from_secs(2),
+            search: Duration::from_millis(500),
+            startup: Duration::from_secs(5),
+        },
+        warmup: false,
+        restart_backoff: Duration::from_millis(20),
+        max_restart_backoff: Duration::from_millis(100),
+        analysis_cache: false,
+        command: Some(command),
+    }
+}
+
+fn mock_pool(board_size: u8, args: &[&str]) -> Arc<EnginePool> {
+    Arc::new(EnginePool::new(board_size, ai_level::resolve(None).unwrap(), mock_config(args)))
+}
+
+fn mv(color: &str, position: &str) -> MoveItem {
+    MoveItem { color: color.to_string(), position: position.to_string() }
+}
+
+fn genmove_req(moves: Vec<MoveItem>, forbidden: Option<Vec<String>>) -> AiGenmoveRequest {
+    AiGenmoveRequest { board_size: 9, next_to_move: "white".to_string(), moves, komi: None, rules: None, forbidden, level: None }
+}
+
+
+#[tokio::test]
+async fn test_genmove_replays_history_in_gtp_coordinates() {
+    let log = temp_file("log");
+    let pool = mock_pool(9, &["--genmove", "C3", "--log", log.to_str().unwrap()]);
+    let req = AiGenmoveRequest { rules: Some("Japanese".to_string()), ..genmove_req(vec![mv("black", "1,1"), mv("white", "9,9")], None) };
+    let resp = genmove_in_pool(&pool, &req).await.unwrap();
+    assert_eq!(resp.move_coord, "C3");
+
+    let commands = std::fs::read_to_string(&log).unwrap();
+    assert!(commands.lines().any(|l| l == "kata-set-rules japanese"));
+    let plays: Vec<&str> = commands.lines().filter(|l| l.starts_with("play")).collect();
+    assert_eq!(plays, vec!["play B A9", "play W J1"]);
+    assert!(commands.lines().any(|l| l == "genmove W"));
+    let _ = std::fs::remove_file(log);
+}
+
+#[tokio::test]
+async fn test_genmove_rejects_unknown_rules() {
+    let pool = mock_pool(9, &["--genmove", "C3"]);
+    let req = AiGenmoveRequest { rules: Some("ing".to_string()), ..genmove_req(vec![], None) };
+    let err = genmove_in_pool(&pool, &req).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[tokio::test]
+async fn test_genmove_retries_forbidden_moves() {
+    let counter = temp_file("counter");
+    let pool = mock_pool(9, &["--genmove", "C3,D4", "--counter", counter.to_str().unwrap()]);
+    let forbidden = vec![gtp_to_xy("C3", 9).unwrap()];
+    let resp = genmove_in_pool(&pool, &genmove_req(vec![], Some(forbidden))).await.unwrap();
+    assert_eq!(resp.move_coord, "D4");
+    // The forbidden suggestion forced a fresh process
+    assert_eq!(pool.stats().spawned, 2);
+    let _ = std::fs::remove_file(counter);
+}
+
+#[tokio::test]
+async fn test_genmove_gives_up_with_pass_when_everything_is_forbidden() {
+    let pool = mock_pool(9, &["--genmove", "C3"]);
+    let forbidden = vec![gtp_to_xy("C3", 9).unwrap()];
+    let resp = genmove_in_pool(&pool, &genmove_req(vec![], Some(forbidden))).await.unwrap();
+    assert_eq!(resp.move_coord, "pass");
+}
+
+#[tokio::test]
+async fn test_genmove_respawns_after_engine_crash() {
+    let counter = temp_file("counter");
+    let pool = mock_pool(9, &["--genmove", "!crash,E5", "--counter", counter.to_str().unwrap()]);
+    let resp = genmove_in_pool(&pool, &genmove_req(vec![mv("black", "5,5")], None)).await.unwrap();
+    assert_eq!(resp.move_coord, "E5");
+    let stats = pool.stats();
+    assert_eq!(stats.spawned, 2);
+    assert_eq!(stats.discarded, 1);
+    let _ = std::fs::remove_file(counter);
+}
+
+#[tokio::test]
+async fn test_hung_engine_times_out_and_is_replaced() {
+    let counter = temp_file("counter");
+    let pool = mock_pool(9, &["--genmove", "!hang,E5", "--counter", counter.to_str().unwrap()]);
+    let resp = genmove_in_pool(&pool, &genmove_req(vec![], None)).await.unwrap();
+    assert_eq!(resp.move_coord, "E5");
+    let stats = pool.stats();
+    assert_eq!((stats.spawned, stats.discarded), (2, 1));
+
+    // The hung process was killed; only the replacement is registered, and it answered,
+    // so the failure no longer holds back restarts
+    let health = pool.health();
+    assert_eq!(health.engines.len(), 1);
+    assert_eq!(health.engines[0].name, "MockGTP");
+    assert!(!health.engines[0].in_use);
+    assert!(health.healthy);
+    assert!(health.last_error.unwrap().contains("stopped responding"));
+
+    pool.probe_idle().await;
+    assert_eq!(pool.health().engines[0].last_probe_ok, Some(true));
+    let _ = std::fs::remove_file(counter);
+}
+
+fn dual_req(metric: &str, forbidden: Option<Vec<String>>) -> AiDualGenmoveRequest {
+    AiDualGenmoveRequest {
+        board_size: 9,
+        next_to_move: "black".to_string(),
+        board_a_moves: vec![],
+        board_b_moves: vec![],
+        komi: None,
+        rules: None,
+        forbidden,
+        k: Some(4),
+        metric: Some(metric.to_string()),
+        level: None,
+    }
+}
+
+// D4 is best on board A but collapses on board B; C3 is decent on both
+const DUAL_SCRIPT: [&str; 8] = [
+    "--info",
+    "info move D4 visits 200 winrate 0.60 scoreLead 2.0 order 0 pv D4 info move C3 visits 100 winrate 0.55 scoreLead 1.0 order 1 pv C3",
+    "--after",
+    "D4=0.8,5.0",
+    "--after",
+    "C3=0.4,-1.0",
+    "--genmove",
+    "D4",
+];
+
+#[tokio::test]
+async fn test_dual_genmove_balances_both_boards() {
+    let pool = mock_pool(9, &DUAL_SCRIPT);
+    let by_winrate = genmove_dual_in_pool(&pool, &dual_req("winrate", None)).await.unwrap();
+    assert_eq!(by_winrate.move_coord, "C3");
+    let by_score = genmove_dual_in_pool(&pool, &dual_req("score_lead", None)).await.unwrap();
+    assert_eq!(by_score.move_coord, "C3");
+}
+
+#[tokio::test]
+async fn test_dual_genmove_skips_forbidden_candidates() {
+    let pool = mock_pool(9, &DUAL_SCRIPT);
+    let forbidden = vec![gtp_to_xy("C3", 9).unwrap()];
+    let resp = genmove_dual_in_pool(&pool, &dual_req("winrate", Some(forbidden))).await.unwrap();
+    assert_eq!(resp.move_coord, "D4");
+}
+
+#[tokio::test]
+async fn test_dual_genmove_reuses_cached_analysis() {
+    let log = temp_file("log");
+    let mut args = DUAL_SCRIPT.to_vec();
+    args.extend(["--log", log.to_str().unwrap()]);
+    let config = PoolConfig { analysis_cache: true, ..mock_config(&args) };
+    let pool = Arc::new(EnginePool::new(9, ai_level::resolve(None).unwrap(), config));
+    // A komi no other test uses keeps the shared cache entries to this test
+    let req = AiDualGenmoveRequest { komi: Some(3.25), ..dual_req("winrate", None) };
+    let analyses = || std::fs::read_to_string(&log).unwrap().lines().filter(|l| l.starts_with("kata-genmove_analyze")).count();
+
+    assert_eq!(genmove_dual_in_pool(&pool, &req).await.unwrap().move_coord, "C3");
+    // One search on board A, one per candidate on board B
+    assert_eq!(analyses(), 3);
+    assert_eq!(genmove_dual_in_pool(&pool, &req).await.unwrap().move_coord, "C3");
+    assert_eq!(analyses(), 3);
+    let _ = std::fs::remove_file(log);
+}
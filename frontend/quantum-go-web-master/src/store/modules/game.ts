@@ -194,7 +194,7 @@ const mutations = {
 
 const actions = {
   async setGameInfo({ commit, rootState, state }: any, data: Record<string, any>) {
-    const { room_id, status, owner_id, round, board, moves, white_lost, black_lost, model, chessman_records, phase, komi } = data;
+    const { room_id, status, owner_id, black_id, white_id, round, board, moves, white_lost, black_lost, model, chessman_records, phase, komi } = data;
     const boardMap = new Map(JSON.stringify(board) === "{}" ? [] : board);
     state.board1.clear();
     state.board2.clear();
@@ -221,9 +221,16 @@ const actions = {
       sound.startBgmFromBeginning();
     }
     sound.syncBgm(state.status, state.bgmEnabled);
-    const isOwner = owner_id === rootState.user.id;
-    state.camp = isOwner ? "black" : "white";
-    state.round = isOwner ? round === "black" : round === "white";
+    // 执子颜色以服务端座位为准（房主可执白 / 随机 / 猜先）；猜先未定时暂按房主执黑显示
+    const myId = rootState.user.id;
+    if (black_id === myId) {
+      state.camp = "black";
+    } else if (white_id === myId) {
+      state.camp = "white";
+    } else {
+      state.camp = owner_id === myId ? "black" : "white";
+    }
+    state.round = round === state.camp;
     const count = boardMap.size;
     if (count === 0) {
       state.blackQuantum = "";